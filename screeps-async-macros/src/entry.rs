@@ -42,7 +42,7 @@ fn async_body(input: &ItemFn) -> TokenStream {
     let stmts = &input.block.stmts;
 
     quote! {
-        let res = ::screeps_async::block_on(async move {
+        let res = ::screeps_async::run_entrypoint(async move {
             #(#stmts)*
         });
 
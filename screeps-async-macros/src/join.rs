@@ -0,0 +1,97 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::{Expr, Token};
+
+pub fn join(input: TokenStream) -> TokenStream {
+    expand(input, false)
+}
+
+pub fn try_join(input: TokenStream) -> TokenStream {
+    expand(input, true)
+}
+
+fn expand(input: TokenStream, short_circuit: bool) -> TokenStream {
+    let parser = Punctuated::<Expr, Token![,]>::parse_terminated;
+    let futures = match parser.parse2(input) {
+        Ok(it) => it,
+        Err(e) => return e.into_compile_error(),
+    };
+
+    if futures.is_empty() {
+        return if short_circuit {
+            quote! { ::core::result::Result::Ok(()) }
+        } else {
+            quote! { () }
+        };
+    }
+
+    // Internal names use mixed site hygiene so they can't clash with names used in the futures
+    let span = Span::mixed_site();
+    let cx = format_ident!("__cx", span = span);
+    let done = format_ident!("__done", span = span);
+    let futs: Vec<_> = (0..futures.len())
+        .map(|i| format_ident!("__fut_{}", i, span = span))
+        .collect();
+    let outs: Vec<_> = (0..futures.len())
+        .map(|i| format_ident!("__out_{}", i, span = span))
+        .collect();
+
+    let polls = futs.iter().zip(&outs).map(|(fut, out)| {
+        let ready = if short_circuit {
+            quote! {
+                ::core::task::Poll::Ready(::core::result::Result::Ok(value)) => {
+                    #out = ::core::option::Option::Some(value);
+                }
+                ::core::task::Poll::Ready(::core::result::Result::Err(err)) => {
+                    return ::core::task::Poll::Ready(::core::result::Result::Err(err));
+                }
+            }
+        } else {
+            quote! {
+                ::core::task::Poll::Ready(value) => {
+                    #out = ::core::option::Option::Some(value);
+                }
+            }
+        };
+
+        quote! {
+            if #out.is_none() {
+                match #fut.as_mut().poll(#cx) {
+                    #ready
+                    ::core::task::Poll::Pending => #done = false,
+                }
+            }
+        }
+    });
+
+    let outputs = quote! { (#(#outs.take().unwrap(),)*) };
+    let outputs = if short_circuit {
+        quote! { ::core::result::Result::Ok(#outputs) }
+    } else {
+        outputs
+    };
+
+    let futures = futures.iter();
+    quote! {{
+        #(
+            let mut #futs = ::core::pin::pin!(::core::future::IntoFuture::into_future(#futures));
+            let mut #outs = ::core::option::Option::None;
+        )*
+
+        ::core::future::poll_fn(|#cx| {
+            use ::core::future::Future;
+
+            let mut #done = true;
+            #(#polls)*
+
+            if #done {
+                ::core::task::Poll::Ready(#outputs)
+            } else {
+                ::core::task::Poll::Pending
+            }
+        })
+        .await
+    }}
+}
@@ -3,6 +3,8 @@
 //! Macros used with screeps-async
 
 mod entry;
+mod join;
+mod select;
 
 use proc_macro::TokenStream;
 
@@ -10,8 +12,96 @@ use proc_macro::TokenStream;
 /// the ScreepsRuntime and invoke it at the end of each tick.
 ///
 /// The wrapped function may be `async` in which case the body will be passed to
-/// [screeps_async::block_on] before calling [screeps_async::run]
+/// [screeps_async::run_entrypoint] before calling [screeps_async::run]
 #[proc_macro_attribute]
 pub fn main(args: TokenStream, item: TokenStream) -> TokenStream {
     entry::main(args.into(), item.into()).into()
 }
+
+/// Wait on multiple futures at once, running the branch of whichever future completes first.
+///
+/// Each branch has the form `<pattern> = <future> => <body>`. All futures are polled each time the
+/// task is woken. Once a future completes, its output is matched against the branch's pattern and
+/// all remaining futures are dropped before the branch body runs. If the output doesn't match the
+/// pattern, that branch is disabled and the remaining branches continue to be polled.
+///
+/// By default, the branch that is polled first changes each time, so that when several futures
+/// are ready at once no branch is favoured over the others. It is picked using the runtime's
+/// random number generator, so it is reproducible with `Builder::rng_seed`. Starting with `biased;` instead polls
+/// the branches in the order they are written, so earlier branches always win ties. Like
+/// `tokio::select!`, this is useful when some branches are more important than others, but it is
+/// up to you to make sure the later branches aren't starved.
+///
+/// An optional `else => <body>` branch runs if every branch is disabled.
+/// Without an `else` branch, `select!` panics if all branches are disabled.
+///
+/// `select!` must be used within an async context and evaluates to the value of the branch that ran.
+///
+/// # Examples
+///
+/// Implementing a timeout by racing a channel against a delay:
+/// ```ignore
+/// use screeps_async::sync::oneshot;
+/// use screeps_async::time::delay;
+///
+/// async fn wait_for_path(rx: oneshot::Receiver<Path>) -> Option<Path> {
+///     screeps_async::select! {
+///         Ok(path) = rx => Some(path),
+///         _ = delay(10) => None,
+///     }
+/// }
+/// ```
+///
+/// Always handling threats before routine work:
+/// ```ignore
+/// loop {
+///     screeps_async::select! {
+///         biased;
+///         Some(enemy) = enemies.recv() => defend(enemy).await,
+///         _ = patrol_timer.tick() => patrol().await,
+///     }
+/// }
+/// ```
+#[proc_macro]
+pub fn select(input: TokenStream) -> TokenStream {
+    select::select(input.into()).into()
+}
+
+/// Wait on multiple futures concurrently, returning a tuple of all their outputs.
+///
+/// Unlike awaiting each future in turn, every incomplete future is polled each time the task is
+/// woken, so independent work (e.g. delays) happens in parallel rather than one after the other.
+///
+/// `join!` must be used within an async context.
+///
+/// # Examples
+/// ```ignore
+/// use screeps_async::time::delay;
+///
+/// async fn fetch_both() -> (Path, Path) {
+///     // Completes in 5 ticks, not 5 plus however long the paths take to find
+///     let (_, path_a, path_b) = screeps_async::join!(delay(5), find_path(a), find_path(b));
+///     (path_a, path_b)
+/// }
+/// ```
+#[proc_macro]
+pub fn join(input: TokenStream) -> TokenStream {
+    join::join(input.into()).into()
+}
+
+/// Like [join!], but for futures that output a [Result].
+///
+/// Resolves to [Ok] with a tuple of all the [Ok] values once every future has completed,
+/// or to the first [Err] as soon as any future fails. The remaining futures are dropped as soon as
+/// an error is returned.
+///
+/// # Examples
+/// ```ignore
+/// async fn fetch_both() -> Result<(Path, Path), PathError> {
+///     screeps_async::try_join!(try_find_path(a), try_find_path(b))
+/// }
+/// ```
+#[proc_macro]
+pub fn try_join(input: TokenStream) -> TokenStream {
+    join::try_join(input.into()).into()
+}
@@ -0,0 +1,193 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::parse::{Parse, ParseStream};
+use syn::{Expr, Ident, Pat, Token};
+
+struct Branch {
+    pat: Pat,
+    future: Expr,
+    body: Expr,
+}
+
+struct Select {
+    /// Whether branches are always polled in the order they are written
+    biased: bool,
+    branches: Vec<Branch>,
+    else_body: Option<Expr>,
+}
+
+impl Parse for Select {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let biased = parse_biased(input)?;
+        let mut branches = Vec::new();
+        let mut else_body = None;
+
+        while !input.is_empty() {
+            if input.peek(Token![else]) {
+                let else_token: Token![else] = input.parse()?;
+                if else_body.is_some() {
+                    return Err(syn::Error::new_spanned(
+                        else_token,
+                        "select! may only have one `else` branch",
+                    ));
+                }
+                input.parse::<Token![=>]>()?;
+                else_body = Some(parse_body(input)?);
+                continue;
+            }
+
+            let pat = Pat::parse_multi_with_leading_vert(input)?;
+            input.parse::<Token![=]>()?;
+            let future = input.parse()?;
+            input.parse::<Token![=>]>()?;
+            let body = parse_body(input)?;
+
+            branches.push(Branch { pat, future, body });
+        }
+
+        if branches.is_empty() {
+            return Err(input.error("select! requires at least one branch"));
+        }
+
+        Ok(Self {
+            biased,
+            branches,
+            else_body,
+        })
+    }
+}
+
+/// Parse the optional leading `biased;` marker
+fn parse_biased(input: ParseStream) -> syn::Result<bool> {
+    let fork = input.fork();
+    let is_biased =
+        fork.parse::<Ident>().is_ok_and(|ident| ident == "biased") && fork.peek(Token![;]);
+    if is_biased {
+        input.parse::<Ident>()?;
+        input.parse::<Token![;]>()?;
+    }
+
+    Ok(is_biased)
+}
+
+/// Parse a branch body along with its trailing comma, which is optional for blocks
+fn parse_body(input: ParseStream) -> syn::Result<Expr> {
+    let body: Expr = input.parse()?;
+    let is_block = matches!(body, Expr::Block(_));
+    if input.is_empty() || (is_block && !input.peek(Token![,])) {
+        return Ok(body);
+    }
+
+    input.parse::<Token![,]>()?;
+    Ok(body)
+}
+
+pub fn select(input: TokenStream) -> TokenStream {
+    let select: Select = match syn::parse2(input) {
+        Ok(it) => it,
+        Err(e) => return e.into_compile_error(),
+    };
+
+    // Internal names use mixed site hygiene so they can't clash with names used in the branches
+    let span = Span::mixed_site();
+    let output = format_ident!("__SelectOutput", span = span);
+    let disabled = format_ident!("__disabled", span = span);
+    let cx = format_ident!("__cx", span = span);
+    let out = format_ident!("__out", span = span);
+    let start = format_ident!("__start", span = span);
+    let offset = format_ident!("__offset", span = span);
+    let branch = format_ident!("__branch", span = span);
+
+    let count = select.branches.len();
+    let variants: Vec<_> = (0..count)
+        .map(|i| format_ident!("_{}", i, span = span))
+        .collect();
+    let futures: Vec<_> = (0..count)
+        .map(|i| format_ident!("__fut_{}", i, span = span))
+        .collect();
+
+    let future_exprs = select.branches.iter().map(|b| &b.future);
+    let pats = select.branches.iter().map(|b| &b.pat);
+    let polls = select.branches.iter().enumerate().map(|(i, branch)| {
+        let fut = &futures[i];
+        let variant = &variants[i];
+        let pat = &branch.pat;
+        quote! {
+            #i => if !#disabled[#i] {
+                if let ::core::task::Poll::Ready(#out) = #fut.as_mut().poll(#cx) {
+                    // Each branch resolves at most once
+                    #disabled[#i] = true;
+
+                    #[allow(unused_variables, unused_mut, irrefutable_let_patterns)]
+                    let matched = match &#out {
+                        #pat => true,
+                        _ => false,
+                    };
+                    if matched {
+                        return ::core::task::Poll::Ready(#output::#variant(#out));
+                    }
+                }
+            },
+        }
+    });
+    let bodies = select.branches.iter().map(|b| &b.body);
+
+    let start_branch = if select.biased || count == 1 {
+        quote! { 0 }
+    } else {
+        // Pick a different branch to go first each poll, so that no branch is favoured
+        quote! { ::screeps_async::macros::__select_start(#count) }
+    };
+
+    let else_body = match select.else_body {
+        Some(body) => quote! { #body },
+        None => quote! {
+            ::core::panic!("all branches are disabled and there is no else branch")
+        },
+    };
+
+    quote! {{
+        #[allow(non_camel_case_types)]
+        enum #output<#(#variants,)*> {
+            #(#variants(#variants),)*
+            Disabled,
+        }
+
+        let #out = {
+            #(
+                let mut #futures = ::core::pin::pin!(::core::future::IntoFuture::into_future(#future_exprs));
+            )*
+            let mut #disabled = [false; #count];
+
+            ::core::future::poll_fn(|#cx| {
+                use ::core::future::Future;
+
+                let #start: usize = #start_branch;
+                for #offset in 0..#count {
+                    #[allow(clippy::modulo_one)]
+                    let #branch = (#start + #offset) % #count;
+                    match #branch {
+                        #(#polls)*
+                        _ => ::core::unreachable!(),
+                    }
+                }
+
+                if #disabled.iter().all(|d| *d) {
+                    ::core::task::Poll::Ready(#output::Disabled)
+                } else {
+                    ::core::task::Poll::Pending
+                }
+            })
+            .await
+
+            // The futures of all other branches are dropped here, before the branch body runs
+        };
+
+        #[allow(unreachable_patterns)]
+        match #out {
+            #(#output::#variants(#pats) => #bodies,)*
+            #output::Disabled => #else_body,
+            _ => ::core::unreachable!("select! branch pattern did not match"),
+        }
+    }}
+}
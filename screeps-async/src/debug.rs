@@ -0,0 +1,92 @@
+//! Development aids for catching mistakes in async bot code
+
+use crate::time::Tick;
+
+/// Guard that checks it is dropped on the same tick it was created on. Created by [tick_guard]
+///
+/// Game objects, like a `Creep` read from `screeps::game`, are only valid for the tick they were
+/// read on. Holding a [TickGuard] for as long as a section of code relies on such values turns
+/// accidentally awaiting across a tick boundary into an assertion failure, instead of a bug that
+/// only shows up as stale data.
+///
+/// The check is a [debug_assert], so it does nothing in release builds
+#[derive(Debug)]
+pub struct TickGuard {
+    created: Tick,
+}
+
+impl TickGuard {
+    /// The tick this guard was created on
+    pub fn created(&self) -> Tick {
+        self.created
+    }
+}
+
+impl Drop for TickGuard {
+    fn drop(&mut self) {
+        // Don't turn an unrelated panic into an abort
+        if cfg!(debug_assertions) && !std::thread::panicking() {
+            let now = Tick::now();
+            debug_assert!(
+                now == self.created,
+                "TickGuard created on tick {} was dropped on tick {}. \
+                 Game objects read before an await may be stale",
+                self.created.0,
+                now.0
+            );
+        }
+    }
+}
+
+/// Create a [TickGuard] that asserts (in debug builds) that it is dropped on the current tick.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::debug::tick_guard;
+/// # fn idle_creeps() -> Vec<String> { Vec::new() }
+/// # async fn send_report() {}
+/// screeps_async::spawn(async move {
+///     let _guard = tick_guard();
+///     // Stands in for game objects, which are only valid this tick
+///     let creeps = idle_creeps();
+///     // Panics in debug builds, since `creeps` is used on a later tick
+///     send_report().await;
+///     for creep in creeps {
+///         // give the creep a job
+///     }
+/// }).detach();
+/// ```
+#[must_use = "the guard checks the tick when it is dropped"]
+pub fn tick_guard() -> TickGuard {
+    TickGuard {
+        created: Tick::now(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{init_test, tick, GAME_TIME};
+
+    #[test]
+    fn same_tick_passes() {
+        init_test();
+        let guard = tick_guard();
+        assert_eq!(Tick(0), guard.created());
+        crate::block_on(async move {
+            let _guard = guard;
+            crate::time::yield_now().await;
+        })
+        .unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "TickGuard created on tick 0 was dropped on tick 1")]
+    fn later_tick_fails() {
+        init_test();
+        let guard = tick_guard();
+        tick().unwrap();
+        assert_eq!(1, GAME_TIME.with_borrow(|t| *t));
+        drop(guard);
+    }
+}
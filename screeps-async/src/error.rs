@@ -1,4 +1,5 @@
 //! Errors emitted by screeps-async
+use std::any::Any;
 use std::fmt::{Debug, Display, Formatter};
 
 /// An Error returned by the [crate::runtime::ScreepsRuntime]
@@ -11,6 +12,16 @@ pub enum RuntimeError {
     /// This usually means you tried to [block_on](crate::block_on) a future that [delay](crate::time::delay_ticks)s
     /// across ticks
     DeadlockDetected,
+    /// The runtime has been [shut down](crate::runtime::ScreepsRuntime::shutdown)
+    ShutDown,
+    /// The runtime has been dropped
+    Dropped,
+    /// [try_run](crate::runtime::ScreepsRuntime::try_run) was called while the runtime was already
+    /// busy: from within a task, from the [on_tick_end](crate::runtime::Builder::on_tick_end) hook,
+    /// or while [blocking on](crate::block_on) a future
+    AlreadyRunning,
+    /// There is no [current runtime](crate::CURRENT) to run
+    NoRuntime,
 }
 
 impl Display for RuntimeError {
@@ -22,8 +33,137 @@ impl Display for RuntimeError {
             RuntimeError::DeadlockDetected => {
                 write!(f, "Async runtime has been deadlocked")
             }
+            RuntimeError::ShutDown => {
+                write!(f, "Async runtime has been shut down")
+            }
+            RuntimeError::Dropped => {
+                write!(f, "Async runtime has been dropped")
+            }
+            RuntimeError::AlreadyRunning => {
+                write!(f, "Async runtime is already running")
+            }
+            RuntimeError::NoRuntime => {
+                write!(f, "No screeps_async runtime configured")
+            }
         }
     }
 }
 
 impl std::error::Error for RuntimeError {}
+
+/// Error returned by [ScreepsRuntime::try_new](crate::runtime::ScreepsRuntime::try_new) when
+/// another runtime already exists on this thread
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct RuntimeExistsError;
+
+impl Display for RuntimeExistsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "A screeps-async runtime already exists on this thread")
+    }
+}
+
+impl std::error::Error for RuntimeExistsError {}
+
+/// Error returned by [try_spawn](crate::try_spawn) when a task can't be spawned
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[non_exhaustive]
+pub enum SpawnError {
+    /// There is no [current runtime](crate::CURRENT), e.g. because it hasn't been initialized
+    /// yet or has been dropped
+    NoRuntime,
+    /// The runtime is [shutting down](crate::runtime::ScreepsRuntime::shutdown), so the task
+    /// would never be polled
+    ShutDown,
+}
+
+impl Display for SpawnError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpawnError::NoRuntime => {
+                write!(f, "No screeps_async runtime configured")
+            }
+            SpawnError::ShutDown => {
+                write!(f, "Async runtime is shutting down")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SpawnError {}
+
+/// Error returned by an [Abortable](crate::job::Abortable) future that was aborted before it
+/// completed
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Aborted;
+
+impl Display for Aborted {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Future was aborted")
+    }
+}
+
+impl std::error::Error for Aborted {}
+
+/// An error returned when awaiting a [JoinHandle](crate::job::JoinHandle) whose task did not
+/// run to completion
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum JoinError {
+    /// The task panicked while being polled. Holds the panic payload
+    Panicked(Box<dyn Any + Send>),
+    /// The task was cancelled before it could complete.
+    Cancelled,
+}
+
+impl JoinError {
+    /// Returns true if the task was cancelled
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, JoinError::Cancelled)
+    }
+
+    /// Returns true if the task panicked
+    pub fn is_panic(&self) -> bool {
+        matches!(self, JoinError::Panicked(_))
+    }
+
+    /// Consume the error, returning the payload of the panic.
+    /// Pass it to [std::panic::resume_unwind] to continue unwinding from the panic.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the task didn't panic
+    pub fn into_panic(self) -> Box<dyn Any + Send> {
+        match self {
+            JoinError::Panicked(payload) => payload,
+            JoinError::Cancelled => panic!("called `JoinError::into_panic` on a cancelled task"),
+        }
+    }
+}
+
+impl Display for JoinError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JoinError::Panicked(_) => {
+                write!(f, "Task panicked")
+            }
+            JoinError::Cancelled => {
+                write!(f, "Task was cancelled")
+            }
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+/// Error returned by [timeout](crate::time::timeout) when the deadline passes before the future
+/// completes
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Elapsed;
+
+impl Display for Elapsed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Deadline has elapsed")
+    }
+}
+
+impl std::error::Error for Elapsed {}
@@ -0,0 +1,686 @@
+//! Building blocks for writing [Future]s by hand
+//!
+//! These match the shape of the equivalents in the `futures` crate, without pulling it in.
+
+use crate::sync::{WaitKey, WakerList};
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+/// Extension methods for chaining [Future]s, implemented for every [Future]
+pub trait ScreepsFutureExt: Future + Sized {
+    /// Once this future succeeds, pass its output to `next` and await the future it returns.
+    ///
+    /// Resolves to the [Err] of this future right away if it fails, without calling `next`.
+    /// Handy for chaining game actions, like moving to a target and then working on it.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use screeps_async::future::ScreepsFutureExt;
+    /// async fn move_to_source() -> Result<u32, &'static str> { Ok(7) }
+    /// async fn harvest(source: u32) -> Result<u32, &'static str> { Ok(10) }
+    ///
+    /// async fn harvester_role() -> Result<u32, &'static str> {
+    ///     move_to_source().then_action(harvest).await
+    /// }
+    /// ```
+    fn then_action<T, U, E, F, Fut>(self, next: F) -> impl Future<Output = Result<U, E>>
+    where
+        Self: Future<Output = Result<T, E>>,
+        F: FnOnce(T) -> Fut,
+        Fut: Future<Output = Result<U, E>>,
+    {
+        async move { next(self.await?).await }
+    }
+}
+
+impl<F: Future> ScreepsFutureExt for F {}
+
+/// Future returned by [poll_fn]
+pub struct PollFn<F> {
+    f: F,
+}
+
+// The closure is never pinned, so neither is the future
+impl<F> Unpin for PollFn<F> {}
+
+/// Create a [Future] that calls `f` each time it is polled, resolving once `f` returns
+/// [Poll::Ready].
+///
+/// `f` is responsible for arranging to be woken via the [Context] whenever it returns
+/// [Poll::Pending], just like a hand written [Future::poll].
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::future::poll_fn;
+/// # use std::task::Poll;
+/// # screeps_async::initialize();
+/// let mut polls = 0;
+/// let fut = poll_fn(move |cx| {
+///     polls += 1;
+///     if polls < 3 {
+///         cx.waker().wake_by_ref();
+///         Poll::Pending
+///     } else {
+///         Poll::Ready(polls)
+///     }
+/// });
+/// assert_eq!(3, screeps_async::block_on(fut).unwrap());
+/// ```
+pub fn poll_fn<T, F>(f: F) -> PollFn<F>
+where
+    F: FnMut(&mut Context<'_>) -> Poll<T>,
+{
+    PollFn { f }
+}
+
+impl<T, F> Future for PollFn<F>
+where
+    F: FnMut(&mut Context<'_>) -> Poll<T>,
+{
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        (self.f)(cx)
+    }
+}
+
+/// Future returned by [lazy]
+pub struct Lazy<F> {
+    /// [None] once the closure has been called
+    f: Option<F>,
+}
+
+// The closure is never pinned, so neither is the future
+impl<F> Unpin for Lazy<F> {}
+
+/// Create a [Future] that calls `f` the first time it is polled, resolving to its output right
+/// away.
+///
+/// Nothing happens until the future is polled, which is useful for deferring setup work until a
+/// task actually starts running.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::future::lazy;
+/// # screeps_async::initialize();
+/// let fut = lazy(|_cx| 1 + 2);
+/// // Nothing has been computed yet
+/// assert_eq!(3, screeps_async::block_on(fut).unwrap());
+/// ```
+pub fn lazy<R, F>(f: F) -> Lazy<F>
+where
+    F: FnOnce(&mut Context<'_>) -> R,
+{
+    Lazy { f: Some(f) }
+}
+
+impl<R, F> Future for Lazy<F>
+where
+    F: FnOnce(&mut Context<'_>) -> R,
+{
+    type Output = R;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let f = self.f.take().expect("Lazy polled after completion");
+        Poll::Ready(f(cx))
+    }
+}
+
+/// Future returned by [select_all]
+pub struct SelectAll<F> {
+    futures: Vec<F>,
+}
+
+/// Create a [Future] that waits for the first of `futures` to complete.
+///
+/// Resolves to the output of that future, its index in `futures`, and the remaining futures (in
+/// their original order, minus the one that completed) so they can be selected on again. Futures
+/// are polled in order, so if several are ready at once the one with the lowest index wins.
+///
+/// The futures must be [Unpin]; use [Box::pin] to select over futures that aren't.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::future::select_all;
+/// # use screeps_async::time::delay;
+/// # use std::future::Future;
+/// # use std::pin::Pin;
+/// screeps_async::spawn(async move {
+///     let mut enemies: Vec<Pin<Box<dyn Future<Output = u32>>>> = [10, 5, 20]
+///         .into_iter()
+///         .map(|ticks| Box::pin(async move {
+///             delay(ticks).await;
+///             ticks
+///         }) as Pin<Box<dyn Future<Output = u32>>>)
+///         .collect();
+///
+///     while !enemies.is_empty() {
+///         let (ticks, _index, rest) = select_all(enemies).await;
+///         // deal with whichever enemy showed up first
+///         enemies = rest;
+///     }
+/// }).detach();
+/// ```
+///
+/// # Panics
+///
+/// This function panics if `futures` is empty
+pub fn select_all<I>(futures: I) -> SelectAll<I::Item>
+where
+    I: IntoIterator,
+    I::Item: Future + Unpin,
+{
+    let futures: Vec<_> = futures.into_iter().collect();
+    assert!(
+        !futures.is_empty(),
+        "select_all requires at least one future"
+    );
+    SelectAll { futures }
+}
+
+impl<F: Future + Unpin> Future for SelectAll<F> {
+    type Output = (F::Output, usize, Vec<F>);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let ready = self
+            .futures
+            .iter_mut()
+            .enumerate()
+            .find_map(|(index, future)| match Pin::new(future).poll(cx) {
+                Poll::Ready(output) => Some((index, output)),
+                Poll::Pending => None,
+            });
+
+        match ready {
+            Some((index, output)) => {
+                let mut rest = std::mem::take(&mut self.futures);
+                drop(rest.remove(index));
+                Poll::Ready((output, index, rest))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Future returned by [race_ok]
+pub struct RaceOk<F, E> {
+    /// [None] once the future in that slot has failed
+    futures: Vec<Option<F>>,
+    /// The error of each future that has failed so far, in the same slots as [Self::futures]
+    errors: Vec<Option<E>>,
+}
+
+/// Create a [Future] that resolves to the output of the first of `futures` to succeed.
+///
+/// Futures that fail are dropped and their errors kept, so the race only fails once every future
+/// has failed, resolving to [Err] with all of the errors in the same order as `futures`. Unlike
+/// [select_all], a future completing with an error doesn't end the race. The remaining futures
+/// are dropped as soon as one succeeds.
+///
+/// The futures must be [Unpin]; use [Box::pin] to race futures that aren't.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::future::race_ok;
+/// # use std::future::Future;
+/// # use std::pin::Pin;
+/// type PathQuery = Pin<Box<dyn Future<Output = Result<u32, String>>>>;
+///
+/// async fn shortest_path(sources: Vec<PathQuery>) -> Option<u32> {
+///     // Whichever source finds a path first, even if others give up before then
+///     race_ok(sources).await.ok()
+/// }
+/// ```
+///
+/// # Panics
+///
+/// This function panics if `futures` is empty
+pub fn race_ok<I, T, E>(futures: I) -> RaceOk<I::Item, E>
+where
+    I: IntoIterator,
+    I::Item: Future<Output = Result<T, E>> + Unpin,
+{
+    let futures: Vec<_> = futures.into_iter().map(Some).collect();
+    assert!(!futures.is_empty(), "race_ok requires at least one future");
+    let errors = futures.iter().map(|_| None).collect();
+    RaceOk { futures, errors }
+}
+
+// Only the futures, which are Unpin themselves, are ever polled
+impl<F: Unpin, E> Unpin for RaceOk<F, E> {}
+
+impl<T, E, F> Future for RaceOk<F, E>
+where
+    F: Future<Output = Result<T, E>> + Unpin,
+{
+    type Output = Result<T, Vec<E>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        for (slot, error) in this.futures.iter_mut().zip(&mut this.errors) {
+            let Some(future) = slot else {
+                continue;
+            };
+
+            match Pin::new(future).poll(cx) {
+                Poll::Ready(Ok(output)) => {
+                    this.futures.clear();
+                    return Poll::Ready(Ok(output));
+                }
+                Poll::Ready(Err(e)) => {
+                    *slot = None;
+                    *error = Some(e);
+                }
+                Poll::Pending => {}
+            }
+        }
+
+        if this.futures.iter().all(Option::is_none) {
+            let errors = std::mem::take(&mut this.errors);
+            Poll::Ready(Err(errors.into_iter().flatten().collect()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// A cloneable handle to a future whose output is shared by every clone. Created by [shared]
+pub struct Shared<F: Future> {
+    inner: Rc<SharedInner<F>>,
+    /// This clone's place in [SharedInner::notifier] while it is waiting
+    key: Option<WaitKey>,
+}
+
+struct SharedInner<F: Future> {
+    state: RefCell<SharedState<F>>,
+    notifier: Arc<Notifier>,
+}
+
+enum SharedState<F: Future> {
+    Pending(Pin<Box<F>>),
+    /// One of the clones is polling the future right now
+    Polling,
+    /// The future panicked while being polled
+    Poisoned,
+    Done(F::Output),
+}
+
+/// The waker the shared future is polled with, so that waking it wakes every waiting clone
+struct Notifier {
+    waiters: Mutex<WakerList>,
+}
+
+impl Wake for Notifier {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        // Take the wakers first so the lock isn't held while waking them
+        let wakers: Vec<_> = std::iter::from_fn(|| self.waiters.lock().unwrap().pop()).collect();
+        wakers.into_iter().for_each(|(_, waker)| waker.wake());
+    }
+}
+
+/// Wrap `future` so that it can be cloned, with every clone resolving to a clone of its output.
+///
+/// The future only runs once, no matter how many clones await it. It is polled by whichever clone
+/// happens to be polled, and every waiting clone is woken once it makes progress, so it keeps
+/// running as long as any clone is still being awaited.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::future::shared;
+/// # use screeps_async::time::delay;
+/// let distances = shared(async move {
+///     delay(1).await;
+///     // expensive distance transform
+///     vec![0u8; 2500]
+/// });
+///
+/// for _ in 0..3 {
+///     let distances = distances.clone();
+///     screeps_async::spawn(async move {
+///         let distances = distances.await;
+///         // plan buildings
+///     }).detach();
+/// }
+/// ```
+pub fn shared<F>(future: F) -> Shared<F>
+where
+    F: Future,
+    F::Output: Clone,
+{
+    Shared {
+        inner: Rc::new(SharedInner {
+            state: RefCell::new(SharedState::Pending(Box::pin(future))),
+            notifier: Arc::new(Notifier {
+                waiters: Mutex::new(WakerList::new()),
+            }),
+        }),
+        key: None,
+    }
+}
+
+impl<F: Future> Shared<F> {
+    /// Register (or refresh) the waker of this clone with the [Notifier]
+    fn register(&mut self, cx: &Context<'_>) {
+        let mut waiters = self.inner.notifier.waiters.lock().unwrap();
+        match self.key {
+            Some(key) if waiters.update(key, cx) => {}
+            _ => self.key = Some(waiters.register(cx)),
+        }
+    }
+}
+
+impl<F: Future> Clone for Shared<F> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            key: None,
+        }
+    }
+}
+
+impl<F> Future for Shared<F>
+where
+    F: Future,
+    F::Output: Clone,
+{
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        /// Poisons the shared future if polling it panics, so the other clones don't wait forever
+        struct PoisonGuard<'a, F: Future>(&'a SharedInner<F>);
+
+        impl<F: Future> Drop for PoisonGuard<'_, F> {
+            fn drop(&mut self) {
+                if std::thread::panicking() {
+                    self.0.state.replace(SharedState::Poisoned);
+                    Waker::from(self.0.notifier.clone()).wake();
+                }
+            }
+        }
+
+        let state = self.inner.state.replace(SharedState::Polling);
+        let mut future = match state {
+            SharedState::Pending(future) => future,
+            SharedState::Polling => {
+                // A clone further up the stack is polling the future, so it will wake us
+                self.register(cx);
+                return Poll::Pending;
+            }
+            SharedState::Poisoned => panic!("Shared future panicked while being polled"),
+            SharedState::Done(output) => {
+                let res = output.clone();
+                self.inner.state.replace(SharedState::Done(output));
+                return Poll::Ready(res);
+            }
+        };
+
+        // Register first, so that a wake during the poll still reaches us
+        self.register(cx);
+        let waker = Waker::from(self.inner.notifier.clone());
+        let guard = PoisonGuard(&self.inner);
+        let res = future.as_mut().poll(&mut Context::from_waker(&waker));
+        std::mem::forget(guard);
+
+        match res {
+            Poll::Ready(output) => {
+                self.inner.state.replace(SharedState::Done(output.clone()));
+                waker.wake();
+                Poll::Ready(output)
+            }
+            Poll::Pending => {
+                self.inner.state.replace(SharedState::Pending(future));
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<F: Future> Drop for Shared<F> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.inner.notifier.waiters.lock().unwrap().remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spawn;
+    use crate::tests::{init_test, tick};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn lazy_runs_on_first_poll_only() {
+        init_test();
+
+        let calls = Rc::new(Cell::new(0));
+        let handle = {
+            let calls = calls.clone();
+            spawn(lazy(move |_| {
+                calls.set(calls.get() + 1);
+                7
+            }))
+        };
+        assert_eq!(0, calls.get(), "Closure ran before the first poll");
+
+        crate::run().unwrap();
+        assert_eq!(1, calls.get());
+        assert_eq!(7, crate::block_on(handle).unwrap().unwrap());
+    }
+
+    #[test]
+    fn poll_fn_polls_until_ready() {
+        init_test();
+
+        let polls = Rc::new(Cell::new(0));
+        let handle = {
+            let polls = polls.clone();
+            spawn(poll_fn(move |cx| {
+                polls.set(polls.get() + 1);
+                if polls.get() < 3 {
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                } else {
+                    Poll::Ready(())
+                }
+            }))
+        };
+
+        crate::run().unwrap();
+        assert!(handle.is_finished());
+        assert_eq!(3, polls.get());
+    }
+
+    #[test]
+    fn spawn_fn_defers_closure() {
+        init_test();
+
+        let ran = Rc::new(Cell::new(false));
+        let handle = {
+            let ran = ran.clone();
+            crate::spawn_fn(move || {
+                ran.set(true);
+                async move { 1 + 2 }
+            })
+        };
+        assert!(!ran.get(), "Closure ran before the task was polled");
+
+        crate::run().unwrap();
+        assert!(ran.get());
+        assert_eq!(3, crate::block_on(handle).unwrap().unwrap());
+    }
+
+    #[test]
+    fn select_all_resolves_first_and_returns_rest() {
+        init_test();
+
+        let handle = spawn(async move {
+            let futures: Vec<_> = [3, 1, 2]
+                .into_iter()
+                .map(|ticks| {
+                    Box::pin(async move {
+                        crate::time::delay(ticks).await;
+                        ticks
+                    })
+                })
+                .collect();
+
+            let (first, index, rest) = select_all(futures).await;
+            assert_eq!((1, 1), (first, index));
+            assert_eq!(2, rest.len());
+
+            let (second, index, rest) = select_all(rest).await;
+            assert_eq!((2, 1), (second, index));
+            let (third, index, rest) = select_all(rest).await;
+            assert_eq!((3, 0), (third, index));
+            assert!(rest.is_empty());
+        });
+
+        for _ in 0..5 {
+            tick().unwrap();
+        }
+        assert!(handle.is_finished());
+        crate::block_on(handle).unwrap().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "select_all requires at least one future")]
+    fn select_all_empty_panics() {
+        drop(select_all(Vec::<std::future::Ready<()>>::new()));
+    }
+
+    /// A future that resolves to `result` after `ticks` ticks
+    fn after(
+        ticks: u32,
+        result: Result<u32, &'static str>,
+    ) -> Pin<Box<dyn Future<Output = Result<u32, &'static str>>>> {
+        Box::pin(async move {
+            crate::time::delay(ticks).await;
+            result
+        })
+    }
+
+    #[test]
+    fn race_ok_ignores_early_errors() {
+        init_test();
+
+        let handle = spawn(race_ok([
+            after(1, Err("no path")),
+            after(3, Ok(30)),
+            after(2, Ok(20)),
+        ]));
+
+        for _ in 0..4 {
+            tick().unwrap();
+        }
+        assert_eq!(Ok(20), crate::block_on(handle).unwrap().unwrap());
+    }
+
+    #[test]
+    fn race_ok_collects_all_errors() {
+        init_test();
+
+        let handle = spawn(race_ok([
+            after(2, Err("blocked")),
+            after(1, Err("no path")),
+        ]));
+
+        for _ in 0..3 {
+            tick().unwrap();
+        }
+        let res = crate::block_on(handle).unwrap().unwrap();
+        assert_eq!(Err(vec!["blocked", "no path"]), res);
+    }
+
+    #[test]
+    fn then_action_chains_on_success() {
+        init_test();
+
+        let res = crate::block_on(async move {
+            async { Ok::<_, &str>(2) }
+                .then_action(|x| async move { Ok(x * 3) })
+                .await
+        })
+        .unwrap();
+        assert_eq!(Ok(6), res);
+    }
+
+    #[test]
+    fn then_action_skips_on_failure() {
+        init_test();
+
+        let called = Rc::new(Cell::new(false));
+        let res = {
+            let called = called.clone();
+            crate::block_on(async move {
+                async { Err::<u32, _>("out of range") }
+                    .then_action(move |x| {
+                        called.set(true);
+                        async move { Ok(x) }
+                    })
+                    .await
+            })
+            .unwrap()
+        };
+        assert_eq!(Err("out of range"), res);
+        assert!(!called.get(), "Next action ran after a failure");
+    }
+
+    #[test]
+    fn shared_runs_once() {
+        init_test();
+
+        let runs = Rc::new(Cell::new(0));
+        let future = {
+            let runs = runs.clone();
+            shared(async move {
+                runs.set(runs.get() + 1);
+                crate::time::delay(2).await;
+                crate::current_tick()
+            })
+        };
+
+        let handles: Vec<_> = (0..3).map(|_| spawn(future.clone())).collect();
+        drop(future);
+
+        for _ in 0..3 {
+            tick().unwrap();
+        }
+        assert_eq!(1, runs.get(), "Shared future ran more than once");
+        for handle in handles {
+            assert!(handle.is_finished());
+            assert_eq!(2, crate::block_on(handle).unwrap().unwrap());
+        }
+    }
+
+    #[test]
+    fn shared_survives_dropped_poller() {
+        init_test();
+
+        let future = shared(async move {
+            crate::time::delay(2).await;
+            7
+        });
+
+        // The first clone polls the future, then goes away before it completes
+        let first = spawn(future.clone());
+        let second = spawn(future);
+        tick().unwrap();
+        first.abort();
+
+        for _ in 0..3 {
+            tick().unwrap();
+        }
+        assert_eq!(7, crate::block_on(second).unwrap().unwrap());
+    }
+}
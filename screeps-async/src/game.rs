@@ -0,0 +1,147 @@
+//! Async wrappers around Screeps game actions that take multiple ticks to complete
+//!
+//! Requires the `game` feature
+
+use crate::time::yield_tick;
+use screeps::{game, Creep, ErrorCode, Part, StructureSpawn};
+use std::fmt::{Display, Formatter};
+
+/// Error returned by [spawn_creep]
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[non_exhaustive]
+pub enum SpawnCreepError {
+    /// The spawn refused to start spawning the creep
+    Failed(ErrorCode),
+    /// The creep disappeared before it finished spawning, e.g. because the spawn was destroyed
+    Aborted,
+}
+
+impl Display for SpawnCreepError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpawnCreepError::Failed(code) => {
+                write!(f, "Failed to start spawning creep: {code:?}")
+            }
+            SpawnCreepError::Aborted => {
+                write!(f, "Creep was aborted before it finished spawning")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SpawnCreepError {}
+
+/// Spawn a creep named `name` with the given `body`, resolving to the [Creep] once it has
+/// finished spawning.
+///
+/// Spawning is started immediately, then the creep is checked once per tick until it is done.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps::{Part, StructureSpawn};
+/// async fn spawn_harvester(spawn: StructureSpawn) {
+///     match screeps_async::game::spawn_creep(&spawn, &[Part::Work, Part::Move], "harvester").await {
+///         Ok(creep) => { /* put the creep to work */ }
+///         Err(e) => { /* try again later */ }
+///     }
+/// }
+/// ```
+///
+/// # Errors
+///
+/// Returns [SpawnCreepError::Failed] if the spawn couldn't start spawning the creep, or
+/// [SpawnCreepError::Aborted] if the creep disappears before it is done spawning
+pub async fn spawn_creep(
+    spawn: &StructureSpawn,
+    body: &[Part],
+    name: &str,
+) -> Result<Creep, SpawnCreepError> {
+    spawn
+        .spawn_creep(body, name)
+        .map_err(SpawnCreepError::Failed)?;
+
+    let name = name.to_string();
+    wait_for_spawn(|| match game::creeps().get(name.clone()) {
+        Some(creep) if creep.spawning() => SpawnState::Spawning,
+        Some(creep) => SpawnState::Spawned(creep),
+        None => SpawnState::Missing,
+    })
+    .await
+}
+
+/// The state of a creep that has started spawning, as observed on a given tick
+enum SpawnState<T> {
+    Spawning,
+    Spawned(T),
+    /// The creep no longer exists
+    Missing,
+}
+
+/// Check `state` once each tick, starting next tick, until the creep is done spawning
+async fn wait_for_spawn<T>(mut state: impl FnMut() -> SpawnState<T>) -> Result<T, SpawnCreepError> {
+    loop {
+        // Creeps don't show up in the game state until the tick after they start spawning
+        yield_tick().await;
+
+        match state() {
+            SpawnState::Spawning => continue,
+            SpawnState::Spawned(creep) => return Ok(creep),
+            SpawnState::Missing => return Err(SpawnCreepError::Aborted),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spawn;
+    use crate::tests::{init_test, tick};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// Mock a creep that takes `ticks` ticks to spawn, then becomes `state`
+    fn mock_spawn(
+        ticks: u32,
+        state: fn() -> SpawnState<u32>,
+    ) -> crate::job::JoinHandle<Result<u32, SpawnCreepError>> {
+        let checks = Rc::new(Cell::new(0));
+        spawn(wait_for_spawn(move || {
+            checks.set(checks.get() + 1);
+            if checks.get() < ticks {
+                SpawnState::Spawning
+            } else {
+                state()
+            }
+        }))
+    }
+
+    #[test]
+    fn resolves_once_spawned() {
+        init_test();
+
+        let handle = mock_spawn(3, || SpawnState::Spawned(42));
+        for _ in 0..3 {
+            tick().unwrap();
+            assert!(
+                !handle.is_finished(),
+                "Resolved before creep finished spawning"
+            );
+        }
+
+        tick().unwrap();
+        assert_eq!(Ok(42), crate::block_on(handle).unwrap().unwrap());
+    }
+
+    #[test]
+    fn errors_if_aborted() {
+        init_test();
+
+        let handle = mock_spawn(2, || SpawnState::Missing);
+        for _ in 0..3 {
+            tick().unwrap();
+        }
+
+        let res = crate::block_on(handle).unwrap().unwrap();
+        assert_eq!(Err(SpawnCreepError::Aborted), res);
+    }
+}
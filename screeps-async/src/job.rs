@@ -1,31 +1,46 @@
-//! See [JobHandle]
+//! See [JoinHandle]
 
-use async_task::Task;
-use std::cell::RefCell;
+use crate::error::{Aborted, JoinError};
+use async_task::{FallibleTask, Task};
+use std::cell::{Cell, RefCell};
 use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
 use std::pin::Pin;
 use std::rc::Rc;
-use std::task::{Context, Poll};
+use std::task::{Context, Poll, Waker};
 
-/// Reference to a [Future] that has been scheduled via [send](crate::ScreepsRuntime::spawn)
+/// Reference to a [Future] that has been scheduled via [spawn](crate::runtime::ScreepsRuntime::spawn)
 ///
-/// Dropping a [`JobHandle`] cancels it, which means its future won't be polled again.
-/// To drop the [`JobHandle`] handle without canceling it, use [detach()](JobHandle::detach) instead.
-/// To cancel a task gracefully and wait until it is fully destroyed, use the [cancel()](JobHandle::cancel) method
+/// Dropping a [`JoinHandle`] detaches it, which means the task will keep running in the background
+/// but there will be no way to retrieve its output.
+/// To stop the task from being polled again, use [abort()](JoinHandle::abort).
+/// To cancel a task gracefully and wait until it is fully destroyed, use the [cancel()](JoinHandle::cancel) method
 ///
-/// This type implements [Future] to allow awaiting on the result of the spawned task
-pub struct JobHandle<T> {
-    pub(crate) fut_res: Rc<RefCell<Option<T>>>,
-    task: Task<()>,
-    complete: bool,
+/// This type implements [Future] to allow awaiting on the result of the spawned task.
+/// Awaiting a task that was aborted resolves to [JoinError::Cancelled], and awaiting a task that
+/// panicked resolves to [JoinError::Panicked]
+pub struct JoinHandle<T> {
+    /// The spawned task. [None] once the output has been retrieved or the task has been cancelled
+    task: Option<FallibleTask<Result<T, JoinError>>>,
+    abort_handle: AbortHandle,
+    /// Shared with the task's [CatchPanic], cleared once this handle is dropped
+    joined: Rc<Cell<bool>>,
 }
 
-impl<T> JobHandle<T> {
-    pub(crate) fn new(fut_res: Rc<RefCell<Option<T>>>, task: Task<()>) -> Self {
+/// Old name of [JoinHandle]
+#[deprecated(note = "renamed to `JoinHandle`")]
+pub type JobHandle<T> = JoinHandle<T>;
+
+impl<T> JoinHandle<T> {
+    pub(crate) fn new(
+        task: Task<Result<T, JoinError>>,
+        abort_handle: AbortHandle,
+        joined: Rc<Cell<bool>>,
+    ) -> Self {
         Self {
-            fut_res,
-            task,
-            complete: false,
+            task: Some(task.fallible()),
+            abort_handle,
+            joined,
         }
     }
 
@@ -33,41 +48,223 @@ impl<T> JobHandle<T> {
     ///
     /// Returns the task's output if it was completed just before it got canceled, or [`None`] if
     /// it didn't complete.
+    pub async fn cancel(mut self) -> Option<T> {
+        self.task.take()?.cancel().await?.ok()
+    }
+
+    /// Abort the task, preventing its future from being polled again.
     ///
-    /// While it's possible to simply drop the [`JobHandle`] to cancel it, this is a cleaner way of
-    /// canceling because it also waits for the task to stop running.
-    pub async fn cancel(self) -> Option<T> {
-        let _ = self.task.cancel().await;
-        self.fut_res.take()
+    /// The future is dropped the next time the runtime would poll it.
+    /// Aborting a task that has already completed does nothing.
+    pub fn abort(&self) {
+        self.abort_handle.abort();
+    }
+
+    /// Get an [AbortHandle] that can be used to abort this task without needing the [JoinHandle]
+    pub fn abort_handle(&self) -> AbortHandle {
+        self.abort_handle.clone()
     }
 
     /// Detaches the task to let it keep running in the background.
+    ///
+    /// This is equivalent to dropping the [JoinHandle], but is more self-documenting
     pub fn detach(self) {
-        self.task.detach()
+        drop(self)
+    }
+
+    /// Check whether this task has finished running
+    pub fn is_finished(&self) -> bool {
+        self.task.as_ref().is_none_or(FallibleTask::is_finished)
     }
 
-    /// Check whether this job has completed
+    /// Old name of [is_finished](Self::is_finished)
+    #[deprecated(note = "renamed to `is_finished`")]
     pub fn is_complete(&self) -> bool {
-        self.complete || self.fut_res.borrow().is_some()
+        self.is_finished()
     }
 }
 
-impl<T> Future for JobHandle<T> {
-    type Output = T;
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        // Nothing can retrieve a panic from the task anymore
+        self.joined.set(false);
+        if let Some(task) = self.task.take() {
+            task.detach();
+        }
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        if self.complete {
-            panic!("Cannot await on a JobHandle that has already completed")
+        let task = self
+            .task
+            .as_mut()
+            .expect("Cannot await on a JoinHandle that has already completed");
+
+        match Pin::new(task).poll(cx) {
+            Poll::Ready(res) => {
+                self.task = None;
+                Poll::Ready(res.unwrap_or(Err(JoinError::Cancelled)))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Wraps the [Abortable] future of a spawned task to catch panics, so that its [JoinHandle] can
+/// return them as [JoinError::Panicked]
+///
+/// Once the [JoinHandle] has been dropped, panics are resumed instead so that the runtime passes
+/// them to the [panic handler](crate::runtime::Builder::on_task_panic)
+pub(crate) struct CatchPanic<F> {
+    future: F,
+    joined: Rc<Cell<bool>>,
+}
+
+impl<F> CatchPanic<F> {
+    /// Wrap `future`, returning the flag to pass to [JoinHandle::new] along with it
+    pub(crate) fn new(future: F) -> (Self, Rc<Cell<bool>>) {
+        let joined = Rc::new(Cell::new(true));
+        let catch_panic = Self {
+            future,
+            joined: joined.clone(),
+        };
+
+        (catch_panic, joined)
+    }
+}
+
+impl<T, F: Future<Output = Result<T, Aborted>>> Future for CatchPanic<F> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `future` is never moved out of `self`, so it stays pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+        match panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(Poll::Ready(res)) => Poll::Ready(res.map_err(|Aborted| JoinError::Cancelled)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) if this.joined.get() => Poll::Ready(Err(JoinError::Panicked(payload))),
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+}
+
+/// A handle that can be used to abort a spawned task without owning its [JoinHandle], or to abort
+/// an [Abortable] future
+///
+/// Obtained via [JoinHandle::abort_handle] or [abortable]. May be freely cloned.
+#[derive(Clone)]
+pub struct AbortHandle {
+    inner: Rc<AbortInner>,
+}
+
+struct AbortInner {
+    aborted: Cell<bool>,
+    /// Waker of the task polling the [Abortable] future
+    waker: RefCell<Option<Waker>>,
+}
+
+impl AbortHandle {
+    fn new() -> Self {
+        Self {
+            inner: Rc::new(AbortInner {
+                aborted: Cell::new(false),
+                waker: RefCell::new(None),
+            }),
+        }
+    }
+
+    /// Abort the task or [Abortable] future associated with this handle.
+    ///
+    /// The future resolves (and is dropped) the next time it is polled. The task polling it is
+    /// woken so that this happens promptly. See also [JoinHandle::abort]
+    pub fn abort(&self) {
+        if !self.inner.aborted.replace(true) {
+            if let Some(waker) = self.inner.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Check whether [abort](Self::abort) has been called on this handle
+    pub fn is_aborted(&self) -> bool {
+        self.inner.aborted.get()
+    }
+}
+
+/// Wraps a [Future] so that it can be aborted via an [AbortHandle]. Created by [abortable]
+///
+/// Resolves to [Err(Aborted)](Aborted) if the future was aborted before it completed.
+pub struct Abortable<F> {
+    future: Option<F>,
+    inner: Rc<AbortInner>,
+}
+
+impl<F> Abortable<F> {
+    pub(crate) fn new(future: F) -> (Self, AbortHandle) {
+        let handle = AbortHandle::new();
+        let abortable = Self {
+            future: Some(future),
+            inner: handle.inner.clone(),
+        };
+
+        (abortable, handle)
+    }
+}
+
+/// Wrap `future` so that it can be aborted without spawning it as its own task.
+///
+/// Awaiting the returned [Abortable] resolves to the output of `future`, or to [Aborted] if the
+/// [AbortHandle] was used to abort it first. The inner future is dropped as soon as the
+/// [Abortable] sees it has been aborted, releasing anything it holds such as timers.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::time::delay;
+/// screeps_async::spawn(async move {
+///     let (path, abort_handle) = screeps_async::abortable(async move {
+///         delay(10).await;
+///         // find a path
+///     });
+///     // hand `abort_handle` to whatever decides the path is no longer needed
+///     if path.await.is_err() {
+///         // the target went away
+///     }
+/// }).detach();
+/// ```
+pub fn abortable<F: Future>(future: F) -> (Abortable<F>, AbortHandle) {
+    Abortable::new(future)
+}
+
+impl<F: Future> Future for Abortable<F> {
+    type Output = Result<F::Output, Aborted>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `future` is never moved out of, only dropped in place via Pin::set
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+        if this.inner.aborted.get() {
+            // Drop the future right away so it releases any resources (e.g. timers) it holds
+            future.set(None);
+            return Poll::Ready(Err(Aborted));
         }
-        let res = self.fut_res.borrow_mut().take();
 
-        match res {
-            Some(res) => {
-                self.complete = true;
-                Poll::Ready(res)
+        let Some(fut) = future.as_mut().as_pin_mut() else {
+            panic!("Abortable polled after completion");
+        };
+
+        match fut.poll(cx) {
+            Poll::Ready(res) => {
+                future.set(None);
+                Poll::Ready(Ok(res))
             }
-            None => {
-                cx.waker().wake_by_ref();
+            Poll::Pending => {
+                this.inner.waker.replace(Some(cx.waker().clone()));
                 Poll::Pending
             }
         }
@@ -76,8 +273,48 @@ impl<T> Future for JobHandle<T> {
 
 #[cfg(test)]
 mod tests {
+    use crate::error::{Aborted, JoinError};
     use crate::spawn;
     use crate::tests::init_test;
+    use crate::time::{delay_ticks, yield_now};
+    use crate::with_runtime;
+    use std::cell::OnceCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_join_panicked() {
+        init_test();
+
+        let handle = spawn(async move {
+            yield_now().await;
+            panic!("out of energy");
+        });
+
+        // The panic is returned from the handle rather than unwinding out of run
+        crate::run().unwrap();
+        assert!(handle.is_finished());
+
+        match crate::block_on(handle).unwrap() {
+            Err(JoinError::Panicked(payload)) => {
+                assert_eq!(Some(&"out of energy"), payload.downcast_ref::<&str>());
+            }
+            res => panic!("Expected a panic, got {res:?}"),
+        }
+    }
+
+    #[test]
+    fn test_join_aborted() {
+        init_test();
+
+        let handle = spawn(async move { delay_ticks(5).await });
+        crate::tests::tick().unwrap();
+        handle.abort();
+        crate::tests::tick().unwrap();
+
+        let err = crate::block_on(handle).unwrap().unwrap_err();
+        assert!(matches!(err, JoinError::Cancelled));
+        assert!(!err.is_panic());
+    }
 
     #[test]
     fn test_cancel() {
@@ -99,6 +336,135 @@ mod tests {
 
         let result = crate::block_on(async move { spawn(async move { true }).await }).unwrap();
 
-        assert!(result, "Failed to await spawned future");
+        assert!(result.unwrap(), "Failed to await spawned future");
+    }
+
+    #[test]
+    fn test_is_finished() {
+        init_test();
+
+        let handle = spawn(async move {
+            yield_now().await;
+        });
+
+        assert!(!handle.is_finished());
+
+        crate::run().unwrap();
+
+        assert!(handle.is_finished());
+    }
+
+    #[test]
+    fn test_drop_detaches() {
+        init_test();
+
+        let has_run = Rc::new(OnceCell::new());
+        {
+            let has_run = has_run.clone();
+            drop(spawn(async move {
+                has_run.set(()).unwrap();
+            }));
+        }
+
+        crate::run().unwrap();
+
+        assert!(has_run.get().is_some(), "Dropped task was cancelled");
+    }
+
+    #[test]
+    fn test_abort_delayed_task() {
+        init_test();
+
+        let handle = spawn(async move {
+            delay_ticks(5).await;
+        });
+
+        crate::tests::tick().unwrap();
+
+        let has_waker = || {
+            with_runtime(|runtime| {
+                let timers = runtime.timers.borrow();
+                timers.ticks.values().any(|entries| !entries.is_empty())
+            })
+        };
+        assert!(has_waker(), "Task is not waiting on a timer");
+
+        handle.abort();
+        crate::tests::tick().unwrap();
+
+        assert!(!has_waker(), "Aborted task's waker was not removed");
+        assert!(handle.is_finished());
+
+        let result = crate::block_on(handle).unwrap();
+        assert!(matches!(result, Err(JoinError::Cancelled)));
+    }
+
+    #[test]
+    fn test_abort_handle() {
+        init_test();
+
+        let has_run = Rc::new(OnceCell::new());
+        let handle = {
+            let has_run = has_run.clone();
+            spawn(async move {
+                has_run.set(()).unwrap();
+            })
+        };
+
+        let abort_handle = handle.abort_handle();
+        handle.detach();
+        abort_handle.clone().abort();
+        assert!(abort_handle.is_aborted());
+
+        crate::run().unwrap();
+
+        assert!(has_run.get().is_none(), "Aborted task was polled");
+    }
+
+    #[test]
+    fn test_abortable_before_completion() {
+        init_test();
+
+        let (future, abort_handle) = crate::abortable(delay_ticks(5));
+        let handle = spawn(future);
+        crate::tests::tick().unwrap();
+
+        let has_waker = || with_runtime(|runtime| runtime.timer_count() > 0);
+        assert!(has_waker(), "Future is not waiting on a timer");
+
+        abort_handle.abort();
+        crate::tests::tick().unwrap();
+
+        assert!(!has_waker(), "Aborted future's waker was not removed");
+        let result = crate::block_on(handle).unwrap().unwrap();
+        assert_eq!(Err(Aborted), result);
+    }
+
+    #[test]
+    fn test_abortable_completes_before_abort() {
+        init_test();
+
+        let (future, abort_handle) = crate::abortable(async move { 1 + 2 });
+        let result = crate::block_on(async move {
+            let res = future.await;
+            abort_handle.abort();
+            res
+        })
+        .unwrap();
+
+        assert_eq!(Ok(3), result);
+    }
+
+    #[test]
+    fn test_abort_completed_task() {
+        init_test();
+
+        let handle = spawn(async move { 1 + 2 });
+
+        crate::run().unwrap();
+        handle.abort();
+
+        let result = crate::block_on(handle).unwrap();
+        assert_eq!(3, result.unwrap());
     }
 }
@@ -0,0 +1,159 @@
+//! Interop with JavaScript APIs
+//!
+//! Requires the `js` feature
+
+use js_sys::Promise;
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsValue;
+
+type Callback = Closure<dyn FnMut(JsValue)>;
+
+/// State shared between a [PromiseFuture] and the callbacks attached to its [Promise]
+#[derive(Default)]
+struct PromiseState {
+    result: Option<Result<JsValue, JsValue>>,
+    waker: Option<Waker>,
+    /// Kept alive until the [Promise] settles, since JS may call either of them until then
+    callbacks: Option<(Callback, Callback)>,
+}
+
+impl PromiseState {
+    fn settle(state: &RefCell<Self>, result: Result<JsValue, JsValue>) {
+        let (waker, callbacks) = {
+            let mut state = state.borrow_mut();
+            state.result = Some(result);
+            (state.waker.take(), state.callbacks.take())
+        };
+        // Drop the callbacks outside the borrow, this also breaks the reference cycle through them
+        drop(callbacks);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// Future for the result of a JavaScript [Promise]. Created by [await_promise]
+///
+/// Resolves to [Ok] with the value the [Promise] was fulfilled with, or to [Err] with the value it
+/// was rejected with.
+pub struct PromiseFuture {
+    state: Rc<RefCell<PromiseState>>,
+}
+
+impl PromiseFuture {
+    fn new() -> Self {
+        Self {
+            state: Rc::new(RefCell::new(PromiseState::default())),
+        }
+    }
+}
+
+impl Future for PromiseFuture {
+    type Output = Result<JsValue, JsValue>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.borrow_mut();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                match &mut state.waker {
+                    Some(waker) => waker.clone_from(cx.waker()),
+                    None => state.waker = Some(cx.waker().clone()),
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Wait for a JavaScript [Promise] to settle.
+///
+/// When the [Promise] settles, the awaiting task is scheduled and picks up the result the next
+/// time the runtime is [run](crate::run). JS only settles promises between calls into the bot's
+/// code, so in practice the result is available on the next call to [run](crate::run) after the
+/// [Promise] settled, which may be a later tick.
+///
+/// If the [PromiseFuture] is dropped before the [Promise] settles, the callbacks attached to it
+/// are kept alive until it does, and are freed then. A [Promise] that never settles keeps them
+/// alive forever, so avoid awaiting those.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::js::await_promise;
+/// # fn start_download() -> js_sys::Promise { unimplemented!() }
+/// screeps_async::spawn(async move {
+///     match await_promise(start_download()).await {
+///         Ok(value) => { /* use the value */ }
+///         Err(error) => { /* the promise was rejected */ }
+///     }
+/// }).detach();
+/// ```
+pub fn await_promise(promise: Promise) -> PromiseFuture {
+    let future = PromiseFuture::new();
+
+    let resolve = {
+        let state = future.state.clone();
+        Closure::once(move |value| PromiseState::settle(&state, Ok(value)))
+    };
+    let reject = {
+        let state = future.state.clone();
+        Closure::once(move |error| PromiseState::settle(&state, Err(error)))
+    };
+    let _ = promise.then2(&resolve, &reject);
+    future.state.borrow_mut().callbacks = Some((resolve, reject));
+
+    future
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spawn;
+    use crate::tests::{init_test, tick};
+    use std::cell::Cell;
+
+    // The callbacks can only be created on wasm, so these settle the shared state directly,
+    // just as the callbacks would
+
+    #[test]
+    fn wakes_task_on_resolve() {
+        init_test();
+        let future = PromiseFuture::new();
+        let state = future.state.clone();
+        let resolved = Rc::new(Cell::new(false));
+        {
+            let resolved = resolved.clone();
+            spawn(async move {
+                assert!(future.await.is_ok());
+                resolved.set(true);
+            })
+            .detach();
+        }
+
+        tick().unwrap();
+        tick().unwrap();
+        assert!(!resolved.get());
+
+        PromiseState::settle(&state, Ok(JsValue::UNDEFINED));
+        tick().unwrap();
+        assert!(resolved.get());
+    }
+
+    #[test]
+    fn resolves_to_err_on_reject() {
+        init_test();
+        let future = PromiseFuture::new();
+        let state = future.state.clone();
+        // Settling before the first poll is picked up on that poll
+        PromiseState::settle(&state, Err(JsValue::NULL));
+
+        let handle = spawn(async move { future.await.is_err() });
+        tick().unwrap();
+        assert!(crate::run_blocking(handle).unwrap());
+    }
+}
@@ -32,21 +32,41 @@
 //!     screeps_async::run().unwrap();
 //! }
 //! ```
+//!
+//! # Testing off the server
+//!
+//! The game bindings are behind the default `screeps` feature. Without it, or with the `testing`
+//! feature enabled, the runtime reads the game time and CPU usage from the [testing] module
+//! instead, which can be set from your tests. See [testing::TestRuntime] for a harness to drive
+//! the runtime tick by tick.
 
 pub mod macros;
 pub use macros::*;
 
+// Lets the proc macros refer to `::screeps_async` from within this crate too
+extern crate self as screeps_async;
+
 use std::cell::RefCell;
+pub mod debug;
 pub mod error;
+pub mod future;
+#[cfg(feature = "game")]
+pub mod game;
 pub mod job;
+#[cfg(feature = "js")]
+pub mod js;
 pub mod runtime;
+pub mod stream;
 pub mod sync;
+pub mod task;
 pub mod time;
 
-use crate::error::RuntimeError;
-use crate::job::JobHandle;
-use crate::runtime::{Builder, ScreepsRuntime};
-use std::future::Future;
+use crate::error::{RuntimeError, SpawnError};
+pub use crate::job::abortable;
+use crate::job::JoinHandle;
+use crate::runtime::{Builder, Priority, ScreepsRuntime, TickReport};
+pub use crate::time::maybe_yield;
+use std::future::{Future, IntoFuture};
 
 thread_local! {
     /// The current runtime
@@ -66,7 +86,7 @@ pub fn initialize() {
     Builder::new().apply()
 }
 
-/// Run the task executor for one tick
+/// Run the task executor for one tick. See [ScreepsRuntime::run] for details
 ///
 /// This is just shorthand for:
 /// ```no_run
@@ -79,10 +99,21 @@ pub fn initialize() {
 /// # Panics
 ///
 /// This function panics if the current runtime is not set
-pub fn run() -> Result<(), RuntimeError> {
+pub fn run() -> Result<TickReport, RuntimeError> {
     with_runtime(|runtime| runtime.run())
 }
 
+/// Run the task executor for one tick, returning an error instead of panicking if that isn't
+/// possible, e.g. because no runtime has been set up. See [ScreepsRuntime::try_run] for details
+pub fn try_run() -> Result<TickReport, RuntimeError> {
+    CURRENT.with(|runtime| {
+        // Already borrowed mutably means the runtime is being replaced or dropped
+        let runtime = runtime.try_borrow().map_err(|_| RuntimeError::NoRuntime)?;
+        let runtime = runtime.as_ref().ok_or(RuntimeError::NoRuntime)?;
+        runtime.try_run()
+    })
+}
+
 /// The main entrypoint for the async runtime. Runs a future to completion.
 ///
 /// See [ScreepsRuntime::block_on] for details
@@ -93,18 +124,158 @@ where
     with_runtime(|runtime| runtime.block_on(future))
 }
 
-/// Spawn a new async task
+/// Run the async entrypoint of your bot for this tick.
+///
+/// See [ScreepsRuntime::run_entrypoint] for details
+pub fn run_entrypoint<F>(future: F) -> Result<F::Output, RuntimeError>
+where
+    F: Future + 'static,
+{
+    with_runtime(|runtime| runtime.run_entrypoint(future))
+}
+
+/// Drive a future to completion right now, from synchronous code.
+///
+/// See [ScreepsRuntime::run_blocking] for details
+///
+/// # Panics
+///
+/// This function panics if the current runtime is not set, or if the future can't complete
+pub fn run_blocking<F: Future>(future: F) -> F::Output {
+    with_runtime(|runtime| runtime.run_blocking(future))
+}
+
+/// The current game tick, as seen by the running task
+///
+/// See [ScreepsRuntime::current_tick] for details
+///
+/// # Panics
+///
+/// This function panics if the current runtime is not set, or if not called from within a task
+pub fn current_tick() -> u32 {
+    with_runtime(|runtime| runtime.current_tick())
+}
+
+/// The fraction of this tick's time allocation that is still unused
+///
+/// See [ScreepsRuntime::time_remaining] for details
+///
+/// # Panics
+///
+/// This function panics if the current runtime is not set, or if not called from within a task
+pub fn time_remaining() -> f64 {
+    with_runtime(|runtime| runtime.time_remaining())
+}
+
+/// Spawn a new async task. See [ScreepsRuntime::spawn] for details
 ///
 /// # Panics
 ///
 /// This function panics if the current runtime is not set
-pub fn spawn<F>(future: F) -> JobHandle<F::Output>
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
 where
-    F: Future + 'static,
+    F: IntoFuture,
+    F::IntoFuture: 'static,
 {
     with_runtime(|runtime| runtime.spawn(future))
 }
 
+/// Spawn a new async task, returning an error instead of panicking if that isn't possible
+///
+/// Useful for library code that can't assume a runtime has been set up. See
+/// [ScreepsRuntime::try_spawn] for details
+pub fn try_spawn<F>(future: F) -> Result<JoinHandle<F::Output>, SpawnError>
+where
+    F: IntoFuture,
+    F::IntoFuture: 'static,
+{
+    CURRENT.with(|runtime| {
+        // Already borrowed mutably means the runtime is being replaced or dropped
+        let runtime = runtime.try_borrow().map_err(|_| SpawnError::NoRuntime)?;
+        let runtime = runtime.as_ref().ok_or(SpawnError::NoRuntime)?;
+        runtime.try_spawn(future)
+    })
+}
+
+/// Spawn a new async task that calls `f` to create its future the first time it is polled
+///
+/// See [ScreepsRuntime::spawn_fn] for details
+///
+/// # Panics
+///
+/// This function panics if the current runtime is not set
+pub fn spawn_fn<F, Fut>(f: F) -> JoinHandle<Fut::Output>
+where
+    F: FnOnce() -> Fut + 'static,
+    Fut: Future + 'static,
+{
+    with_runtime(|runtime| runtime.spawn_fn(f))
+}
+
+/// Spawn a new async task named `name`
+///
+/// See [ScreepsRuntime::spawn_named] for details
+///
+/// # Panics
+///
+/// This function panics if the current runtime is not set
+pub fn spawn_named<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+{
+    with_runtime(|runtime| runtime.spawn_named(name, future))
+}
+
+/// Spawn a new async task that will first be polled `ticks` ticks from now
+///
+/// See [ScreepsRuntime::spawn_after] for details
+///
+/// # Panics
+///
+/// This function panics if the current runtime is not set
+pub fn spawn_after<F>(ticks: u32, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+{
+    with_runtime(|runtime| runtime.spawn_after(ticks, future))
+}
+
+/// Spawn a new async task with the given [Priority]
+///
+/// See [ScreepsRuntime::spawn_with_priority] for details
+///
+/// # Panics
+///
+/// This function panics if the current runtime is not set
+pub fn spawn_with_priority<F>(priority: Priority, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+{
+    with_runtime(|runtime| runtime.spawn_with_priority(priority, future))
+}
+
+/// Spawn a new async task that may use at most `fraction` of the tick limit each tick
+///
+/// See [ScreepsRuntime::spawn_with_budget] for details
+///
+/// # Panics
+///
+/// This function panics if the current runtime is not set
+pub fn spawn_with_budget<F>(fraction: f64, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+{
+    with_runtime(|runtime| runtime.spawn_with_budget(fraction, future))
+}
+
+/// Check whether a runtime has been set as the [current runtime](CURRENT) on this thread.
+///
+/// Useful for library code that wants to [spawn] tasks when a runtime is available, but fall back
+/// to doing the work synchronously otherwise
+pub fn is_runtime_initialized() -> bool {
+    CURRENT.with_borrow(Option::is_some)
+}
+
 /// Acquire a reference to the [ScreepsRuntime].
 ///
 /// # Panics
@@ -122,7 +293,7 @@ where
     })
 }
 
-#[cfg(not(test))]
+#[cfg(all(feature = "screeps", not(any(test, feature = "testing"))))]
 mod utils {
     use screeps::game;
 
@@ -134,35 +305,35 @@ mod utils {
     pub(super) fn time_used() -> f64 {
         game::cpu::get_used() / game::cpu::tick_limit()
     }
+
+    pub(super) fn bucket() -> i32 {
+        game::cpu::bucket()
+    }
+
+    pub(super) fn tick_limit() -> f64 {
+        game::cpu::tick_limit()
+    }
 }
 
-#[cfg(test)]
+#[cfg(any(test, feature = "testing", not(feature = "screeps")))]
+pub mod testing;
+
+#[cfg(any(test, feature = "testing", not(feature = "screeps")))]
 mod utils {
-    pub(super) use super::tests::*;
+    pub(super) use super::testing::{bucket, game_time, tick_limit, time_used};
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::error::RuntimeError;
+    use crate::error::{RuntimeError, SpawnError};
     use crate::runtime::Builder;
-    use std::cell::RefCell;
-
-    thread_local! {
-        pub(crate) static GAME_TIME: RefCell<u32> = const { RefCell::new(0) };
-        pub(crate) static TIME_USED: RefCell<f64> = const { RefCell::new(0.0) };
-    }
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
 
-    pub(super) fn game_time() -> u32 {
-        GAME_TIME.with_borrow(|t| *t)
-    }
-
-    pub(super) fn time_used() -> f64 {
-        TIME_USED.with_borrow(|t| *t)
-    }
+    pub(crate) use crate::testing::{game_time, BUCKET, GAME_TIME, TICK_LIMIT, TIME_USED};
 
     pub(crate) fn init_test() {
-        GAME_TIME.with_borrow_mut(|t| *t = 0);
-        TIME_USED.with_borrow_mut(|t| *t = 0.0);
+        crate::testing::reset();
 
         Builder::new().apply()
     }
@@ -170,7 +341,112 @@ mod tests {
     /// Calls [crate::run] and increments [GAME_TIME] if [crate::run] succeeded
     pub(crate) fn tick() -> Result<(), RuntimeError> {
         crate::run()?;
-        GAME_TIME.with_borrow_mut(|t| *t += 1);
+        GAME_TIME.with_borrow_mut(|t| *t = t.wrapping_add(1));
         Ok(())
     }
+
+    thread_local! {
+        /// Number of allocations made by this thread, see [allocations]
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// Counts the allocations of each thread, so tests can check that code doesn't allocate
+    struct CountingAllocator;
+
+    impl CountingAllocator {
+        fn count() {
+            // Allocations while the thread is being torn down aren't interesting
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        }
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            Self::count();
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            Self::count();
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    /// The number of allocations (including reallocations) made by this thread so far
+    pub(crate) fn allocations() -> usize {
+        ALLOCATIONS.with(Cell::get)
+    }
+
+    #[test]
+    fn try_spawn_without_runtime() {
+        assert_eq!(
+            Some(SpawnError::NoRuntime),
+            crate::try_spawn(async {}).err()
+        );
+
+        init_test();
+        let handle = crate::try_spawn(async { 1 + 2 }).unwrap();
+        crate::run().unwrap();
+        assert_eq!(3, crate::block_on(handle).unwrap().unwrap());
+
+        drop(crate::CURRENT.with_borrow_mut(Option::take));
+        assert_eq!(
+            Some(SpawnError::NoRuntime),
+            crate::try_spawn(async {}).err()
+        );
+    }
+
+    #[test]
+    fn try_run_without_runtime() {
+        assert_eq!(Some(RuntimeError::NoRuntime), crate::try_run().err());
+
+        init_test();
+        assert!(crate::try_run().is_ok());
+    }
+
+    #[test]
+    fn try_spawn_after_shutdown() {
+        init_test();
+        crate::with_runtime(|runtime| runtime.shutdown());
+
+        assert_eq!(Some(SpawnError::ShutDown), crate::try_spawn(async {}).err());
+    }
+
+    #[test]
+    fn spawn_into_future() {
+        /// A builder-style type that only becomes a future once spawned
+        struct Sum(Vec<u32>);
+
+        impl std::future::IntoFuture for Sum {
+            type Output = u32;
+            type IntoFuture = std::future::Ready<u32>;
+
+            fn into_future(self) -> Self::IntoFuture {
+                std::future::ready(self.0.into_iter().sum())
+            }
+        }
+
+        init_test();
+        let handle = crate::spawn(Sum(vec![1, 2, 3]));
+        crate::run().unwrap();
+        assert_eq!(6, crate::run_blocking(handle).unwrap());
+    }
+
+    #[test]
+    fn is_runtime_initialized() {
+        assert!(!crate::is_runtime_initialized());
+
+        init_test();
+        assert!(crate::is_runtime_initialized());
+
+        drop(crate::CURRENT.with_borrow_mut(Option::take));
+        assert!(!crate::is_runtime_initialized());
+    }
 }
@@ -2,6 +2,8 @@
 
 pub use screeps_async_macros::*;
 
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 /// Run a block of code each tick, resolving the specified list of
 /// dependencies by calling `.resolve` each tick and exiting if
 /// any dependency can't be resolved.
@@ -34,6 +36,7 @@ pub use screeps_async_macros::*;
 ///     }).await;
 /// }
 /// ```
+#[cfg(feature = "screeps")]
 #[macro_export]
 macro_rules! each_tick {
     ($($dep:ident),*, $body:block) => {
@@ -52,3 +55,406 @@ macro_rules! each_tick {
         }
     }
 }
+
+/// Declare a new task-local key of type [`task::LocalKey`](crate::task::LocalKey)
+///
+/// Values are set for the duration of a future with [`LocalKey::scope`](crate::task::LocalKey::scope)
+/// and accessed with [`LocalKey::with`](crate::task::LocalKey::with). This allows providing
+/// per-task context to code deep in the call stack without passing it through every function.
+///
+/// # Examples
+///
+/// ```
+/// screeps_async::task_local! {
+///     static CREEP_NAME: String;
+/// }
+///
+/// async fn log(msg: &str) {
+///     CREEP_NAME.with(|name| println!("[{name}] {msg}"));
+/// }
+///
+/// screeps_async::initialize();
+/// screeps_async::spawn(CREEP_NAME.scope("harvester".to_string(), async move {
+///     log("Harvesting").await;
+/// })).detach();
+/// ```
+#[macro_export]
+macro_rules! task_local {
+    () => {};
+
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty; $($rest:tt)*) => {
+        $crate::task_local!($(#[$attr])* $vis static $name: $t);
+        $crate::task_local!($($rest)*);
+    };
+
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty) => {
+        $(#[$attr])*
+        $vis const $name: $crate::task::LocalKey<$t> = {
+            ::std::thread_local! {
+                static __KEY: ::std::cell::RefCell<::std::option::Option<$t>> =
+                    const { ::std::cell::RefCell::new(::std::option::Option::None) };
+            }
+
+            $crate::task::LocalKey { inner: __KEY }
+        };
+    };
+}
+
+/// Await a list of futures that resolve to [`Result`]s one after the other, stopping at the first
+/// [`Err`].
+///
+/// Evaluates to `Ok` with a tuple of the outputs of every future, or to the first `Err`. Each
+/// future expression is only evaluated once the previous future has succeeded, so later actions
+/// aren't even started if an earlier one fails. All of the futures must have the same error type.
+///
+/// `sequence!` must be used within an async context, or `.await`ed.
+///
+/// # Examples
+///
+/// ```no_run
+/// async fn move_to_source() -> Result<(), &'static str> { Ok(()) }
+/// async fn harvest() -> Result<u32, &'static str> { Ok(10) }
+///
+/// async fn harvester_role() -> Result<u32, &'static str> {
+///     let ((), energy) = screeps_async::sequence![move_to_source(), harvest()].await?;
+///     Ok(energy)
+/// }
+/// ```
+#[macro_export]
+macro_rules! sequence {
+    ($($fut:expr),+ $(,)?) => {
+        async {
+            ::core::result::Result::Ok(($(
+                match $fut.await {
+                    ::core::result::Result::Ok(output) => output,
+                    ::core::result::Result::Err(err) => return ::core::result::Result::Err(err),
+                },
+            )+))
+        }
+    };
+}
+
+/// Pick which branch an unbiased [select!] polls first. Used by the macro, not public API.
+///
+/// The pick comes from the [current runtime's](crate::CURRENT) RNG, so that it is reproducible
+/// with [Builder::rng_seed](crate::runtime::Builder::rng_seed). Without a current runtime the
+/// branches just take turns.
+#[doc(hidden)]
+pub fn __select_start(count: usize) -> usize {
+    static NEXT_START: AtomicUsize = AtomicUsize::new(0);
+
+    let random = crate::CURRENT.with(|runtime| {
+        let runtime = runtime.try_borrow().ok()?;
+        Some(runtime.as_ref()?.next_random())
+    });
+    match random {
+        // Truncating is fine, any bits of the number are as random as any others
+        Some(random) => random as usize % count,
+        None => NEXT_START.fetch_add(1, Ordering::Relaxed) % count,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sync::oneshot;
+    use crate::tests::{init_test, tick};
+    use crate::time::delay;
+    use crate::{spawn, with_runtime};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn timers_registered() -> bool {
+        with_runtime(|runtime| {
+            let timers = runtime.timers.borrow();
+            timers.ticks.values().any(|entries| !entries.is_empty())
+        })
+    }
+
+    #[test]
+    fn select_delay_wins() {
+        init_test();
+
+        let (_tx, rx) = oneshot::channel::<u32>();
+        let handle = spawn(async move {
+            crate::select! {
+                res = rx => res.ok(),
+                _ = delay(2) => None,
+            }
+        });
+
+        while !handle.is_finished() {
+            tick().unwrap();
+        }
+
+        let result = crate::block_on(handle).unwrap().unwrap();
+        assert_eq!(None, result);
+        assert_eq!(3, crate::utils::game_time());
+    }
+
+    #[test]
+    fn select_ready_future_wins() {
+        init_test();
+
+        let (tx, rx) = oneshot::channel();
+        let handle = spawn(async move {
+            crate::select! {
+                _ = delay(5) => None,
+                Ok(value) = rx => Some(value),
+            }
+        });
+
+        tick().unwrap();
+        assert!(timers_registered(), "Delay branch did not register a timer");
+
+        tx.send(42).unwrap();
+        tick().unwrap();
+
+        assert!(handle.is_finished());
+        assert!(
+            !timers_registered(),
+            "Dropped delay branch did not remove its timer"
+        );
+
+        let result = crate::block_on(handle).unwrap().unwrap();
+        assert_eq!(Some(42), result);
+    }
+
+    #[test]
+    fn select_disables_unmatched_branch() {
+        init_test();
+
+        let (tx, rx) = oneshot::channel::<u32>();
+        drop(tx);
+
+        let handle = spawn(async move {
+            crate::select! {
+                Ok(value) = rx => value,
+                _ = delay(1) => 0,
+            }
+        });
+
+        while !handle.is_finished() {
+            tick().unwrap();
+        }
+
+        let result = crate::block_on(handle).unwrap().unwrap();
+        assert_eq!(0, result);
+    }
+
+    #[test]
+    fn select_else_branch() {
+        init_test();
+
+        let result = crate::block_on(async move {
+            crate::select! {
+                Some(value) = async { None::<u32> } => value,
+                else => 7,
+            }
+        })
+        .unwrap();
+
+        assert_eq!(7, result);
+    }
+
+    #[test]
+    fn select_biased_polls_in_order() {
+        init_test();
+
+        let winners = crate::block_on(async move {
+            let mut winners = Vec::new();
+            for _ in 0..4 {
+                winners.push(crate::select! {
+                    biased;
+                    v = async { "enemy detected" } => v,
+                    v = async { "routine patrol" } => v,
+                });
+            }
+            winners
+        })
+        .unwrap();
+
+        assert_eq!(vec!["enemy detected"; 4], winners);
+    }
+
+    #[test]
+    fn select_unbiased_shares_ties() {
+        init_test();
+
+        let winners = crate::block_on(async move {
+            let mut winners = Vec::new();
+            for _ in 0..4 {
+                winners.push(crate::select! {
+                    v = async { 1 } => v,
+                    v = async { 2 } => v,
+                });
+            }
+            winners
+        })
+        .unwrap();
+
+        assert!(winners.contains(&1) && winners.contains(&2), "{winners:?}");
+    }
+
+    /// Pick between several ready branches over and over, returning the winners
+    fn select_winners(seed: u64) -> Vec<u32> {
+        crate::runtime::Builder::new().rng_seed(seed).apply();
+
+        crate::block_on(async move {
+            let mut winners = Vec::new();
+            for _ in 0..16 {
+                winners.push(crate::select! {
+                    v = async { 1 } => v,
+                    v = async { 2 } => v,
+                    v = async { 3 } => v,
+                });
+            }
+            winners
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn select_is_deterministic_with_seed() {
+        init_test();
+
+        let first = select_winners(42);
+        let second = select_winners(42);
+        assert_eq!(first, second);
+        assert!(
+            (1..=3).all(|branch| first.contains(&branch)),
+            "Seeded select still favours some branches: {first:?}"
+        );
+    }
+
+    #[test]
+    fn sequence_runs_in_order() {
+        init_test();
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let step = {
+            let log = log.clone();
+            move |name: &'static str, ticks: u32| {
+                let log = log.clone();
+                async move {
+                    delay(ticks).await;
+                    log.borrow_mut().push(name);
+                    Ok::<_, &str>(ticks)
+                }
+            }
+        };
+
+        let handle = spawn(async move {
+            crate::sequence![step("move", 2), step("harvest", 1), step("transfer", 1)].await
+        });
+        while !handle.is_finished() {
+            tick().unwrap();
+        }
+
+        let res = crate::block_on(handle).unwrap().unwrap();
+        assert_eq!(Ok((2, 1, 1)), res);
+        assert_eq!(vec!["move", "harvest", "transfer"], log.take());
+    }
+
+    #[test]
+    fn sequence_short_circuits() {
+        init_test();
+
+        let started = Rc::new(RefCell::new(Vec::new()));
+        let step = {
+            let started = started.clone();
+            move |name: &'static str, res: Result<(), &'static str>| {
+                started.borrow_mut().push(name);
+                async move { res }
+            }
+        };
+
+        let res = crate::block_on(async move {
+            crate::sequence![
+                step("move", Ok(())),
+                step("harvest", Err("source empty")),
+                step("transfer", Ok(())),
+            ]
+            .await
+        })
+        .unwrap();
+
+        assert_eq!(Err("source empty"), res);
+        assert_eq!(
+            vec!["move", "harvest"],
+            started.take(),
+            "Action after the failure was started"
+        );
+    }
+
+    #[test]
+    fn join_runs_concurrently() {
+        init_test();
+
+        let handle = spawn(async move {
+            let start = crate::utils::game_time();
+            let (a, b, _) = crate::join!(
+                async {
+                    delay(3).await;
+                    1
+                },
+                async {
+                    delay(5).await;
+                    2
+                },
+                delay(1),
+            );
+            (a, b, crate::utils::game_time() - start)
+        });
+
+        while !handle.is_finished() {
+            tick().unwrap();
+        }
+
+        let (a, b, elapsed) = crate::block_on(handle).unwrap().unwrap();
+        assert_eq!((1, 2), (a, b));
+        assert_eq!(5, elapsed, "Delays did not run concurrently");
+    }
+
+    #[test]
+    fn try_join_all_ok() {
+        init_test();
+
+        let result =
+            crate::block_on(
+                async move { crate::try_join!(async { Ok::<_, ()>(1) }, async { Ok(2) }) },
+            )
+            .unwrap();
+
+        assert_eq!(Ok((1, 2)), result);
+    }
+
+    #[test]
+    fn try_join_short_circuits() {
+        init_test();
+
+        let handle = spawn(async move {
+            crate::try_join!(
+                async {
+                    delay(10).await;
+                    Ok(1)
+                },
+                async {
+                    delay(1).await;
+                    Err::<u32, _>("failed")
+                },
+            )
+        });
+
+        while !handle.is_finished() {
+            tick().unwrap();
+        }
+
+        let result = crate::block_on(handle).unwrap().unwrap();
+        assert_eq!(Err("failed"), result);
+        assert!(
+            crate::utils::game_time() < 10,
+            "try_join! did not short circuit"
+        );
+        assert!(!timers_registered(), "Remaining future was not dropped");
+    }
+}
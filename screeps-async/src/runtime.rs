@@ -1,16 +1,22 @@
 //! The Screeps Async runtime
 
-use crate::error::RuntimeError;
-use crate::job::JobHandle;
-use crate::utils::{game_time, time_used};
+use crate::error::Elapsed;
+use crate::error::{JoinError, RuntimeError, RuntimeExistsError, SpawnError};
+use crate::job::{Abortable, CatchPanic, JoinHandle};
+use crate::time::{Deadline, Delay, Tick, TimerRegistration};
 use crate::CURRENT;
-use async_task::Runnable;
-use std::cell::RefCell;
-use std::collections::BTreeMap;
-use std::future::Future;
-use std::rc::Rc;
-use std::sync::Mutex;
-use std::task::Waker;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
+use std::future::{Future, IntoFuture};
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::{pin, Pin};
+use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::task::{Context, Poll, Wake, Waker};
+
+pub use async_task::{Runnable, Task};
 
 /// Builder to construct a [ScreepsRuntime]
 pub struct Builder {
@@ -26,16 +32,365 @@ impl Builder {
     }
 
     /// Set what percentage of available CPU time the runtime should use per tick
+    ///
+    /// This overrides any function set with [tick_time_allocation_fn](Self::tick_time_allocation_fn)
     pub fn tick_time_allocation(mut self, dur: f64) -> Self {
-        self.config.tick_time_allocation = dur;
+        self.config.tick_time_allocation = RefCell::new(Box::new(move |_, _| dur));
+        self
+    }
+
+    /// Never use more than `cpu` CPU time per tick, whatever the
+    /// [tick time allocation](Self::tick_time_allocation) works out to.
+    ///
+    /// The allocation is a fraction of the tick limit (`screeps::game::cpu::tick_limit`), so the
+    /// CPU it allows changes along with the limit. This sets an absolute ceiling on top: each
+    /// [run](ScreepsRuntime::run) polls tasks while the CPU used is below both the allocation and
+    /// `cpu`. [run_entrypoint](ScreepsRuntime::run_entrypoint) ignores the cap, like it ignores
+    /// the allocation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cpu` isn't positive
+    pub fn tick_time_cpu(mut self, cpu: f64) -> Self {
+        assert!(cpu > 0.0, "Tick CPU cap must be positive, got {cpu}");
+        self.config.tick_time_cpu = Some(cpu);
+        self
+    }
+
+    /// Set a function that computes what percentage of available CPU time the runtime should use
+    /// each tick, given the current CPU bucket and [tick limit](screeps::game::cpu::tick_limit).
+    ///
+    /// The function is evaluated at the start of each call to [run](ScreepsRuntime::run) and
+    /// [block_on](ScreepsRuntime::block_on).
+    /// Defaults to [default_tick_time_allocation]
+    ///
+    /// # Examples
+    /// ```
+    /// # use screeps_async::runtime::Builder;
+    /// // Only use half the tick until we have banked some CPU
+    /// Builder::new()
+    ///     .tick_time_allocation_fn(|bucket, _tick_limit| if bucket < 5000 { 0.5 } else { 0.9 })
+    ///     .apply();
+    /// ```
+    pub fn tick_time_allocation_fn<F>(mut self, f: F) -> Self
+    where
+        F: Fn(i32, f64) -> f64 + 'static,
+    {
+        self.config.tick_time_allocation = RefCell::new(Box::new(f));
+        self
+    }
+
+    /// Set the minimum CPU bucket required for [run](ScreepsRuntime::run) to poll any tasks
+    ///
+    /// See [ScreepsRuntime::run] for details
+    pub fn min_bucket(mut self, min_bucket: i32) -> Self {
+        self.config.min_bucket = min_bucket;
+        self
+    }
+
+    /// Set how much of the [tick time allocation](Self::tick_time_allocation) must be left for
+    /// [maybe_yield](crate::maybe_yield) to resolve without yielding.
+    ///
+    /// `threshold` is a fraction of the allocation, in the same units as
+    /// [time_remaining](ScreepsRuntime::time_remaining). Defaults to `0.1`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threshold` is not between `0.0` and `1.0`
+    pub fn maybe_yield_threshold(mut self, threshold: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&threshold),
+            "maybe_yield threshold must be in [0.0, 1.0], got {threshold}"
+        );
+        self.config.maybe_yield_threshold = threshold;
+        self
+    }
+
+    /// Set a handler to call with the payload of any panic that occurs while polling a task
+    ///
+    /// The panicking task is dropped and the runtime continues polling the remaining tasks.
+    /// By default, the payload is simply dropped. The handler is only used with
+    /// [PanicPolicy::Continue], see [panic_policy](Self::panic_policy).
+    ///
+    /// Tasks whose [JoinHandle] is still around don't go through the handler: awaiting the
+    /// handle resolves to [JoinError::Panicked] with the payload instead.
+    ///
+    /// Note: catching panics requires unwinding support. On targets that abort on panic
+    /// (like `wasm32-unknown-unknown` by default), the handler will never be called.
+    ///
+    /// # Examples
+    /// ```
+    /// # use screeps_async::runtime::Builder;
+    /// Builder::new()
+    ///     .on_task_panic(|payload| {
+    ///         let msg = payload
+    ///             .downcast_ref::<&str>()
+    ///             .map(ToString::to_string)
+    ///             .or_else(|| payload.downcast_ref::<String>().cloned());
+    ///         println!("Task panicked: {msg:?}");
+    ///     })
+    ///     .apply();
+    /// ```
+    pub fn on_task_panic<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(Box<dyn Any + Send>) + 'static,
+    {
+        self.config.on_task_panic = Box::new(handler);
+        self
+    }
+
+    /// Set what happens when a detached task panics. Defaults to [PanicPolicy::Continue], which
+    /// passes the panic to the [panic handler](Self::on_task_panic) and carries on with the tick.
+    ///
+    /// [PanicPolicy::Abort] is handy in tests, where a panicking task should fail the test.
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Self {
+        self.config.panic_policy = policy;
+        self
+    }
+
+    /// Limit the number of tasks that may be scheduled at once, for each [Priority].
+    ///
+    /// By default there is no limit. Setting a limit gives you a safety valve against runaway task
+    /// creation or wake storms, at the cost of cancelling tasks when the limit is hit:
+    /// if a task is spawned or woken while its priority's queue is full, the task is **cancelled**
+    /// and the handler set by [on_schedule_overflow](Self::on_schedule_overflow) is called.
+    pub fn max_scheduled(mut self, max: usize) -> Self {
+        self.config.max_scheduled = Some(max);
+        self
+    }
+
+    /// Set a handler to call with the task's [Priority] whenever a task is cancelled
+    /// because too many tasks were scheduled. See [max_scheduled](Self::max_scheduled)
+    ///
+    /// Since wakers may be sent to other threads, the handler must be [Send] and [Sync]
+    pub fn on_schedule_overflow<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(Priority) + Send + Sync + 'static,
+    {
+        self.config.on_schedule_overflow = Arc::new(handler);
+        self
+    }
+
+    /// Limit the number of tasks [run](ScreepsRuntime::run) may poll each tick
+    ///
+    /// This is enforced in addition to the [tick time allocation](Self::tick_time_allocation),
+    /// which can be too coarse to catch a burst of many cheap tasks.
+    /// Any tasks that aren't polled stay scheduled for the next tick.
+    pub fn max_polls_per_tick(mut self, max: usize) -> Self {
+        self.config.max_polls_per_tick = Some(max);
+        self
+    }
+
+    /// Set the [Scheduler] that decides the order tasks are polled in.
+    ///
+    /// `new_scheduler` is called to create the run queue of each [Priority] of each
+    /// [task group](Handle::group), so priorities and group weights still apply across queues.
+    /// Defaults to [FifoScheduler], which polls tasks in the order they were woken.
+    ///
+    /// # Examples
+    /// ```
+    /// # use screeps_async::runtime::{Builder, Runnable, Scheduler};
+    /// /// Polls the most recently woken task first
+    /// #[derive(Default)]
+    /// struct LifoScheduler(Vec<Runnable>);
+    ///
+    /// impl Scheduler for LifoScheduler {
+    ///     fn push(&mut self, runnable: Runnable) {
+    ///         self.0.push(runnable);
+    ///     }
+    ///
+    ///     fn next(&mut self) -> Option<Runnable> {
+    ///         self.0.pop()
+    ///     }
+    ///
+    ///     fn len(&self) -> usize {
+    ///         self.0.len()
+    ///     }
+    /// }
+    ///
+    /// Builder::new().scheduler(LifoScheduler::default).apply();
+    /// ```
+    pub fn scheduler<S, F>(mut self, new_scheduler: F) -> Self
+    where
+        S: Scheduler + 'static,
+        F: Fn() -> S + 'static,
+    {
+        self.config.new_scheduler = Rc::new(move || Box::new(new_scheduler()));
+        self
+    }
+
+    /// Set the [ClockSource] the runtime reads the game time and CPU usage from.
+    ///
+    /// Defaults to [GameClock], which reads them from the game. Useful for running bot logic in
+    /// a simulator, or for scripting the passage of time in tests.
+    pub fn clock(mut self, clock: impl ClockSource + 'static) -> Self {
+        self.config.clock = Rc::new(clock);
+        self
+    }
+
+    /// Set a hook to call at the end of every [run](ScreepsRuntime::run), once the runtime has
+    /// finished polling tasks for the tick.
+    ///
+    /// The hook is called exactly once per [run](ScreepsRuntime::run), even if the run stopped
+    /// early because the [tick time allocation](Self::tick_time_allocation) was used up or the
+    /// bucket was below [min_bucket](Self::min_bucket). Tasks that weren't polled are still
+    /// scheduled when the hook is called, and any tasks the hook spawns or wakes are polled on
+    /// the next run. Useful for flushing work that tasks have buffered up during the tick.
+    pub fn on_tick_end<F>(mut self, hook: F) -> Self
+    where
+        F: FnMut() + 'static,
+    {
+        self.config.on_tick_end = RefCell::new(Box::new(hook));
+        self
+    }
+
+    /// Call `handler` whenever polling a single task takes more than `fraction` of the tick limit.
+    ///
+    /// `handler` is called with the name of the task, if it was spawned with
+    /// [spawn_named](ScreepsRuntime::spawn_named), and the fraction of the tick limit it used.
+    /// This is purely diagnostic: a task that never yields can't be interrupted, but the handler
+    /// helps track down which task it was.
+    ///
+    /// # Examples
+    /// ```
+    /// # use screeps_async::runtime::Builder;
+    /// Builder::new()
+    ///     .poll_watchdog(0.2, |name, used| {
+    ///         println!("Task {name:?} used {:.0}% of the tick", used * 100.0);
+    ///     })
+    ///     .apply();
+    /// ```
+    pub fn poll_watchdog<F>(mut self, fraction: f64, handler: F) -> Self
+    where
+        F: Fn(Option<&str>, f64) + 'static,
+    {
+        self.config.poll_watchdog = Some((fraction, Box::new(handler)));
+        self
+    }
+
+    /// Call `hook` after every time a task is polled, with a [PollEvent] describing the poll.
+    ///
+    /// Unlike the [poll watchdog](Self::poll_watchdog), this sees every poll, so it can be used to
+    /// build a profiler that adds up the CPU used by each [named task](ScreepsRuntime::spawn_named)
+    /// or subsystem. When no hook is set, the runtime doesn't measure polls at all.
+    ///
+    /// # Examples
+    /// ```
+    /// # use screeps_async::runtime::Builder;
+    /// # use std::collections::HashMap;
+    /// let mut cpu_by_task = HashMap::<String, f64>::new();
+    /// Builder::new()
+    ///     .on_poll(move |event| {
+    ///         let name = event.name.unwrap_or("unnamed").to_string();
+    ///         *cpu_by_task.entry(name).or_default() += event.used_after - event.used_before;
+    ///     })
+    ///     .apply();
+    /// ```
+    pub fn on_poll<F>(mut self, hook: F) -> Self
+    where
+        F: FnMut(PollEvent<'_>) + 'static,
+    {
+        self.config.on_poll = Some(RefCell::new(Box::new(hook)));
+        self
+    }
+
+    /// Stop polling for the tick early if the next poll is expected to go over the
+    /// [tick time allocation](Self::tick_time_allocation), rather than only once it already has.
+    ///
+    /// The cost of the next poll is estimated as a running average of the CPU used by recent
+    /// polls, where `smoothing` is how much weight the latest poll gets. A `smoothing` of `1.0`
+    /// just uses the cost of the previous poll, while smaller values even out spikes. Without this,
+    /// a single expensive poll started just under the allocation can overshoot it by its full cost.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `smoothing` isn't in `(0.0, 1.0]`
+    pub fn poll_reserve(mut self, smoothing: f64) -> Self {
+        assert!(
+            smoothing > 0.0 && smoothing <= 1.0,
+            "poll_reserve smoothing must be in (0.0, 1.0], got {smoothing}"
+        );
+        self.config.poll_reserve = Some(smoothing);
         self
     }
 
-    /// Build a [ScreepsRuntime]
+    /// Seed the runtime's random number generator, which is used wherever the runtime or its
+    /// combinators would otherwise pick arbitrarily, e.g. which branch of an unbiased
+    /// [select!](crate::select) is polled first.
+    ///
+    /// Two runs with the same seed and the same inputs schedule everything in the same order,
+    /// which makes simulations reproducible. Defaults to `0`, so scheduling is deterministic even
+    /// if no seed is set. Anything whose order is already well defined, like the FIFO run queues,
+    /// isn't affected.
+    pub fn rng_seed(mut self, seed: u64) -> Self {
+        self.config.rng_seed = seed;
+        self
+    }
+
+    /// Pre-allocate room for `capacity` timers in the list of timers for each game tick.
+    ///
+    /// The runtime keeps the lists of ticks whose timers have fired for reuse, so once it has
+    /// warmed up, registering timers allocates nothing as long as no more than `capacity` timers
+    /// share a tick. Set this to roughly the number of timers that usually fire on the same tick.
+    /// Defaults to `0`, which leaves the lists to grow as needed.
+    pub fn timer_bucket_capacity(mut self, capacity: usize) -> Self {
+        self.config.timer_bucket_capacity = capacity;
+        self
+    }
+
+    /// Build a [ScreepsRuntime] and set it as the [current runtime](crate::CURRENT),
+    /// replacing (and dropping) any existing current runtime
     pub fn apply(self) {
-        CURRENT.with_borrow_mut(|runtime| {
-            *runtime = Some(ScreepsRuntime::new(self.config));
-        })
+        let old = CURRENT.with_borrow_mut(Option::take);
+        // Drop outside the borrow since cancelled tasks may try to use the runtime
+        drop(old);
+
+        let runtime = self.build();
+        CURRENT.with_borrow_mut(|current| *current = Some(runtime));
+    }
+
+    /// Replace the [current runtime](crate::CURRENT) with one built from this builder, cancelling
+    /// every task of the old runtime first. Use this instead of [apply](Self::apply) to start over,
+    /// e.g. after a global reset.
+    ///
+    /// The old runtime stays current while its tasks are cancelled, so code run as they are
+    /// dropped can still use it. Tasks it had scheduled or waiting on timers are dropped right
+    /// away. Tasks waiting on anything else, like a [sync](crate::sync) primitive stored outside
+    /// the runtime, keep wakers that point at the old runtime: they are cancelled the next time
+    /// they are woken, and never run on the new runtime. Tasks spawned afterwards always go to the
+    /// new runtime.
+    ///
+    /// If there is no current runtime, this is the same as [apply](Self::apply).
+    pub fn reset(self) {
+        CURRENT.with_borrow(|current| {
+            if let Some(runtime) = current {
+                runtime.shutdown.set(Shutdown::Closed);
+                runtime.cancel_remaining();
+            }
+        });
+        self.apply();
+    }
+
+    /// Build a [ScreepsRuntime] without setting it as the [current runtime](crate::CURRENT)
+    ///
+    /// # Panics
+    ///
+    /// Panics if another [ScreepsRuntime] already exists on this thread.
+    /// See [try_build](Self::try_build) for a non-panicking version
+    pub fn build(self) -> ScreepsRuntime {
+        ScreepsRuntime::new(self.config)
+    }
+
+    /// Build a [ScreepsRuntime] without setting it as the [current runtime](crate::CURRENT)
+    ///
+    /// Returns [RuntimeExistsError] if another [ScreepsRuntime] already exists on this thread
+    pub fn try_build(self) -> Result<ScreepsRuntime, RuntimeExistsError> {
+        ScreepsRuntime::try_new(self.config)
+    }
+
+    /// Build an isolated [ScreepsRuntime]. See [ScreepsRuntime::new_isolated]
+    pub fn build_isolated(self) -> ScreepsRuntime {
+        ScreepsRuntime::new_isolated(self.config)
     }
 }
 
@@ -47,98 +402,486 @@ impl Default for Builder {
 
 /// Configuration options for the [ScreepsRuntime]
 pub struct Config {
-    /// Percentage of per-tick CPU time allowed to be used by the async runtime
+    /// Computes the percentage of per-tick CPU time allowed to be used by the async runtime
+    /// from the current CPU bucket and tick limit
     ///
     /// Specifically, the runtime will continue polling new futures as long as
     /// `[screeps::game::cpu::get_used] < tick_time_allocation * [screeps::game::cpu::tick_limit]`
-    tick_time_allocation: f64,
+    ///
+    /// In a [RefCell] so it can be changed with [ScreepsRuntime::set_tick_time_allocation]
+    tick_time_allocation: RefCell<Box<dyn Fn(i32, f64) -> f64>>,
+    /// Absolute cap on the CPU time used per tick, on top of the allocation
+    tick_time_cpu: Option<f64>,
+    /// If the CPU bucket is below this value, [run](ScreepsRuntime::run) won't poll any tasks
+    min_bucket: i32,
+    /// [maybe_yield](crate::maybe_yield) yields once [ScreepsRuntime::time_remaining] is at or
+    /// below this value
+    maybe_yield_threshold: f64,
+    /// Called with the payload of any panic caught while polling a task
+    on_task_panic: Box<dyn Fn(Box<dyn Any + Send>)>,
+    /// Whether to keep going after a task panics
+    panic_policy: PanicPolicy,
+    /// Maximum number of tasks to poll in a single [run](ScreepsRuntime::run)
+    max_polls_per_tick: Option<usize>,
+    /// Maximum number of scheduled tasks per [Priority], or [None] for no limit
+    max_scheduled: Option<usize>,
+    /// Called when a task is cancelled because its scheduled queue was full
+    on_schedule_overflow: Arc<dyn Fn(Priority) + Send + Sync>,
+    /// Creates the [Scheduler] of each run queue
+    new_scheduler: Rc<dyn Fn() -> Box<dyn Scheduler>>,
+    /// Where the game time and CPU usage are read from
+    clock: Rc<dyn ClockSource>,
+    /// Called at the end of every [run](ScreepsRuntime::run)
+    on_tick_end: RefCell<Box<dyn FnMut()>>,
+    /// Called when a single poll uses more than the given fraction of the tick limit
+    poll_watchdog: Option<(f64, WatchdogHandler)>,
+    /// Smoothing of the running average of poll costs, if polls should stop once the next one is
+    /// expected to go over the allocation
+    poll_reserve: Option<f64>,
+    /// Called after every poll of a task
+    on_poll: Option<RefCell<PollHook>>,
+    /// Initial state of [ScreepsRuntime::rng]
+    rng_seed: u64,
+    /// Initial capacity of the timer list for each tick
+    timer_bucket_capacity: usize,
 }
 
+/// See [Builder::poll_watchdog]
+type WatchdogHandler = Box<dyn Fn(Option<&str>, f64)>;
+
+/// See [Builder::on_poll]
+type PollHook = Box<dyn FnMut(PollEvent<'_>)>;
+
 impl Default for Config {
     fn default() -> Self {
         Self {
-            tick_time_allocation: 0.9,
+            tick_time_allocation: RefCell::new(Box::new(default_tick_time_allocation)),
+            tick_time_cpu: None,
+            min_bucket: 0,
+            maybe_yield_threshold: 0.1,
+            on_task_panic: Box::new(drop),
+            panic_policy: PanicPolicy::default(),
+            max_polls_per_tick: None,
+            max_scheduled: None,
+            on_schedule_overflow: Arc::new(|_| {}),
+            new_scheduler: Rc::new(|| Box::new(FifoScheduler::default())),
+            clock: Rc::new(GameClock),
+            on_tick_end: RefCell::new(Box::new(|| {})),
+            poll_watchdog: None,
+            poll_reserve: None,
+            on_poll: None,
+            rng_seed: 0,
+            timer_bucket_capacity: 0,
         }
     }
 }
 
-/// A very basic futures executor based on a channel. When tasks are woken, they
-/// are scheduled by queuing them in the send half of the channel. The executor
-/// waits on the receive half and executes received tasks.
+/// Where the runtime reads the game time and CPU usage from. See [Builder::clock]
+///
+/// The runtime consults its clock whenever it needs to know the time, e.g. to fire timers or to
+/// check whether there is time left in the tick. Values only need to change between calls to
+/// [run](ScreepsRuntime::run) to simulate ticks passing, but may also change during a
+/// [run](ScreepsRuntime::run) to simulate CPU being used.
+pub trait ClockSource {
+    /// The current game tick
+    fn game_time(&self) -> u32;
+
+    /// The fraction of the [tick limit](Self::tick_limit) used so far this tick
+    fn cpu_used_fraction(&self) -> f64;
+
+    /// The current CPU bucket
+    fn bucket(&self) -> i32;
+
+    /// The CPU time available this tick
+    fn tick_limit(&self) -> f64;
+}
+
+/// The default [ClockSource], which reads from `screeps::game`.
+///
+/// When the [testing](crate::testing) module is enabled, reads the mocked game state instead
+#[derive(Debug, Default, Copy, Clone)]
+pub struct GameClock;
+
+impl ClockSource for GameClock {
+    fn game_time(&self) -> u32 {
+        crate::utils::game_time()
+    }
+
+    fn cpu_used_fraction(&self) -> f64 {
+        crate::utils::time_used()
+    }
+
+    fn bucket(&self) -> i32 {
+        crate::utils::bucket()
+    }
+
+    fn tick_limit(&self) -> f64 {
+        crate::utils::tick_limit()
+    }
+}
+
+/// The default tick time allocation. See [Builder::tick_time_allocation_fn]
+///
+/// Uses 90% of the tick while the bucket is healthy, backing off as the bucket empties so that
+/// quiet ticks can bank CPU:
+///
+/// | Bucket      | Allocation |
+/// |-------------|------------|
+/// | < 1000      | 50%        |
+/// | 1000 - 4999 | 75%        |
+/// | >= 5000     | 90%        |
+pub fn default_tick_time_allocation(bucket: i32, _tick_limit: f64) -> f64 {
+    match bucket {
+        ..1000 => 0.5,
+        1000..5000 => 0.75,
+        _ => 0.9,
+    }
+}
+
+/// The priority of a spawned task
+///
+/// Each time the runtime looks for a task to poll, it picks from the highest priority tasks that
+/// are ready. Thus, when there isn't enough time in the tick to poll every task, lower priority
+/// tasks are the ones left waiting until next tick.
+///
+/// A task keeps the priority it was spawned with for its whole life.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Priority {
+    /// Work that can wait, e.g. long-running planning
+    Low,
+    /// The priority of tasks created with [spawn](ScreepsRuntime::spawn)
+    #[default]
+    Normal,
+    /// Critical work that should run first, e.g. defense
+    High,
+}
+
+impl Priority {
+    /// All priorities, from highest to lowest
+    const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// How many rounds of scheduled tasks [ScreepsRuntime::shutdown] polls before cancelling the rest
+const SHUTDOWN_POLL_ROUNDS: usize = 16;
+
+/// A very basic futures executor based on channels. When tasks are woken, they
+/// are scheduled by queuing them in the send half of the channel for their [Priority] and
+/// [task group](Handle::group). The executor waits on the receive halves and executes received
+/// tasks, highest priority first.
 ///
 /// When a task is executed, the send half of the channel is passed along via
 /// the task's Waker.
+///
+/// Wakeups are coalesced: a task is queued at most once no matter how many times its waker is
+/// called before it is next polled, so a chatty waker can't make the runtime poll a task (and
+/// spend the tick's budget on it) more than once per wake.
+///
+/// Dropping the runtime doesn't immediately drop tasks that are parked, but waking one afterwards
+/// (e.g. from a channel held elsewhere) is safe: the task is cancelled rather than scheduled.
 pub struct ScreepsRuntime {
-    /// Receives scheduled tasks. When a task is scheduled, the associated future
-    /// is ready to make progress. This usually happens when a resource the task
-    /// uses becomes ready to perform an operation.
-    scheduled: flume::Receiver<Runnable>,
+    /// Run queues of each task group. The first group is the default group that tasks are spawned
+    /// into unless they are spawned via a [group](Handle::group) handle.
+    groups: Rc<RefCell<Vec<TaskGroup>>>,
 
-    /// Send half of the scheduled channel.
-    sender: flume::Sender<Runnable>,
+    /// Handle used to spawn tasks onto this runtime
+    handle: Handle,
 
     /// Stores [`Waker`]s used to wake tasks that are waiting for a specific game tick
     // TODO should this really be pub(crate)?
-    pub(crate) timers: Rc<Mutex<TimerMap>>,
+    pub(crate) timers: Rc<RefCell<TimerMap>>,
 
     /// Config for the runtime
     config: Config,
 
+    /// Percentage of the tick time we may use, computed at the start of each [run](Self::run)
+    tick_time_allocation: Cell<f64>,
+
+    /// Running average of the fraction of the tick limit used by each poll.
+    /// Only tracked with [Builder::poll_reserve]
+    poll_cost: Cell<f64>,
+
     /// Mutex used to ensure you don't block_on multiple futures simultaneously
     is_blocking: Mutex<()>,
+
+    /// Set while [run_blocking](Self::run_blocking) or [run_entrypoint](Self::run_entrypoint)
+    /// is polling its future. Shared with [Handle]s
+    fast_forward: Rc<Cell<bool>>,
+
+    /// Set while a task is being polled
+    in_task: Cell<bool>,
+
+    /// Set while [run](Self::run) is in progress, including its [on_tick_end](Builder::on_tick_end)
+    /// hook
+    running: Cell<bool>,
+
+    /// Whether [pause](Self::pause) has been called without a matching [resume](Self::resume)
+    paused: Cell<bool>,
+
+    /// State of the random number generator. See [Builder::rng_seed]
+    rng: Cell<u64>,
+
+    /// Whether [shutdown](Self::shutdown) or [shutdown_timeout](Self::shutdown_timeout) has been called
+    shutdown: Rc<Cell<Shutdown>>,
+
+    /// Whether this runtime was created with [new_isolated](Self::new_isolated), so doesn't
+    /// count towards the one runtime per thread
+    isolated: bool,
+
+    /// Counters behind [metrics](Self::metrics). Shared with [Handle]s
+    metrics: Rc<MetricCounters>,
+}
+
+impl Drop for ScreepsRuntime {
+    fn drop(&mut self) {
+        if !self.isolated {
+            RUNTIME_EXISTS.set(false);
+        }
+    }
+}
+
+thread_local! {
+    /// Name of the most recently polled [named task](Handle::spawn_named), for the
+    /// [poll watchdog](Builder::poll_watchdog)
+    static POLLED_TASK_NAME: RefCell<Option<Rc<str>>> = const { RefCell::new(None) };
+
+    /// Whether the most recently polled task completed, for the [poll hook](Builder::on_poll)
+    static POLL_COMPLETED: Cell<bool> = const { Cell::new(false) };
+
+    /// Whether a [ScreepsRuntime] currently exists on this thread
+    static RUNTIME_EXISTS: Cell<bool> = const { Cell::new(false) };
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Shutdown {
+    Running,
+    /// No new tasks are scheduled, and existing tasks are cancelled after `deadline` (if any)
+    Closing {
+        deadline: Option<u32>,
+    },
+    /// All tasks have been cancelled. Any tasks woken later are cancelled right away
+    Closed,
 }
 
 impl ScreepsRuntime {
     /// Initialize a new runtime instance.
     ///
-    /// Only one ScreepsRuntime may exist. Attempting to create a second one before the first is
-    /// dropped with panic
-    pub(crate) fn new(config: Config) -> Self {
-        let (sender, scheduled) = flume::unbounded();
+    /// Only one ScreepsRuntime may exist per thread. Attempting to create a second one before the
+    /// first is dropped will panic. See [try_new](Self::try_new) for a non-panicking version.
+    /// [Isolated](Self::new_isolated) runtimes don't count towards this limit
+    pub fn new(config: Config) -> Self {
+        Self::try_new(config).expect("Cannot create a second ScreepsRuntime on this thread")
+    }
+
+    /// Initialize a new runtime instance.
+    ///
+    /// Returns [RuntimeExistsError] if another ScreepsRuntime already exists on this thread
+    pub fn try_new(config: Config) -> Result<Self, RuntimeExistsError> {
+        if RUNTIME_EXISTS.replace(true) {
+            return Err(RuntimeExistsError);
+        }
+
+        Ok(Self::with_config(config, false))
+    }
+
+    /// Initialize a runtime that is independent of the [current runtime](crate::CURRENT).
+    ///
+    /// Isolated runtimes don't count towards the one runtime per thread, so any number of them
+    /// can exist alongside the current runtime, e.g. to run several simulations side by side.
+    /// Each has its own tasks, timers and [clock](Builder::clock). Since an isolated runtime is
+    /// never the current runtime, tasks must be spawned onto it with its [Handle], and tasks
+    /// running on it must use the [Handle]'s [delay](Handle::delay) and
+    /// [timeout](Handle::timeout) rather than the free functions in [time](crate::time), which
+    /// use the current runtime. Channels and the other [sync](crate::sync) primitives don't
+    /// depend on a runtime, so work the same on any runtime.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use screeps_async::runtime::Builder;
+    /// let sim = Builder::new().build_isolated();
+    /// let handle = sim.handle();
+    /// let task = sim.handle().spawn(async move {
+    ///     handle.delay(0).await;
+    /// }).unwrap();
+    ///
+    /// sim.run().unwrap();
+    /// assert!(task.is_finished());
+    /// ```
+    pub fn new_isolated(config: Config) -> Self {
+        Self::with_config(config, true)
+    }
 
-        let timers = Rc::new(Mutex::new(BTreeMap::new()));
+    fn with_config(config: Config, isolated: bool) -> Self {
+        let (senders, default_group) = TaskGroup::new(1, &*config.new_scheduler);
+        let groups = Rc::new(RefCell::new(vec![default_group]));
+
+        let timers = Rc::new(RefCell::new(TimerMap::new(config.timer_bucket_capacity)));
+        let shutdown = Rc::new(Cell::new(Shutdown::Running));
+        let next_timer_id = Rc::new(Cell::new(0));
+        let fast_forward = Rc::new(Cell::new(false));
+        let metrics = Rc::new(MetricCounters::default());
+        let rng = Cell::new(config.rng_seed);
+
+        let handle = Handle {
+            senders,
+            groups: Rc::downgrade(&groups),
+            max_scheduled: config.max_scheduled,
+            timers: Rc::downgrade(&timers),
+            shutdown: shutdown.clone(),
+            on_overflow: config.on_schedule_overflow.clone(),
+            new_scheduler: config.new_scheduler.clone(),
+            next_timer_id,
+            fast_forward: fast_forward.clone(),
+            clock: config.clock.clone(),
+            metrics: metrics.clone(),
+        };
 
         Self {
-            scheduled,
-            sender,
+            groups,
+            handle,
             timers,
             config,
+            tick_time_allocation: Cell::new(0.0),
+            poll_cost: Cell::new(0.0),
             is_blocking: Mutex::new(()),
+            fast_forward,
+            in_task: Cell::new(false),
+            running: Cell::new(false),
+            paused: Cell::new(false),
+            rng,
+            shutdown,
+            isolated,
+            metrics,
         }
     }
 
+    /// Get a [Handle] that can be used to spawn tasks onto this runtime
+    pub fn handle(&self) -> Handle {
+        self.handle.clone()
+    }
+
     /// Spawn a new async task that will be polled next time the scheduler runs
-    pub fn spawn<F>(&self, future: F) -> JobHandle<F::Output>
+    ///
+    /// Screeps is single-threaded, so tasks never leave the thread they were spawned on and
+    /// `future` doesn't need to be [Send]. It is free to hold [Rc]s and [RefCell] borrows across
+    /// `.await`s.
+    ///
+    /// If the runtime is [shutting down](Self::shutdown), the task is dropped without ever being
+    /// polled and awaiting the returned [JoinHandle] resolves to
+    /// [JoinError::Cancelled](crate::error::JoinError::Cancelled).
+    ///
+    /// Anything that implements [IntoFuture] can be spawned, it is converted into a future right
+    /// away.
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: IntoFuture,
+        F::IntoFuture: 'static,
+    {
+        self.spawn_with_priority(Priority::default(), future.into_future())
+    }
+
+    /// Spawn a new async task, or return [SpawnError::ShutDown] if the runtime is
+    /// [shutting down](Self::shutdown) rather than returning an already cancelled [JoinHandle]
+    pub fn try_spawn<F>(&self, future: F) -> Result<JoinHandle<F::Output>, SpawnError>
+    where
+        F: IntoFuture,
+        F::IntoFuture: 'static,
+    {
+        if self.shutdown.get() != Shutdown::Running {
+            return Err(SpawnError::ShutDown);
+        }
+
+        Ok(self.spawn(future))
+    }
+
+    /// Spawn a new async task with the given [Priority]
+    ///
+    /// Higher priority tasks are always polled before lower priority tasks
+    pub fn spawn_with_priority<F>(&self, priority: Priority, future: F) -> JoinHandle<F::Output>
     where
         F: Future + 'static,
     {
-        let fut_res = Rc::new(RefCell::new(None));
+        self.handle
+            .spawn_with_priority(priority, future)
+            .expect("Runtime is still alive")
+    }
 
-        let future = {
-            let fut_res = fut_res.clone();
-            async move {
-                let res = future.await;
-                let mut fut_res = fut_res.borrow_mut();
-                *fut_res = Some(res);
-            }
-        };
+    /// Spawn a new async task that calls `f` to create its future the first time it is polled
+    ///
+    /// Unlike [spawn](Self::spawn), any setup done by `f` is deferred until the task starts
+    /// running rather than happening right away.
+    pub fn spawn_fn<F, Fut>(&self, f: F) -> JoinHandle<Fut::Output>
+    where
+        F: FnOnce() -> Fut + 'static,
+        Fut: Future + 'static,
+    {
+        self.spawn(async move { f().await })
+    }
 
-        let sender = self.sender.clone();
-        let (runnable, task) = async_task::spawn_local(future, move |runnable| {
-            // Don't try to send if disconnected, this only happens when runtime is being dropped
-            if !sender.is_disconnected() {
-                sender.send(runnable).unwrap();
-            }
-        });
+    /// Spawn a new async task named `name`
+    ///
+    /// The name is passed to the [poll watchdog](Builder::poll_watchdog). With the `tracing`
+    /// feature enabled, a `task` span with the given name is also entered each time the task is
+    /// polled, so that logs and timings are attributed to the right task.
+    pub fn spawn_named<F>(&self, name: &str, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+    {
+        self.handle
+            .spawn_named(name, future)
+            .expect("Runtime is still alive")
+    }
+
+    /// Spawn a new async task that will first be polled `ticks` ticks from now
+    ///
+    /// This behaves like `spawn(async move { delay(ticks).await; future.await })`, except that
+    /// the task is registered with the timers directly, so it isn't polled at all until it is due
+    pub fn spawn_after<F>(&self, ticks: u32, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+    {
+        self.handle
+            .spawn_after(ticks, future)
+            .expect("Runtime is still alive")
+    }
 
-        runnable.schedule();
+    /// Spawn a new async task that may use at most `fraction` of the tick limit each tick
+    ///
+    /// The CPU used by each poll of the task is added up over the tick. Once it reaches `fraction`,
+    /// the task isn't polled again until the next tick, even if it is woken, while other tasks
+    /// carry on as normal. A single poll can't be interrupted, so the task can still go over its
+    /// budget by however much its last poll of the tick uses.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fraction` isn't in `(0.0, 1.0]`
+    pub fn spawn_with_budget<F>(&self, fraction: f64, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+    {
+        self.handle
+            .spawn_with_budget(fraction, future)
+            .expect("Runtime is still alive")
+    }
 
-        JobHandle::new(fut_res, task)
+    /// Schedule `future` and return the raw [Task]. Dropping the [Task] will cancel it
+    fn spawn_task<F>(&self, priority: Priority, future: F) -> Task<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: TaskOutcome,
+    {
+        self.handle
+            .spawn_task(priority, future)
+            .expect("Runtime is still alive")
     }
 
     /// The main entrypoint for the async runtime. Runs a future to completion.
     ///
     /// Returns [RuntimeError::DeadlockDetected] if blocking [Future] doesn't complete this tick
+    /// Returns [RuntimeError::ShutDown] if the runtime is [shutting down](Self::shutdown)
     ///
     /// # Panics
     ///
@@ -148,182 +891,3368 @@ impl ScreepsRuntime {
     where
         F: Future + 'static,
     {
-        let _guard = self
-            .is_blocking
-            .try_lock()
-            .expect("Cannot block_on multiple futures at once. Please .await on the inner future");
-        let handle = self.spawn(future);
-
-        while !handle.is_complete() {
-            if !self.try_poll_scheduled()? {
-                return Err(RuntimeError::DeadlockDetected);
-            }
-        }
-
-        Ok(handle.fut_res.take().unwrap())
+        self.block_on_task(future, false)
     }
 
-    /// Run the executor for one game tick
+    /// Run the async entrypoint of your bot for this tick. This is what
+    /// [`#[screeps_async::main]`](crate::main) uses for `async` main functions.
     ///
-    /// This should generally be the last thing you call in your loop as by default the runtime
-    /// will keep polling for work until 90% of this tick's CPU time has been exhausted.
-    /// Thus, with enough scheduled work, this function will run for AT LEAST 90% of the tick time
-    /// (90% + however long the last Future takes to poll)
-    pub fn run(&self) -> Result<(), RuntimeError> {
-        // Only need to call this once per tick since delay_ticks(0) will execute synchronously
-        self.wake_timers();
-
-        // Poll tasks until there are no more, or we get an error
-        while self.try_poll_scheduled()? {}
-
-        Ok(())
+    /// Like [block_on](Self::block_on), but makes a best effort to complete `future` within
+    /// this tick: any [delay](crate::time::delay) awaited directly by `future` is treated as
+    /// `delay(0)`, and `future` may use the entire tick rather than just the
+    /// [tick time allocation](Builder::tick_time_allocation). Tasks spawned by `future` are not
+    /// affected by this, their delays wait as normal.
+    ///
+    /// Returns [RuntimeError::DeadlockDetected] if `future` still can't complete this tick
+    /// Returns [RuntimeError::OutOfTime] if the tick runs out of CPU time first
+    /// Returns [RuntimeError::ShutDown] if the runtime is [shutting down](Self::shutdown)
+    ///
+    /// # Panics
+    ///
+    /// Panics if another future is already being blocked on
+    pub fn run_entrypoint<F>(&self, future: F) -> Result<F::Output, RuntimeError>
+    where
+        F: Future + 'static,
+    {
+        self.block_on_task(future, true)
+    }
+
+    fn block_on_task<F>(&self, future: F, entrypoint: bool) -> Result<F::Output, RuntimeError>
+    where
+        F: Future + 'static,
+    {
+        /// Fast-forwards delays registered while it is alive, restoring the previous state on drop
+        struct FastForward {
+            flag: Rc<Cell<bool>>,
+            was_enabled: bool,
+        }
+
+        impl FastForward {
+            fn enable(flag: &Rc<Cell<bool>>) -> Self {
+                Self {
+                    flag: flag.clone(),
+                    was_enabled: flag.replace(true),
+                }
+            }
+        }
+
+        impl Drop for FastForward {
+            fn drop(&mut self) {
+                self.flag.set(self.was_enabled);
+            }
+        }
+
+        let _guard = self.lock_blocking(
+            "Cannot block_on multiple futures at once. Please .await on the inner future",
+        );
+
+        if self.shutdown.get() != Shutdown::Running {
+            return Err(RuntimeError::ShutDown);
+        }
+
+        if entrypoint {
+            self.tick_time_allocation.set(1.0);
+        } else {
+            self.update_tick_time_allocation();
+        }
+
+        let fut_res = Rc::new(RefCell::new(None));
+        // Keep the task around so it gets cancelled if we return early
+        let _task = {
+            let fut_res = fut_res.clone();
+            let fast_forward = self.fast_forward.clone();
+            self.spawn_task(Priority::default(), async move {
+                let mut future = pin!(future);
+                let res = std::future::poll_fn(|cx| {
+                    let _fast_forward = entrypoint.then(|| FastForward::enable(&fast_forward));
+                    future.as_mut().poll(cx)
+                })
+                .await;
+                *fut_res.borrow_mut() = Some(res);
+            })
+        };
+
+        while fut_res.borrow().is_none() {
+            if !self.try_poll_scheduled()? {
+                // The entrypoint's own delays are due this tick, so give them a chance to fire
+                if !(entrypoint && self.wake_timers() > 0) {
+                    return Err(RuntimeError::DeadlockDetected);
+                }
+            }
+        }
+
+        Ok(fut_res.take().unwrap())
+    }
+
+    /// Drive `future` to completion right now, from synchronous code.
+    ///
+    /// Unlike [block_on](Self::block_on), `future` doesn't need to be `'static`, and any
+    /// [delay](crate::time::delay) awaited directly by `future` is treated as `delay(0)` so that
+    /// it can complete this tick. Other scheduled tasks are polled while waiting on `future`, and
+    /// the [tick time allocation](Builder::tick_time_allocation) is ignored.
+    ///
+    /// Since Screeps is single-threaded, nothing can make progress while this method runs except
+    /// `future` and the tasks it waits on. Be careful to only use this with futures you know can
+    /// complete this tick, as there is no way to interrupt a runaway future.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `future` can never complete because nothing is left that can wake it (e.g. it is
+    /// waiting on a channel that no task will send to this tick), or if another future is already
+    /// being blocked on.
+    pub fn run_blocking<F: Future>(&self, future: F) -> F::Output {
+        /// Records whether the blocked future has been woken
+        struct Flag(AtomicBool);
+
+        impl Wake for Flag {
+            fn wake(self: Arc<Self>) {
+                self.0.store(true, Ordering::Relaxed);
+            }
+        }
+
+        let _guard = self.lock_blocking(
+            "Cannot block on multiple futures at once. Please .await on the inner future",
+        );
+
+        let woken = Arc::new(Flag(AtomicBool::new(true)));
+        let waker = Waker::from(woken.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut future = pin!(future);
+
+        loop {
+            let mut progressed = false;
+
+            if woken.0.swap(false, Ordering::Relaxed) {
+                self.fast_forward.set(true);
+                let res = self.enter_task(|| future.as_mut().poll(&mut cx));
+                self.fast_forward.set(false);
+
+                if let Poll::Ready(output) = res {
+                    return output;
+                }
+                progressed = true;
+            }
+
+            // Give other tasks a turn between each poll of `future`, in case it is waiting on them
+            if let Some(runnable) = self.next_scheduled() {
+                self.run_task(runnable);
+                progressed = true;
+            }
+
+            if !progressed && self.wake_timers() == 0 {
+                panic!("run_blocking deadlocked: the future can't complete this tick");
+            }
+        }
+    }
+
+    /// Lock [Self::is_blocking], panicking with `msg` if a future is already being blocked on.
+    ///
+    /// A panic that unwound out of an earlier block leaves the lock poisoned. It doesn't guard any
+    /// data, so that is ignored rather than breaking every later block
+    fn lock_blocking(&self, msg: &str) -> MutexGuard<'_, ()> {
+        match self.is_blocking.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(err)) => err.into_inner(),
+            Err(TryLockError::WouldBlock) => panic!("{msg}"),
+        }
+    }
+
+    /// The next number from the runtime's random number generator (SplitMix64).
+    /// See [Builder::rng_seed]
+    pub(crate) fn next_random(&self) -> u64 {
+        let state = self.rng.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+        self.rng.set(state);
+
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// The [ClockSource] this runtime reads the time from. See [Builder::clock]
+    pub(crate) fn clock(&self) -> &dyn ClockSource {
+        &*self.config.clock
+    }
+
+    /// Run the executor for one game tick, returning a [TickReport] of the work done
+    ///
+    /// This should generally be the last thing you call in your loop as by default the runtime
+    /// will keep polling for work until 90% of this tick's CPU time has been exhausted.
+    /// Thus, with enough scheduled work, this function will run for AT LEAST 90% of the tick time
+    /// (90% + however long the last Future takes to poll)
+    ///
+    /// Running out of time isn't an error: tasks that didn't get polled stay scheduled for next
+    /// tick, and are counted in [TickReport::deferred]. Keep an eye on it to spot work backing up.
+    ///
+    /// If the CPU bucket is below the configured [min_bucket](Builder::min_bucket), timers are
+    /// still fired but no tasks are polled at all, regardless of their [Priority], to give the
+    /// bucket a chance to recover. Woken tasks stay scheduled and are polled (highest priority
+    /// first) on the next run where the bucket is above the minimum.
+    ///
+    /// Once polling is done, the [on_tick_end](Builder::on_tick_end) hook is called, whether or
+    /// not the budget was exhausted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the runtime is already running, since that is a bug in the caller. Use
+    /// [try_run](Self::try_run) to get [RuntimeError::AlreadyRunning] back instead. Any other
+    /// error is returned.
+    pub fn run(&self) -> Result<TickReport, RuntimeError> {
+        Self::expect_not_running(self.try_run_tick(None))
+    }
+
+    /// [Run](Self::run) the executor for one game tick, returning an error instead of panicking
+    /// if the runtime can't be run right now.
+    ///
+    /// Fails with [RuntimeError::AlreadyRunning] if called from within a task, from the
+    /// [on_tick_end](Builder::on_tick_end) hook, or while [blocking on](Self::block_on) a future.
+    /// None of these affect the runtime, so a game loop can log the error and carry on with the
+    /// next tick.
+    pub fn try_run(&self) -> Result<TickReport, RuntimeError> {
+        self.try_run_tick(None)
+    }
+
+    /// [Run](Self::run) the executor for one game tick, using `allocation` as the
+    /// [tick time allocation](Builder::tick_time_allocation) for this run only.
+    ///
+    /// The configured allocation is left untouched, so this is handy when the game loop knows
+    /// better for a single tick, e.g. to use more CPU while the bucket is full or less after a
+    /// spike.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `allocation` is not in the range `(0.0, 1.0]`, or if the runtime is already
+    /// running (see [run](Self::run))
+    pub fn run_with_allocation(&self, allocation: f64) -> Result<TickReport, RuntimeError> {
+        assert!(
+            allocation > 0.0 && allocation <= 1.0,
+            "Tick time allocation must be in (0.0, 1.0], got {allocation}"
+        );
+        Self::expect_not_running(self.try_run_tick(Some(allocation)))
+    }
+
+    /// Panic if `result` is [RuntimeError::AlreadyRunning], otherwise pass it on
+    fn expect_not_running(
+        result: Result<TickReport, RuntimeError>,
+    ) -> Result<TickReport, RuntimeError> {
+        match result {
+            Err(err @ RuntimeError::AlreadyRunning) => panic!("{err}"),
+            result => result,
+        }
+    }
+
+    /// [Try to run](Self::try_run) with the given allocation, or the configured one if [None]
+    fn try_run_tick(&self, allocation: Option<f64>) -> Result<TickReport, RuntimeError> {
+        /// Clears [ScreepsRuntime::running] when the run ends, even if a task panics through it
+        struct Running<'a>(&'a Cell<bool>);
+
+        impl Drop for Running<'_> {
+            fn drop(&mut self) {
+                self.0.set(false);
+            }
+        }
+
+        let blocking = matches!(self.is_blocking.try_lock(), Err(TryLockError::WouldBlock));
+        if self.running.get() || self.in_task.get() || blocking {
+            return Err(RuntimeError::AlreadyRunning);
+        }
+        self.running.set(true);
+        let _running = Running(&self.running);
+
+        let mut report = self.poll_tick(allocation);
+        (self.config.on_tick_end.borrow_mut())();
+
+        // Count after the hook, so that tasks it woke are included
+        report.deferred = self.pending_count();
+        Ok(report)
+    }
+
+    /// Fire timers and poll tasks for [run](Self::run)
+    fn poll_tick(&self, allocation: Option<f64>) -> TickReport {
+        let mut report = TickReport::default();
+        if self.shutdown.get() == Shutdown::Closed {
+            self.cancel_remaining();
+            return report;
+        }
+        if self.paused.get() {
+            return report;
+        }
+
+        match allocation {
+            Some(allocation) => self
+                .tick_time_allocation
+                .set(self.capped_allocation(allocation)),
+            None => self.update_tick_time_allocation(),
+        }
+
+        // Timers still fire below min_bucket so tasks will be ready once the bucket refills
+        report.timers_fired += self.wake_timers();
+
+        if self.clock().bucket() < self.config.min_bucket {
+            return report;
+        }
+
+        // Poll tasks until there are no more, or we run out of budget
+        loop {
+            if self
+                .config
+                .max_polls_per_tick
+                .is_some_and(|max| report.polled >= max)
+            {
+                // Leave the remaining tasks scheduled for next tick
+                report.budget_exhausted = true;
+                break;
+            }
+
+            match self.try_poll_scheduled() {
+                Ok(true) => report.polled += 1,
+                Ok(false) => {
+                    // Tasks polled this tick may have registered timers that are already due
+                    let fired = self.wake_timers();
+                    if fired == 0 {
+                        break;
+                    }
+                    report.timers_fired += fired;
+                }
+                Err(_) => {
+                    report.budget_exhausted = true;
+                    break;
+                }
+            }
+        }
+
+        if let Shutdown::Closing {
+            deadline: Some(deadline),
+        } = self.shutdown.get()
+        {
+            if is_due(deadline, self.clock().game_time()) {
+                self.shutdown.set(Shutdown::Closed);
+                self.cancel_remaining();
+            }
+        }
+
+        report
+    }
+
+    /// Step the runtime by a single poll, returning whether any work was done.
+    ///
+    /// Fires any timers that are due, then polls the next scheduled task (if any), picked the same
+    /// way as in [run](Self::run). The [tick time allocation](Builder::tick_time_allocation),
+    /// [min_bucket](Builder::min_bucket) and [on_tick_end](Builder::on_tick_end) hook are all
+    /// ignored. Useful for stepping through tasks one poll at a time in tests and diagnostics.
+    pub fn poll_once(&self) -> bool {
+        let fired = self.wake_timers() > 0;
+        match self.next_scheduled() {
+            Some(runnable) => {
+                self.run_task(runnable);
+                true
+            }
+            None => fired,
+        }
+    }
+
+    /// Pause the runtime, so that [run](Self::run) neither fires timers nor polls tasks until
+    /// [resume](Self::resume) is called.
+    ///
+    /// Unlike [shutdown](Self::shutdown), no work is lost: tasks can still be spawned and woken
+    /// while paused, and just stay scheduled. The [on_tick_end](Builder::on_tick_end) hook is
+    /// still called, and [poll_once](Self::poll_once) still steps the runtime, which is handy for
+    /// debugging a frozen runtime one poll at a time.
+    pub fn pause(&self) {
+        self.paused.set(true);
+    }
+
+    /// Resume a runtime that was [paused](Self::pause). Timers that came due while paused fire on
+    /// the next [run](Self::run)
+    pub fn resume(&self) {
+        self.paused.set(false);
+    }
+
+    /// Whether the runtime is [paused](Self::pause)
+    pub fn is_paused(&self) -> bool {
+        self.paused.get()
+    }
+
+    /// Shut down the runtime, polling all currently scheduled tasks until there are none left
+    /// (ignoring the [tick time allocation](Builder::tick_time_allocation)),
+    /// then cancelling any tasks that are still waiting on timers.
+    ///
+    /// Tasks that keep rescheduling themselves, like a [yield_now](crate::time::yield_now) loop,
+    /// would keep this going forever, so scheduled tasks are polled in at most 16 rounds, each
+    /// polling the tasks that were scheduled when it started. Tasks still scheduled after that are
+    /// cancelled too.
+    ///
+    /// After calling this method, newly spawned tasks are never polled (see [spawn](Self::spawn)),
+    /// and tasks waiting on anything else are cancelled the next time they are woken
+    pub fn shutdown(&self) {
+        self.shutdown.set(Shutdown::Closing { deadline: None });
+
+        for _ in 0..SHUTDOWN_POLL_ROUNDS {
+            let scheduled = self.pending_count();
+            if scheduled == 0 {
+                break;
+            }
+            for runnable in std::iter::from_fn(|| self.next_scheduled()).take(scheduled) {
+                self.run_task(runnable);
+            }
+        }
+
+        self.shutdown.set(Shutdown::Closed);
+        self.cancel_remaining();
+    }
+
+    /// Shut down the runtime, giving tasks up to `ticks` game ticks to complete.
+    ///
+    /// New tasks are not accepted (see [spawn](Self::spawn)), but [run](Self::run) continues to
+    /// poll existing tasks as normal. Once `ticks` ticks have passed, any remaining tasks are
+    /// cancelled at the end of [run](Self::run)
+    pub fn shutdown_timeout(&self, ticks: u32) {
+        let deadline = self.clock().game_time().wrapping_add(ticks);
+        self.shutdown.set(Shutdown::Closing {
+            deadline: Some(deadline),
+        });
+    }
+
+    /// Cancel all tasks waiting on timers or scheduled to be polled
+    fn cancel_remaining(&self) {
+        // Take the timers out first so no lock is held while they are woken
+        let timers = std::mem::take(&mut self.timers.borrow_mut().ticks);
+        // Waking schedules the tasks so that they can be cancelled below
+        timers
+            .into_values()
+            .flatten()
+            .for_each(|entry| entry.target.fire());
+
+        // Dropping tasks may wake other tasks, so keep going until nothing is left
+        while let Some(runnable) = self.next_scheduled() {
+            drop(runnable);
+        }
+    }
+
+    /// Attempts to poll the next scheduled task, ensuring that there is time left in the tick
+    ///
+    /// Returns [Ok(true)] if a task was successfully polled
+    /// Returns [Ok(false)] if there are no tasks ready to poll
+    /// Returns [Err] if we have run out of allocated time this tick
+    pub(crate) fn try_poll_scheduled(&self) -> Result<bool, RuntimeError> {
+        // Without a poll reserve the cost is always 0, so this is just checking the allocation
+        let expected_cost = self.poll_cost.get();
+        if self.clock().cpu_used_fraction() + expected_cost > self.tick_time_allocation.get() {
+            return Err(RuntimeError::OutOfTime);
+        }
+
+        if let Some(runnable) = self.next_scheduled() {
+            self.run_task(runnable);
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Take the highest priority task that is ready to be polled.
+    ///
+    /// Within a priority, task groups are picked by smooth weighted round-robin: each time a task
+    /// is taken, every group with a task ready gains credit equal to its weight, and the group
+    /// with the most credit is picked and pays back the total weight of the ready groups.
+    fn next_scheduled(&self) -> Option<Runnable> {
+        let mut groups = self.groups.borrow_mut();
+        // Forget groups that nothing can spawn onto or wake anymore
+        groups.retain(|group| !group.is_abandoned());
+
+        Priority::ALL.iter().find_map(|priority| {
+            let idx = priority.index();
+            let mut total_weight = 0;
+            let mut picked: Option<(usize, i64)> = None;
+            for (i, group) in groups.iter_mut().enumerate() {
+                if group.queues[idx].is_empty() {
+                    continue;
+                }
+
+                group.credit[idx] += i64::from(group.weight);
+                total_weight += i64::from(group.weight);
+                let credit = group.credit[idx];
+                if picked.is_none_or(|(_, most)| credit > most) {
+                    picked = Some((i, credit));
+                }
+            }
+
+            let (picked, _) = picked?;
+            let group = &mut groups[picked];
+            group.credit[idx] -= total_weight;
+            group.queues[idx].pop()
+        })
+    }
+
+    fn run_task(&self, runnable: Runnable) {
+        let measure = self.config.poll_watchdog.is_some()
+            || self.config.poll_reserve.is_some()
+            || self.config.on_poll.is_some();
+        let used_before = measure.then(|| {
+            POLLED_TASK_NAME.take();
+            POLL_COMPLETED.set(false);
+            self.clock().cpu_used_fraction()
+        });
+
+        // If the task panics, async_task has already closed it so it is safe to keep going
+        let res = self.enter_task(|| panic::catch_unwind(AssertUnwindSafe(|| runnable.run())));
+        MetricCounters::bump(&self.metrics.polled);
+
+        if let Some(before) = used_before {
+            let after = self.clock().cpu_used_fraction();
+            let used = after - before;
+            if let Some(smoothing) = self.config.poll_reserve {
+                let cost = self.poll_cost.get();
+                self.poll_cost.set(cost + smoothing * (used - cost));
+            }
+            if let Some((limit, handler)) = &self.config.poll_watchdog {
+                if used > *limit {
+                    let name = POLLED_TASK_NAME.with_borrow(Option::clone);
+                    handler(name.as_deref(), used);
+                }
+            }
+            if let Some(hook) = &self.config.on_poll {
+                let name = POLLED_TASK_NAME.take();
+                (hook.borrow_mut())(PollEvent {
+                    name: name.as_deref(),
+                    used_before: before,
+                    used_after: after,
+                    completed: POLL_COMPLETED.get() || res.is_err(),
+                });
+            }
+        }
+        if let Err(payload) = res {
+            MetricCounters::bump(&self.metrics.panicked);
+            // The task is already gone and nothing is borrowed, so the runtime is left in a
+            // usable state however we carry on
+            match self.config.panic_policy {
+                PanicPolicy::Continue => (self.config.on_task_panic)(payload),
+                PanicPolicy::Abort => panic::resume_unwind(payload),
+            }
+        }
+    }
+
+    /// Mark the runtime as polling a task while `f` runs
+    fn enter_task<R>(&self, f: impl FnOnce() -> R) -> R {
+        let was_in_task = self.in_task.replace(true);
+        let res = f();
+        self.in_task.set(was_in_task);
+        res
+    }
+
+    /// The current game tick.
+    ///
+    /// # Panics
+    ///
+    /// Panics if not called from within a task running on this runtime
+    pub fn current_tick(&self) -> u32 {
+        self.assert_in_task("current_tick");
+        self.clock().game_time()
+    }
+
+    /// The fraction of this tick's [time allocation](Builder::tick_time_allocation) that is
+    /// still unused, from `1.0` (none used yet) down to `0.0` (the allocation is used up).
+    ///
+    /// Useful for tasks that want to decide how much work to do before yielding.
+    ///
+    /// # Panics
+    ///
+    /// Panics if not called from within a task running on this runtime
+    pub fn time_remaining(&self) -> f64 {
+        self.assert_in_task("time_remaining");
+        let allocation = self.tick_time_allocation.get();
+        if allocation <= 0.0 {
+            return 0.0;
+        }
+
+        ((allocation - self.clock().cpu_used_fraction()) / allocation).clamp(0.0, 1.0)
+    }
+
+    /// Whether the tick budget is nearly used up. See [maybe_yield](crate::maybe_yield)
+    pub(crate) fn should_yield(&self) -> bool {
+        self.time_remaining() <= self.config.maybe_yield_threshold
+    }
+
+    fn assert_in_task(&self, method: &str) {
+        assert!(
+            self.in_task.get(),
+            "{method} must be called from within a task running on the screeps-async runtime"
+        );
+    }
+
+    /// The number of tasks currently scheduled to be polled
+    pub fn pending_count(&self) -> usize {
+        let groups = self.groups.borrow();
+        groups
+            .iter()
+            .flat_map(|group| &group.queues)
+            .map(RunQueue::len)
+            .sum()
+    }
+
+    /// The number of timers waiting to fire
+    pub fn timer_count(&self) -> usize {
+        let timers = self.timers.borrow();
+        Self::count_timers(&timers)
+    }
+
+    /// The earliest game tick that a timer is waiting for, if any
+    pub fn next_timer_tick(&self) -> Option<u32> {
+        let timers = self.timers.borrow();
+        Self::first_timer_tick(&timers, self.clock().game_time())
+    }
+
+    /// Whether [run](Self::run) has anything to do this tick: any tasks are scheduled, or any
+    /// timers are due at or before the current game tick.
+    ///
+    /// Bots that don't need to run every tick can skip calling [run](Self::run) while this is
+    /// `false`, using [next_timer_tick](Self::next_timer_tick) to know when to come back. Only
+    /// inspects the run queues and timers, so it is cheap to call every tick
+    pub fn has_work(&self) -> bool {
+        self.pending_count() > 0
+            || self
+                .next_timer_tick()
+                .is_some_and(|when| is_due(when, self.clock().game_time()))
+    }
+
+    /// Get a snapshot of [RuntimeStats] for this runtime
+    ///
+    /// Prefer this over calling [pending_count](Self::pending_count),
+    /// [timer_count](Self::timer_count) and [next_timer_tick](Self::next_timer_tick) separately
+    pub fn stats(&self) -> RuntimeStats {
+        let timers = self.timers.borrow();
+        RuntimeStats {
+            pending_count: self.pending_count(),
+            timer_count: Self::count_timers(&timers),
+            next_timer_tick: Self::first_timer_tick(&timers, self.clock().game_time()),
+        }
+    }
+
+    /// Get a snapshot of the [RuntimeMetrics] accumulated over the lifetime of this runtime
+    ///
+    /// Unlike [stats](Self::stats), these only ever go up, so the difference between two
+    /// snapshots gives the rate of each event over the ticks between them
+    pub fn metrics(&self) -> RuntimeMetrics {
+        let metrics = &self.metrics;
+        RuntimeMetrics {
+            tasks_spawned: metrics.spawned.get(),
+            tasks_completed: metrics.completed.get(),
+            tasks_aborted: metrics.aborted.get(),
+            tasks_panicked: metrics.panicked.get(),
+            timers_fired: metrics.timers_fired.get(),
+            polled: metrics.polled.get(),
+        }
+    }
+
+    fn count_timers(timers: &TimerMap) -> usize {
+        timers.ticks.values().map(Vec::len).sum()
+    }
+
+    /// The earliest tick in `timers`, counting ticks from [TIMER_HORIZON] ticks before
+    /// `game_time` so that ticks after the game time wraps around come last
+    fn first_timer_tick(timers: &TimerMap, game_time: u32) -> Option<u32> {
+        let oldest = game_time.wrapping_sub(TIMER_HORIZON);
+        timers
+            .ticks
+            .range(oldest..)
+            .next()
+            .or_else(|| timers.ticks.iter().next())
+            .map(|(when, _)| *when)
+    }
+
+    /// The percentage of available CPU time the runtime will use per tick, as configured by
+    /// [Builder::tick_time_allocation_fn] and evaluated for the current CPU bucket and tick limit.
+    /// Lowered to the [absolute CPU cap](Builder::tick_time_cpu) if that is lower
+    pub fn tick_time_allocation(&self) -> f64 {
+        let clock = self.clock();
+        let allocation =
+            (self.config.tick_time_allocation.borrow())(clock.bucket(), clock.tick_limit());
+        self.capped_allocation(allocation)
+    }
+
+    /// Lower `allocation` to the [absolute CPU cap](Builder::tick_time_cpu), if there is one
+    fn capped_allocation(&self, allocation: f64) -> f64 {
+        match self.config.tick_time_cpu {
+            Some(cpu) => allocation.min(cpu / self.clock().tick_limit()),
+            None => allocation,
+        }
+    }
+
+    /// Set what percentage of available CPU time the runtime should use per tick, replacing the
+    /// allocation set when the runtime was [built](Builder::tick_time_allocation).
+    ///
+    /// Takes effect from the next call to [run](Self::run). Useful for adjusting to the phase of
+    /// the game without having to rebuild the runtime.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `allocation` is not in the range `(0.0, 1.0]`
+    pub fn set_tick_time_allocation(&self, allocation: f64) {
+        assert!(
+            allocation > 0.0 && allocation <= 1.0,
+            "Tick time allocation must be in (0.0, 1.0], got {allocation}"
+        );
+        *self.config.tick_time_allocation.borrow_mut() = Box::new(move |_, _| allocation);
+    }
+
+    fn update_tick_time_allocation(&self) {
+        self.tick_time_allocation.set(self.tick_time_allocation());
+    }
+
+    /// Wake all timers that are due this tick in the order they were registered,
+    /// returning how many were woken
+    fn wake_timers(&self) -> usize {
+        let game_time = self.clock().game_time();
+
+        let mut fired = 0;
+        loop {
+            // Take one tick at a time, since the borrow must be released before waking in case a
+            // waker registers a new timer
+            let due = self.timers.borrow_mut().pop_due(game_time);
+            let Some(mut entries) = due else {
+                break;
+            };
+
+            fired += entries.len();
+            entries.drain(..).for_each(|entry| entry.target.fire());
+            self.timers.borrow_mut().recycle(entries);
+        }
+
+        self.metrics
+            .timers_fired
+            .set(self.metrics.timers_fired.get() + fired as u64);
+
+        fired
+    }
+}
+
+/// A handle to a [ScreepsRuntime] that can be cloned and stored, to spawn tasks without relying
+/// on the [current runtime](crate::CURRENT). Obtained via [ScreepsRuntime::handle]
+///
+/// A [Handle] also provides [delay](Handle::delay) and [timeout](Handle::timeout) tied to its
+/// runtime rather than the current one, for use with [isolated](ScreepsRuntime::new_isolated)
+/// runtimes.
+///
+/// Spawning via a [Handle] after its runtime has been dropped returns [RuntimeError::Dropped]
+#[derive(Clone)]
+pub struct Handle {
+    /// Send halves of this handle's task group's run queues, indexed by [Priority]
+    senders: [QueueSender; 3],
+    /// The runtime's task groups, so that new groups can be added
+    groups: Weak<RefCell<Vec<TaskGroup>>>,
+    /// Capacity of each run queue. See [Builder::max_scheduled]
+    max_scheduled: Option<usize>,
+    /// The runtime's timers. Weak so that a stored [Handle] doesn't keep them alive
+    timers: Weak<RefCell<TimerMap>>,
+    shutdown: Rc<Cell<Shutdown>>,
+    on_overflow: Arc<dyn Fn(Priority) + Send + Sync>,
+    /// Creates the [Scheduler] of each run queue of new task groups
+    new_scheduler: Rc<dyn Fn() -> Box<dyn Scheduler>>,
+    /// Id to assign to the next timer registered in [Self::timers]
+    next_timer_id: Rc<Cell<u64>>,
+    /// See [ScreepsRuntime::fast_forward]
+    fast_forward: Rc<Cell<bool>>,
+    clock: Rc<dyn ClockSource>,
+    metrics: Rc<MetricCounters>,
+}
+
+impl Handle {
+    /// The current game tick, according to this handle's runtime's [clock](Builder::clock)
+    pub fn game_time(&self) -> u32 {
+        self.clock.game_time()
+    }
+
+    /// Sleep until `deadline` on this handle's runtime.
+    ///
+    /// Works like [time::delay](crate::time::delay), but measures time with this runtime's
+    /// [clock](Builder::clock) and registers with this runtime, so it can be used by tasks of
+    /// [isolated](ScreepsRuntime::new_isolated) runtimes. If the runtime is dropped, the delay
+    /// resolves right away since nothing would ever fire it.
+    pub fn delay(&self, deadline: impl Into<Deadline>) -> Delay {
+        let when = deadline.into().when_from(self.game_time());
+        Delay::with_handle(self.clone(), when)
+    }
+
+    /// Sleep until game tick `when` on this handle's runtime.
+    ///
+    /// See [delay](Self::delay) and [time::delay_until](crate::time::delay_until)
+    pub fn delay_until(&self, when: impl Into<Tick>) -> Delay {
+        Delay::with_handle(self.clone(), when.into().0)
+    }
+
+    /// Wait for `fut` to complete, giving up at `deadline` on this handle's runtime.
+    ///
+    /// See [delay](Self::delay) and [time::timeout](crate::time::timeout)
+    pub async fn timeout<F: Future>(
+        &self,
+        deadline: impl Into<Deadline>,
+        fut: F,
+    ) -> Result<F::Output, Elapsed> {
+        let delay = self.delay(deadline);
+        crate::select! {
+            output = fut => Ok(output),
+            _ = delay => Err(Elapsed),
+        }
+    }
+
+    /// Register `waker` to be woken on game tick `when`, returning the tick it was actually
+    /// registered for. Returns [None] if the runtime has been dropped
+    pub(crate) fn register_timer(
+        &self,
+        when: u32,
+        waker: &Waker,
+    ) -> Option<(u32, TimerRegistration)> {
+        self.push_timer(when, TimerTarget::Waker(waker.clone()))
+    }
+
+    /// Register `target` to fire on game tick `when`. See [register_timer](Self::register_timer)
+    fn push_timer(&self, when: u32, target: TimerTarget) -> Option<(u32, TimerRegistration)> {
+        let timers = self.timers.upgrade()?;
+        // run_blocking can't wait for future ticks, so treat this like delay(0)
+        let when = if self.fast_forward.get() {
+            let now = self.game_time();
+            if is_due(when, now) {
+                when
+            } else {
+                now
+            }
+        } else {
+            when
+        };
+
+        let id = self.next_timer_id.get();
+        self.next_timer_id.set(id + 1);
+        timers.borrow_mut().push(when, TimerEntry { id, target });
+
+        let timer = TimerRegistration {
+            id,
+            timers: self.timers.clone(),
+        };
+        Some((when, timer))
+    }
+
+    /// Spawn a new async task that will be polled next time the scheduler runs
+    ///
+    /// See [ScreepsRuntime::spawn]
+    pub fn spawn<F>(&self, future: F) -> Result<JoinHandle<F::Output>, RuntimeError>
+    where
+        F: IntoFuture,
+        F::IntoFuture: 'static,
+    {
+        self.spawn_with_priority(Priority::default(), future.into_future())
+    }
+
+    /// Spawn a new async task with the given [Priority]
+    ///
+    /// See [ScreepsRuntime::spawn_with_priority]
+    pub fn spawn_with_priority<F>(
+        &self,
+        priority: Priority,
+        future: F,
+    ) -> Result<JoinHandle<F::Output>, RuntimeError>
+    where
+        F: Future + 'static,
+    {
+        let (future, abort_handle) = Abortable::new(future);
+        let (future, joined) = CatchPanic::new(future);
+        let task = self.spawn_task(priority, future)?;
+        Ok(JoinHandle::new(task, abort_handle, joined))
+    }
+
+    /// Spawn a new async task named `name`
+    ///
+    /// See [ScreepsRuntime::spawn_named]
+    pub fn spawn_named<F>(
+        &self,
+        name: &str,
+        future: F,
+    ) -> Result<JoinHandle<F::Output>, RuntimeError>
+    where
+        F: Future + 'static,
+    {
+        #[cfg(feature = "tracing")]
+        let future = tracing::Instrument::instrument(future, tracing::info_span!("task", name));
+
+        self.spawn(NamedTask {
+            name: name.into(),
+            future,
+        })
+    }
+
+    /// Spawn a new async task that will first be polled `ticks` ticks from now
+    ///
+    /// See [ScreepsRuntime::spawn_after]
+    pub fn spawn_after<F>(
+        &self,
+        ticks: u32,
+        future: F,
+    ) -> Result<JoinHandle<F::Output>, RuntimeError>
+    where
+        F: Future + 'static,
+    {
+        let when = Deadline::from(ticks).when_from(self.game_time());
+        let (future, abort_handle) = Abortable::new(future);
+        let (future, joined) = CatchPanic::new(future);
+        let task = self.start_task(Priority::default(), future, |runnable| {
+            // Registering the task itself as the timer means it isn't polled until it is due
+            self.push_timer(when, TimerTarget::Task(runnable));
+        })?;
+        Ok(JoinHandle::new(task, abort_handle, joined))
+    }
+
+    /// Spawn a new async task that may use at most `fraction` of the tick limit each tick
+    ///
+    /// See [ScreepsRuntime::spawn_with_budget]
+    pub fn spawn_with_budget<F>(
+        &self,
+        fraction: f64,
+        future: F,
+    ) -> Result<JoinHandle<F::Output>, RuntimeError>
+    where
+        F: Future + 'static,
+    {
+        assert!(
+            fraction > 0.0 && fraction <= 1.0,
+            "Task budget must be in (0.0, 1.0], got {fraction}"
+        );
+
+        self.spawn(BudgetedTask {
+            future,
+            handle: self.clone(),
+            budget: fraction,
+            tick: self.game_time(),
+            used: 0.0,
+            deferred: None,
+        })
+    }
+
+    /// Create a new task group with the given `weight`, returning a [Handle] that spawns tasks
+    /// into the group.
+    ///
+    /// Each group has its own run queues. When tasks from several groups are ready to be polled,
+    /// the runtime shares polls between the groups in proportion to their weights, rather than
+    /// polling tasks in the order they were woken. This stops one group that wakes lots of tasks
+    /// from starving other groups when there isn't enough time in the tick to poll everything.
+    /// [Priority] still comes first: groups only share polls between tasks of the same priority.
+    ///
+    /// The default group that tasks are spawned into has a weight of `1`. A group lives for as
+    /// long as any of its handles or tasks do.
+    ///
+    /// # Examples
+    /// ```
+    /// # screeps_async::initialize();
+    /// let handle = screeps_async::with_runtime(|runtime| runtime.handle());
+    /// // Give combat 3 times as many polls as economy when the tick is busy
+    /// let combat = handle.group(3).unwrap();
+    /// let economy = handle.group(1).unwrap();
+    ///
+    /// combat.spawn(async { /* defend */ }).unwrap().detach();
+    /// economy.spawn(async { /* harvest */ }).unwrap().detach();
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `weight` is zero
+    pub fn group(&self, weight: u32) -> Result<Handle, RuntimeError> {
+        assert!(weight > 0, "Task group weight must be non-zero");
+        let groups = self.groups.upgrade().ok_or(RuntimeError::Dropped)?;
+
+        let (senders, group) = TaskGroup::new(weight, &*self.new_scheduler);
+        groups.borrow_mut().push(group);
+
+        Ok(Handle {
+            senders,
+            ..self.clone()
+        })
+    }
+
+    /// Create a task for `future` without scheduling it, returning its [Runnable] and [Task].
+    ///
+    /// This is how every task is spawned under the hood, for embedding your own scheduling logic
+    /// in the runtime, e.g. to delay a task's first poll until some condition is met. The task is
+    /// only polled once the [Runnable] is scheduled, either with [Runnable::schedule] or
+    /// [schedule](Self::schedule), or run directly with [Runnable::run].
+    ///
+    /// A few invariants hold that the rest of the runtime relies on:
+    /// - Dropping the [Runnable] without running it cancels the task, as does dropping the [Task].
+    ///   Use [Task::detach] to let the task run in the background.
+    /// - Once polled, the task's wakers always schedule it onto this handle's runtime, at
+    ///   [Priority::default], just like a task from [spawn](Self::spawn).
+    /// - The task can only be polled on this thread. Running the [Runnable] directly skips the
+    ///   runtime's bookkeeping, like catching panics and the [poll hook](Builder::on_poll), and
+    ///   functions like [current_tick](crate::current_tick) that must be called from a task panic,
+    ///   so prefer scheduling it.
+    ///
+    /// Unlike [spawn](Self::spawn), panics aren't caught for the [Task], so a panicking task is
+    /// handled like a detached one. Returns [RuntimeError::ShutDown] if the runtime is
+    /// [shutting down](ScreepsRuntime::shutdown), and [RuntimeError::Dropped] if it has been
+    /// dropped.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use screeps_async::runtime::Handle;
+    /// fn spawn_when_ready(handle: &Handle, ready: bool) {
+    ///     let (runnable, task) = handle.spawn_raw(async { /* claim the room */ }).unwrap();
+    ///     task.detach();
+    ///     if ready {
+    ///         handle.schedule(runnable).unwrap();
+    ///     } else {
+    ///         // keep the runnable and schedule it later
+    ///     }
+    /// }
+    /// ```
+    pub fn spawn_raw<F>(&self, future: F) -> Result<(Runnable, Task<F::Output>), RuntimeError>
+    where
+        F: Future + 'static,
+    {
+        if self.shutdown.get() != Shutdown::Running {
+            return Err(RuntimeError::ShutDown);
+        }
+        self.create_task(Priority::default(), future, |_, metrics| &metrics.completed)
+    }
+
+    /// Schedule `runnable` onto this handle's runtime, at [Priority::default], to be polled the
+    /// next time the runtime polls tasks.
+    ///
+    /// Meant for [Runnable]s from [spawn_raw](Self::spawn_raw), but any [Runnable] created on this
+    /// thread can be scheduled, e.g. from your own [async_task::spawn_local]. Its wakers are
+    /// unaffected though, so they must schedule it themselves. Like a woken task, the runnable is
+    /// cancelled if the queue is full (see [Builder::max_scheduled]).
+    ///
+    /// Returns [RuntimeError::Dropped] if the runtime has been dropped, in which case the runnable
+    /// is dropped too.
+    pub fn schedule(&self, runnable: Runnable) -> Result<(), RuntimeError> {
+        if self.timers.strong_count() == 0 {
+            return Err(RuntimeError::Dropped);
+        }
+
+        let priority = Priority::default();
+        let max_scheduled = self.max_scheduled.unwrap_or(usize::MAX);
+        self.senders[priority.index()].push(runnable, priority, max_scheduled, &*self.on_overflow);
+        Ok(())
+    }
+
+    /// Schedule `future` and return the raw [Task]. Dropping the [Task] will cancel it
+    fn spawn_task<F>(&self, priority: Priority, future: F) -> Result<Task<F::Output>, RuntimeError>
+    where
+        F: Future + 'static,
+        F::Output: TaskOutcome,
+    {
+        self.start_task(priority, future, Runnable::schedule)
+    }
+
+    /// Create a task for `future` and pass its [Runnable] to `start`, or cancel it if the runtime
+    /// is shutting down. Dropping the returned [Task] will cancel it
+    fn start_task<F>(
+        &self,
+        priority: Priority,
+        future: F,
+        start: impl FnOnce(Runnable),
+    ) -> Result<Task<F::Output>, RuntimeError>
+    where
+        F: Future + 'static,
+        F::Output: TaskOutcome,
+    {
+        let (runnable, task) = self.create_task(priority, future, F::Output::counter)?;
+
+        if self.shutdown.get() != Shutdown::Running {
+            // Dropping the runnable without running it cancels the task
+            drop(runnable);
+        } else {
+            start(runnable);
+        }
+
+        Ok(task)
+    }
+
+    /// Create a task for `future` that is scheduled at `priority` when woken, counting how it
+    /// finished with `outcome`. See [spawn_raw](Self::spawn_raw)
+    fn create_task<F>(
+        &self,
+        priority: Priority,
+        future: F,
+        outcome: OutcomeCounter<F::Output>,
+    ) -> Result<(Runnable, Task<F::Output>), RuntimeError>
+    where
+        F: Future + 'static,
+    {
+        if self.timers.strong_count() == 0 {
+            return Err(RuntimeError::Dropped);
+        }
+
+        // Wakers reschedule through this closure, so the task always keeps this priority.
+        // async_task only calls it when the task isn't already scheduled (or running), which is
+        // what coalesces repeated wakeups into a single queued runnable
+        let sender = self.senders[priority.index()].clone();
+        let max_scheduled = self.max_scheduled.unwrap_or(usize::MAX);
+        let on_overflow = self.on_overflow.clone();
+        let future = CountedTask {
+            future,
+            metrics: self.metrics.clone(),
+            outcome,
+            finished: false,
+        };
+        MetricCounters::bump(&self.metrics.spawned);
+
+        Ok(async_task::spawn_local(future, move |runnable| {
+            sender.push(runnable, priority, max_scheduled, &*on_overflow);
+        }))
+    }
+}
+
+/// Wraps the future of a task spawned with [Handle::spawn_named] to record its name when polled
+struct NamedTask<F> {
+    name: Rc<str>,
+    future: F,
+}
+
+impl<F: Future> Future for NamedTask<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `future` is never moved out of `self`, so it stays pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        POLLED_TASK_NAME.set(Some(this.name.clone()));
+        unsafe { Pin::new_unchecked(&mut this.future) }.poll(cx)
+    }
+}
+
+/// Wraps the future of a task spawned with [Handle::spawn_with_budget] to stop polling it once it
+/// has used up its budget for the tick
+struct BudgetedTask<F> {
+    future: F,
+    handle: Handle,
+    budget: f64,
+    /// The tick that [Self::used] was added up over
+    tick: u32,
+    /// Fraction of the tick limit used by polls of the task during [Self::tick]
+    used: f64,
+    /// Wakes the task next tick once it has used up its budget
+    deferred: Option<Delay>,
+}
+
+impl<F: Future> Future for BudgetedTask<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `future` is never moved out of `self`, so it stays pinned
+        let this = unsafe { self.get_unchecked_mut() };
+
+        if let Some(deferred) = &mut this.deferred {
+            // Woken before the budget is back, the future will be polled once it is
+            if Pin::new(deferred).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.deferred = None;
+        }
+
+        let now = this.handle.game_time();
+        if now != this.tick {
+            this.tick = now;
+            this.used = 0.0;
+        }
+
+        let before = this.handle.clock.cpu_used_fraction();
+        let res = unsafe { Pin::new_unchecked(&mut this.future) }.poll(cx);
+        this.used += this.handle.clock.cpu_used_fraction() - before;
+
+        if res.is_pending() && this.used >= this.budget {
+            let mut deferred = this.handle.delay_until(now.wrapping_add(1));
+            // Poll it now to register the timer
+            let _ = Pin::new(&mut deferred).poll(cx);
+            this.deferred = Some(deferred);
+        }
+        res
+    }
+}
+
+/// Wraps the future of every task to count when it completes or is dropped early
+struct CountedTask<F: Future> {
+    future: F,
+    metrics: Rc<MetricCounters>,
+    /// Picks the counter to bump once the future completes
+    outcome: OutcomeCounter<F::Output>,
+    finished: bool,
+}
+
+/// Picks the counter of [MetricCounters] to bump for a task that finished with the given output.
+/// Usually [TaskOutcome::counter]
+type OutcomeCounter<T> = for<'a> fn(&T, &'a MetricCounters) -> &'a Cell<u64>;
+
+impl<F: Future> Future for CountedTask<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `future` is never moved out of `self`, so it stays pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        let res = unsafe { Pin::new_unchecked(&mut this.future) }.poll(cx);
+        if let Poll::Ready(output) = &res {
+            this.finished = true;
+            POLL_COMPLETED.set(true);
+            MetricCounters::bump((this.outcome)(output, &this.metrics));
+        }
+        res
+    }
+}
+
+impl<F: Future> Drop for CountedTask<F> {
+    fn drop(&mut self) {
+        // Panics that escape the task are counted by ScreepsRuntime::run_task, which catches them
+        if !self.finished && !std::thread::panicking() {
+            MetricCounters::bump(&self.metrics.aborted);
+        }
+    }
+}
+
+/// The output of a task's future, which tells [CountedTask] how the task finished
+trait TaskOutcome {
+    /// The counter to bump now that the task has finished with this output
+    fn counter<'a>(&self, metrics: &'a MetricCounters) -> &'a Cell<u64>;
+}
+
+impl TaskOutcome for () {
+    fn counter<'a>(&self, metrics: &'a MetricCounters) -> &'a Cell<u64> {
+        &metrics.completed
+    }
+}
+
+impl<T> TaskOutcome for Result<T, JoinError> {
+    fn counter<'a>(&self, metrics: &'a MetricCounters) -> &'a Cell<u64> {
+        match self {
+            Ok(_) => &metrics.completed,
+            Err(JoinError::Panicked(_)) => &metrics.panicked,
+            Err(JoinError::Cancelled) => &metrics.aborted,
+        }
+    }
+}
+
+/// Counters behind [ScreepsRuntime::metrics]
+#[derive(Default)]
+struct MetricCounters {
+    spawned: Cell<u64>,
+    completed: Cell<u64>,
+    aborted: Cell<u64>,
+    panicked: Cell<u64>,
+    timers_fired: Cell<u64>,
+    polled: Cell<u64>,
+}
+
+impl MetricCounters {
+    fn bump(counter: &Cell<u64>) {
+        counter.set(counter.get() + 1);
+    }
+}
+
+/// A run queue of tasks that are ready to be polled. See [Builder::scheduler]
+///
+/// Each time the runtime wants to poll a task from a queue, it first [pushes](Self::push) every
+/// task that has been scheduled onto the queue since the last time, then polls the task returned
+/// by [next](Self::next). A task is never in a queue more than once at a time, and every task pushed
+/// must eventually be returned by [next](Self::next) (or dropped, which cancels it).
+pub trait Scheduler {
+    /// Add a task that has been spawned or woken to the queue
+    fn push(&mut self, runnable: Runnable);
+
+    /// Take the next task to poll, or [None] if the queue is empty
+    fn next(&mut self) -> Option<Runnable>;
+
+    /// The number of tasks in the queue
+    fn len(&self) -> usize;
+
+    /// Whether the queue is empty
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The default [Scheduler], which polls tasks in the order they were scheduled
+#[derive(Default)]
+pub struct FifoScheduler {
+    queue: VecDeque<Runnable>,
+}
+
+impl Scheduler for FifoScheduler {
+    fn push(&mut self, runnable: Runnable) {
+        self.queue.push_back(runnable);
+    }
+
+    fn next(&mut self) -> Option<Runnable> {
+        self.queue.pop_front()
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+/// The half of a [RunQueue] that wakers use to schedule tasks
+#[derive(Clone)]
+struct QueueSender {
+    sender: flume::Sender<Runnable>,
+    /// The number of tasks in the queue, shared with [RunQueue::scheduled]
+    scheduled: Arc<AtomicUsize>,
+}
+
+impl QueueSender {
+    /// Push `runnable` onto the queue for `priority`, cancelling it instead if the queue already
+    /// holds `max_scheduled` tasks
+    fn push(
+        &self,
+        runnable: Runnable,
+        priority: Priority,
+        max_scheduled: usize,
+        on_overflow: &(dyn Fn(Priority) + Send + Sync),
+    ) {
+        if self.scheduled.fetch_add(1, Ordering::Relaxed) >= max_scheduled {
+            self.scheduled.fetch_sub(1, Ordering::Relaxed);
+            // Dropping the runnable cancels the task
+            drop(runnable);
+            on_overflow(priority);
+            return;
+        }
+
+        if self.sender.send(runnable).is_err() {
+            // The runtime has been dropped, so nothing will ever poll the task. Dropping the
+            // runnable cancels it, so wakers that outlive the runtime are harmless
+            self.scheduled.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The tasks of one [Priority] of a task group that are ready to be polled.
+///
+/// Wakers may be called from anywhere, so they send tasks down a channel which is only drained
+/// into the [Scheduler] when the runtime takes a task.
+struct RunQueue {
+    receiver: flume::Receiver<Runnable>,
+    scheduler: Box<dyn Scheduler>,
+    /// Counts tasks both in the channel and in the scheduler, so wakers can enforce
+    /// [Builder::max_scheduled]
+    scheduled: Arc<AtomicUsize>,
+}
+
+impl RunQueue {
+    fn new(scheduler: Box<dyn Scheduler>) -> (QueueSender, Self) {
+        let (sender, receiver) = flume::unbounded();
+        let scheduled = Arc::new(AtomicUsize::new(0));
+        let queue = Self {
+            receiver,
+            scheduler,
+            scheduled: scheduled.clone(),
+        };
+        (QueueSender { sender, scheduled }, queue)
+    }
+
+    /// Take the next task to poll, as picked by the [Scheduler]
+    fn pop(&mut self) -> Option<Runnable> {
+        self.receiver
+            .try_iter()
+            .for_each(|runnable| self.scheduler.push(runnable));
+
+        let runnable = self.scheduler.next()?;
+        self.scheduled.fetch_sub(1, Ordering::Relaxed);
+        Some(runnable)
+    }
+
+    fn len(&self) -> usize {
+        self.receiver.len() + self.scheduler.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The run queues of a task group. See [Handle::group]
+struct TaskGroup {
+    weight: u32,
+    /// Scheduled tasks, indexed by [Priority]. When a task is scheduled, the associated
+    /// future is ready to make progress. This usually happens when a resource the task
+    /// uses becomes ready to perform an operation.
+    queues: [RunQueue; 3],
+    /// Weighted round-robin credit, indexed by [Priority]. See [ScreepsRuntime::next_scheduled]
+    credit: [i64; 3],
+}
+
+impl TaskGroup {
+    /// Create the run queues for a group, returning the send halves along with the group
+    fn new(
+        weight: u32,
+        new_scheduler: &dyn Fn() -> Box<dyn Scheduler>,
+    ) -> ([QueueSender; 3], Self) {
+        let queues = Priority::ALL.map(|_| RunQueue::new(new_scheduler()));
+        let senders = queues.each_ref().map(|(sender, _)| sender.clone());
+        let queues = queues.map(|(_, queue)| queue);
+
+        let group = Self {
+            weight,
+            queues,
+            credit: [0; 3],
+        };
+        (senders, group)
+    }
+
+    /// Whether no [Handle] or task can schedule anything onto this group anymore
+    fn is_abandoned(&self) -> bool {
+        self.queues
+            .iter()
+            .all(|queue| queue.receiver.sender_count() == 0 && queue.is_empty())
+    }
+}
+
+/// A snapshot of the state of a [ScreepsRuntime]. See [ScreepsRuntime::stats]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct RuntimeStats {
+    /// The number of tasks currently scheduled to be polled
+    pub pending_count: usize,
+    /// The number of timers waiting to fire
+    pub timer_count: usize,
+    /// The earliest game tick that a timer is waiting for, if any
+    pub next_timer_tick: Option<u32>,
+}
+
+/// Totals of task and timer events over the lifetime of a [ScreepsRuntime].
+/// See [ScreepsRuntime::metrics]
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct RuntimeMetrics {
+    /// The number of tasks spawned, including the futures passed to
+    /// [block_on](ScreepsRuntime::block_on)
+    pub tasks_spawned: u64,
+    /// The number of tasks whose future ran to completion
+    pub tasks_completed: u64,
+    /// The number of tasks dropped before completing, either because they were
+    /// [aborted](crate::job::JoinHandle::abort) or the runtime cancelled them while shutting down
+    pub tasks_aborted: u64,
+    /// The number of tasks that panicked while being polled
+    pub tasks_panicked: u64,
+    /// The number of timers that have fired
+    pub timers_fired: u64,
+    /// The total number of times a task has been polled
+    pub polled: u64,
+}
+
+/// What the runtime does when a detached task panics. See [Builder::panic_policy]
+///
+/// Only applies to panics the runtime catches: tasks whose [JoinHandle] is still around return
+/// the panic from the handle instead.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum PanicPolicy {
+    /// Pass the panic to the [panic handler](Builder::on_task_panic), then keep polling the
+    /// remaining tasks. Keeps one buggy task from taking down the whole game loop
+    #[default]
+    Continue,
+    /// Resume the panic once the runtime has finished cleaning up after the task, unwinding out
+    /// of [run](ScreepsRuntime::run). The panic handler isn't called. Tasks that weren't polled
+    /// yet stay scheduled, so the runtime can still be used if the panic is caught.
+    Abort,
+}
+
+/// A single poll of a task, passed to the [poll hook](Builder::on_poll)
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct PollEvent<'a> {
+    /// The name of the task, if it was spawned with [spawn_named](ScreepsRuntime::spawn_named)
+    pub name: Option<&'a str>,
+    /// The fraction of the tick limit used before the task was polled
+    pub used_before: f64,
+    /// The fraction of the tick limit used after the task was polled
+    pub used_after: f64,
+    /// Whether the task finished during this poll, including by panicking, so won't be polled
+    /// again
+    pub completed: bool,
+}
+
+/// A summary of the work done by a single [run](ScreepsRuntime::run)
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct TickReport {
+    /// The number of tasks polled
+    pub polled: usize,
+    /// The number of tasks still scheduled when the run finished, left to be polled next tick
+    pub deferred: usize,
+    /// The number of timers that fired
+    pub timers_fired: usize,
+    /// Whether the run stopped early because it used up its
+    /// [tick time allocation](Builder::tick_time_allocation) or
+    /// [poll limit](Builder::max_polls_per_tick)
+    pub budget_exhausted: bool,
+}
+
+/// Timers keyed by the game tick they fire on.
+///
+/// Entries within a tick are kept in the order they were registered, and are fired in that order,
+/// so timers always fire in a deterministic order. Removing an entry must preserve this order.
+///
+/// Game ticks wrap around at [u32::MAX], so the map isn't in firing order once timers are
+/// registered across the wrap. Use [is_due] rather than comparing ticks directly.
+///
+/// The lists of ticks that have been removed can be [recycled](Self::recycle) to save allocating
+/// new ones, see [Builder::timer_bucket_capacity].
+pub(crate) struct TimerMap {
+    pub(crate) ticks: BTreeMap<u32, Vec<TimerEntry>>,
+    /// Emptied lists to reuse for new ticks
+    spare: Vec<Vec<TimerEntry>>,
+    /// Capacity to allocate new lists with
+    bucket_capacity: usize,
+}
+
+/// The most emptied lists a [TimerMap] keeps for reuse
+const MAX_SPARE_TIMER_BUCKETS: usize = 16;
+
+impl TimerMap {
+    pub(crate) fn new(bucket_capacity: usize) -> Self {
+        Self {
+            ticks: BTreeMap::new(),
+            spare: Vec::new(),
+            bucket_capacity,
+        }
+    }
+
+    /// Add `entry` to the end of the timers for tick `when`
+    pub(crate) fn push(&mut self, when: u32, entry: TimerEntry) {
+        let Self {
+            ticks,
+            spare,
+            bucket_capacity,
+        } = self;
+        ticks
+            .entry(when)
+            .or_insert_with(|| {
+                spare
+                    .pop()
+                    .unwrap_or_else(|| Vec::with_capacity(*bucket_capacity))
+            })
+            .push(entry);
+    }
+
+    /// Remove the timers of the oldest tick that is [due](is_due) at `game_time`
+    pub(crate) fn pop_due(&mut self, game_time: u32) -> Option<Vec<TimerEntry>> {
+        let oldest = game_time.wrapping_sub(TIMER_HORIZON);
+        let due = if oldest <= game_time {
+            self.ticks.range(oldest..=game_time).next()
+        } else {
+            // The due ticks wrap around past u32::MAX
+            self.ticks
+                .range(oldest..)
+                .next()
+                .or_else(|| self.ticks.range(..=game_time).next())
+        };
+        let when = *due?.0;
+        self.ticks.remove(&when)
+    }
+
+    /// Keep the list of a tick that was removed from [ticks](Self::ticks) for reuse
+    pub(crate) fn recycle(&mut self, mut entries: Vec<TimerEntry>) {
+        if self.spare.len() < MAX_SPARE_TIMER_BUCKETS {
+            entries.clear();
+            self.spare.push(entries);
+        }
+    }
+}
+
+/// The furthest a timer can be in the future, in ticks. Ticks further ahead than this are
+/// indistinguishable from ticks in the past once the game time has wrapped around
+pub(crate) const TIMER_HORIZON: u32 = i32::MAX as u32;
+
+/// Whether game tick `when` has been reached at `game_time`.
+///
+/// Compares with wrapping arithmetic so that timers keep working when the game time wraps around
+/// past [u32::MAX]: `when` counts as reached if it is at most [TIMER_HORIZON] ticks before
+/// `game_time`, and as still to come otherwise.
+pub(crate) fn is_due(when: u32, game_time: u32) -> bool {
+    game_time.wrapping_sub(when) <= TIMER_HORIZON
+}
+
+/// A timer waiting for the game tick it is keyed under in the [TimerMap]
+pub(crate) struct TimerEntry {
+    /// Unique (per runtime) id used by the owner of the timer to find it again
+    pub(crate) id: u64,
+    pub(crate) target: TimerTarget,
+}
+
+/// What to do when a [TimerEntry] fires
+pub(crate) enum TimerTarget {
+    /// Wake a task waiting on a [Delay](crate::time::Delay)
+    Waker(Waker),
+    /// Schedule a task that hasn't been polled yet, see [Handle::spawn_after]
+    Task(Runnable),
+}
+
+impl TimerTarget {
+    pub(crate) fn fire(self) {
+        match self {
+            TimerTarget::Waker(waker) => waker.wake(),
+            TimerTarget::Task(runnable) => runnable.schedule(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::RuntimeError::OutOfTime;
+    use crate::tests::*;
+    use crate::time::yield_now;
+    use crate::{spawn, with_runtime};
+    use rstest::rstest;
+    use std::cell::OnceCell;
+
+    #[test]
+    fn test_block_on() {
+        init_test();
+
+        let res = crate::block_on(async move {
+            yield_now().await;
+            1 + 2
+        })
+        .unwrap();
+
+        assert_eq!(3, res);
+    }
+
+    #[test]
+    fn test_spawn() {
+        init_test();
+
+        drop(spawn(async move {}));
+
+        with_runtime(|runtime| {
+            runtime.groups.borrow_mut()[0].queues[Priority::Normal.index()]
+                .pop()
+                .expect("Failed to schedule task");
+        })
+    }
+
+    #[test]
+    fn test_spawn_not_send() {
+        init_test();
+
+        let shared = Rc::new(RefCell::new(Vec::new()));
+        let handle = {
+            let shared = shared.clone();
+            spawn(async move {
+                let local = Rc::new(1);
+                yield_now().await;
+                shared.borrow_mut().push(*local);
+            })
+        };
+
+        crate::run().unwrap();
+        assert!(handle.is_finished());
+        assert_eq!(vec![1], shared.take());
+    }
+
+    #[test]
+    fn test_run() {
+        init_test();
+
+        let has_run = Rc::new(OnceCell::new());
+        {
+            let has_run = has_run.clone();
+            spawn(async move {
+                has_run.set(()).unwrap();
+            })
+            .detach();
+        }
+
+        // task hasn't run yet
+        assert!(has_run.get().is_none());
+
+        crate::run().unwrap();
+
+        // Future has been run
+        assert!(has_run.get().is_some());
+    }
+
+    #[test]
+    fn test_nested_spawn() {
+        init_test();
+
+        let has_run = Rc::new(OnceCell::new());
+        {
+            let has_run = has_run.clone();
+            spawn(async move {
+                let result = spawn(async move { 1 + 2 }).await.unwrap();
+
+                assert_eq!(3, result);
+
+                has_run.set(()).unwrap();
+            })
+            .detach();
+        }
+
+        // task hasn't run yet
+        assert!(has_run.get().is_none());
+
+        crate::run().unwrap();
+
+        // Future has been run
+        assert!(has_run.get().is_some());
+    }
+
+    #[test]
+    fn test_respects_time_remaining() {
+        init_test();
+
+        let has_run = Rc::new(OnceCell::new());
+        {
+            let has_run = has_run.clone();
+            spawn(async move {
+                has_run.set(()).unwrap();
+            })
+            .detach();
+        }
+
+        TIME_USED.with_borrow_mut(|t| *t = 0.95);
+
+        // task hasn't run yet
+        assert!(has_run.get().is_none());
+
+        assert!(crate::run().unwrap().budget_exhausted);
+
+        // Check future still hasn't run
+        assert!(has_run.get().is_none());
+    }
+
+    #[test]
+    fn test_tick_report() {
+        init_test();
+        Builder::new().tick_time_allocation(0.5).apply();
+
+        spawn(crate::time::delay(1)).detach();
+        let report = crate::run().unwrap();
+        assert_eq!(1, report.polled);
+        assert_eq!(0, report.deferred);
+        assert!(!report.budget_exhausted);
+
+        GAME_TIME.with_borrow_mut(|t| *t += 1);
+        for _ in 0..3 {
+            spawn(async move { TIME_USED.with_borrow_mut(|t| *t += 0.3) }).detach();
+        }
+
+        // Only enough time for two tasks, leaving the last one and the woken delay deferred
+        let report = crate::run().unwrap();
+        assert_eq!(1, report.timers_fired);
+        assert_eq!(2, report.polled);
+        assert_eq!(2, report.deferred);
+        assert!(report.budget_exhausted);
+
+        TIME_USED.with_borrow_mut(|t| *t = 0.0);
+        let report = crate::run().unwrap();
+        assert_eq!(0, report.timers_fired);
+        assert_eq!(2, report.polled);
+        assert_eq!(0, report.deferred);
+        assert!(!report.budget_exhausted);
+    }
+
+    #[test]
+    fn test_on_tick_end() {
+        init_test();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        {
+            let log = log.clone();
+            Builder::new()
+                .min_bucket(1000)
+                .tick_time_allocation(0.5)
+                .on_tick_end(move || log.borrow_mut().push("tick end"))
+                .apply();
+        }
+
+        for _ in 0..2 {
+            let log = log.clone();
+            spawn(async move {
+                log.borrow_mut().push("task");
+                TIME_USED.with_borrow_mut(|t| *t = 0.6);
+            })
+            .detach();
+        }
+
+        // Called after the poll loop even when the budget is exhausted
+        let report = crate::run().unwrap();
+        assert!(report.budget_exhausted);
+        assert_eq!(1, report.deferred);
+        assert_eq!(vec!["task", "tick end"], log.take());
+
+        // Called when no tasks are polled at all
+        BUCKET.with_borrow_mut(|b| *b = 0);
+        crate::run().unwrap();
+        assert_eq!(vec!["tick end"], log.take());
+
+        BUCKET.with_borrow_mut(|b| *b = 10000);
+        TIME_USED.with_borrow_mut(|t| *t = 0.0);
+        crate::run().unwrap();
+        assert_eq!(vec!["task", "tick end"], log.take());
+    }
+
+    #[test]
+    fn test_poll_once() {
+        init_test();
+        // poll_once ignores the time allocation
+        TIME_USED.with_borrow_mut(|t| *t = 1.0);
+
+        let stage = Rc::new(Cell::new(0));
+        {
+            let stage = stage.clone();
+            spawn(async move {
+                stage.set(1);
+                yield_now().await;
+                stage.set(2);
+                crate::time::delay(0).await;
+                stage.set(3);
+            })
+            .detach();
+        }
+
+        let poll_once = || with_runtime(|runtime| runtime.poll_once());
+        for expected in 1..=3 {
+            assert!(poll_once());
+            assert_eq!(expected, stage.get());
+        }
+        assert!(!poll_once(), "Did work with nothing scheduled");
+    }
+
+    #[test]
+    fn test_wake_after_runtime_dropped() {
+        init_test();
+
+        let (tx, rx) = crate::sync::oneshot::channel::<()>();
+        let waker = Rc::new(RefCell::new(None::<Waker>));
+        let handle = {
+            let waker = waker.clone();
+            spawn(async move {
+                std::future::poll_fn(|cx| {
+                    waker.replace(Some(cx.waker().clone()));
+                    Poll::Ready(())
+                })
+                .await;
+
+                crate::select! {
+                    _ = rx => {},
+                    _ = crate::time::delay(10) => {},
+                }
+            })
+        };
+        crate::run().unwrap();
+        assert!(!handle.is_finished());
+
+        let runtime = CURRENT.with_borrow_mut(Option::take);
+        drop(runtime);
+
+        // Wake the parked task, which has nowhere left to be scheduled
+        tx.send(()).unwrap();
+        // Stale wakers of the cancelled task must be harmless too
+        let waker = waker.take().unwrap();
+        waker.wake_by_ref();
+        waker.wake();
+
+        assert!(handle.is_finished(), "Woken task was not cancelled");
+    }
+
+    #[test]
+    fn test_repeated_wakes_are_coalesced() {
+        init_test();
+
+        let polls = Rc::new(Cell::new(0));
+        let waker = Rc::new(RefCell::new(None::<Waker>));
+        {
+            let polls = polls.clone();
+            let waker = waker.clone();
+            spawn(std::future::poll_fn(move |cx| {
+                polls.set(polls.get() + 1);
+                waker.replace(Some(cx.waker().clone()));
+                Poll::<()>::Pending
+            }))
+            .detach();
+        }
+        crate::run().unwrap();
+        assert_eq!(1, polls.get());
+
+        let waker = waker.take().unwrap();
+        for _ in 0..100 {
+            waker.wake_by_ref();
+        }
+        assert_eq!(1, with_runtime(ScreepsRuntime::pending_count));
+
+        crate::run().unwrap();
+        assert_eq!(2, polls.get(), "Task was polled once per wake");
+    }
+
+    #[test]
+    fn test_poll_watchdog() {
+        init_test();
+        let slow = Rc::new(RefCell::new(Vec::new()));
+        {
+            let slow = slow.clone();
+            Builder::new()
+                .poll_watchdog(0.1, move |name, used| {
+                    slow.borrow_mut().push((name.map(str::to_string), used));
+                })
+                .apply();
+        }
+
+        let burn_to = |used: f64| async move { TIME_USED.with_borrow_mut(|t| *t = used) };
+        crate::spawn_named("burner", burn_to(0.2)).detach();
+        crate::spawn_named("fine", burn_to(0.25)).detach();
+        spawn(burn_to(0.5)).detach();
+        crate::run().unwrap();
+
+        assert_eq!(
+            vec![(Some("burner".to_string()), 0.2), (None, 0.25)],
+            slow.take()
+        );
+    }
+
+    #[test]
+    fn test_spawn_raw() {
+        init_test();
+        let handle = with_runtime(ScreepsRuntime::handle);
+
+        let polls = Rc::new(Cell::new(0));
+        let (runnable, task) = {
+            let polls = polls.clone();
+            handle
+                .spawn_raw(async move {
+                    polls.set(polls.get() + 1);
+                    crate::time::delay(1).await;
+                    polls.set(polls.get() + 1);
+                    crate::current_tick()
+                })
+                .unwrap()
+        };
+
+        tick().unwrap();
+        assert_eq!(0, polls.get(), "Task was polled before being scheduled");
+
+        handle.schedule(runnable).unwrap();
+        tick().unwrap();
+        assert_eq!(1, polls.get());
+        tick().unwrap();
+        assert_eq!(2, polls.get(), "Waker didn't reschedule the task");
+        assert_eq!(2, crate::run_blocking(task));
+        assert_eq!(1, with_runtime(ScreepsRuntime::metrics).tasks_completed);
+    }
+
+    #[test]
+    fn test_try_run_while_running() {
+        init_test();
+        let hook_res = Rc::new(Cell::new(None));
+        {
+            let hook_res = hook_res.clone();
+            Builder::new()
+                .on_tick_end(move || hook_res.set(Some(crate::try_run().err())))
+                .apply();
+        }
+
+        let task_res = spawn(async move { crate::try_run().err() });
+        crate::try_run().unwrap();
+        assert_eq!(
+            Some(RuntimeError::AlreadyRunning),
+            crate::run_blocking(task_res).unwrap()
+        );
+        assert_eq!(Some(Some(RuntimeError::AlreadyRunning)), hook_res.get());
+
+        // The failed runs left the runtime usable
+        let handle = spawn(async move { 1 + 2 });
+        crate::try_run().unwrap();
+        assert_eq!(3, crate::run_blocking(handle).unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "Async runtime is already running")]
+    fn test_run_while_running_panics() {
+        init_test();
+        Builder::new().panic_policy(PanicPolicy::Abort).apply();
+        crate::block_on(async move { crate::run() }).ok();
+    }
+
+    #[test]
+    fn test_reset() {
+        init_test();
+        let notify = Rc::new(crate::sync::Notify::new());
+        let dropped = Rc::new(Cell::new(0));
+        let polls = Rc::new(Cell::new(0));
+
+        struct DropCounter(Rc<Cell<u32>>);
+        impl Drop for DropCounter {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        {
+            let guard = DropCounter(dropped.clone());
+            let polls = polls.clone();
+            spawn(async move {
+                let _guard = guard;
+                crate::time::delay(5).await;
+                polls.set(polls.get() + 1);
+            })
+            .detach();
+        }
+        {
+            let guard = DropCounter(dropped.clone());
+            let (notify, polls) = (notify.clone(), polls.clone());
+            spawn(async move {
+                let _guard = guard;
+                notify.notified().await;
+                polls.set(polls.get() + 1);
+            })
+            .detach();
+        }
+        tick().unwrap();
+
+        Builder::new().reset();
+        assert_eq!(1, dropped.get(), "Timer task wasn't cancelled");
+
+        // The stale waker cancels its task instead of running it on the new runtime
+        notify.notify_one();
+        assert_eq!(2, dropped.get(), "Woken task wasn't cancelled");
+
+        let handle = spawn(async move { 1 + 2 });
+        for _ in 0..5 {
+            tick().unwrap();
+        }
+        assert_eq!(3, crate::run_blocking(handle).unwrap());
+        assert_eq!(0, polls.get());
+    }
+
+    #[test]
+    fn test_on_poll() {
+        init_test();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        {
+            let events = events.clone();
+            Builder::new()
+                .on_poll(move |event| {
+                    events.borrow_mut().push((
+                        event.name.map(str::to_string),
+                        event.used_before,
+                        event.used_after,
+                        event.completed,
+                    ));
+                })
+                .apply();
+        }
+
+        crate::spawn_named("hauler", async move {
+            TIME_USED.with_borrow_mut(|t| *t = 0.1);
+            crate::time::delay(1).await;
+            TIME_USED.with_borrow_mut(|t| *t = 0.3);
+        })
+        .detach();
+        tick().unwrap();
+        tick().unwrap();
+
+        let name = Some("hauler".to_string());
+        assert_eq!(
+            vec![(name.clone(), 0.0, 0.1, false), (name, 0.1, 0.3, true)],
+            events.take()
+        );
+    }
+
+    #[rstest]
+    #[case::without_reserve(None, 5)]
+    #[case::with_reserve(Some(1.0), 4)]
+    fn test_poll_reserve(#[case] smoothing: Option<f64>, #[case] expected_polls: usize) {
+        init_test();
+        let mut builder = Builder::new().tick_time_allocation(0.9);
+        if let Some(smoothing) = smoothing {
+            builder = builder.poll_reserve(smoothing);
+        }
+        builder.apply();
+
+        // Each task uses 20% of the tick
+        for _ in 0..10 {
+            spawn(async move { TIME_USED.with_borrow_mut(|t| *t += 0.2) }).detach();
+        }
+
+        let report = crate::run().unwrap();
+        assert!(report.budget_exhausted);
+        assert_eq!(expected_polls, report.polled);
+        if smoothing.is_some() {
+            assert!(
+                TIME_USED.with_borrow(|t| *t) <= 0.9,
+                "Overshot the allocation"
+            );
+        }
+    }
+
+    #[rstest]
+    #[case::cap_binds(100.0, 3)]
+    #[case::cap_binds_lower_limit(200.0, 2)]
+    #[case::allocation_binds(20.0, 5)]
+    fn test_tick_time_cpu(#[case] tick_limit: f64, #[case] expected_polls: usize) {
+        init_test();
+        TICK_LIMIT.with_borrow_mut(|l| *l = tick_limit);
+        Builder::new()
+            .tick_time_allocation(0.45)
+            .tick_time_cpu(25.0)
+            .apply();
+
+        // Each task uses 10% of the tick
+        for _ in 0..10 {
+            spawn(async move { TIME_USED.with_borrow_mut(|t| *t += 0.1) }).detach();
+        }
+
+        let report = crate::run().unwrap();
+        assert!(report.budget_exhausted);
+        assert_eq!(expected_polls, report.polled);
+    }
+
+    #[test]
+    fn test_run_with_allocation_is_capped() {
+        init_test();
+        TICK_LIMIT.with_borrow_mut(|l| *l = 100.0);
+        Builder::new().tick_time_cpu(25.0).apply();
+
+        for _ in 0..10 {
+            spawn(async move { TIME_USED.with_borrow_mut(|t| *t += 0.1) }).detach();
+        }
+
+        let report = with_runtime(|runtime| runtime.run_with_allocation(1.0)).unwrap();
+        assert_eq!(3, report.polled);
+    }
+
+    #[test]
+    fn test_poll_reserve_smoothing() {
+        init_test();
+        Builder::new()
+            .tick_time_allocation(0.9)
+            .poll_reserve(0.5)
+            .apply();
+
+        // One spike, then cheap polls: the estimate halves towards each new cost
+        for cost in [0.4, 0.0, 0.0] {
+            spawn(async move { TIME_USED.with_borrow_mut(|t| *t += cost) }).detach();
+        }
+        crate::run().unwrap();
+
+        let cost = with_runtime(|runtime| runtime.poll_cost.get());
+        assert!((cost - 0.05).abs() < 1e-9, "Unexpected estimate {cost}");
+    }
+
+    #[test]
+    fn test_custom_scheduler() {
+        #[derive(Default)]
+        struct LifoScheduler(Vec<Runnable>);
+
+        impl Scheduler for LifoScheduler {
+            fn push(&mut self, runnable: Runnable) {
+                self.0.push(runnable);
+            }
+
+            fn next(&mut self) -> Option<Runnable> {
+                self.0.pop()
+            }
+
+            fn len(&self) -> usize {
+                self.0.len()
+            }
+        }
+
+        init_test();
+        Builder::new().scheduler(LifoScheduler::default).apply();
+
+        let order = Rc::new(RefCell::new(Vec::new()));
+        for i in 0..3 {
+            let order = order.clone();
+            spawn(async move { order.borrow_mut().push(i) }).detach();
+        }
+        assert_eq!(3, with_runtime(ScreepsRuntime::pending_count));
+
+        crate::run().unwrap();
+        assert_eq!(vec![2, 1, 0], order.take());
+    }
+
+    #[test]
+    fn test_priority_ordering() {
+        init_test();
+
+        let order = Rc::new(RefCell::new(Vec::new()));
+        for priority in [Priority::Low, Priority::Normal, Priority::High] {
+            let order = order.clone();
+            crate::spawn_with_priority(priority, async move {
+                order.borrow_mut().push(priority);
+                // Only enough time for one task this tick
+                TIME_USED.with_borrow_mut(|t| *t = 0.95);
+            })
+            .detach();
+        }
+
+        assert!(crate::run().unwrap().budget_exhausted);
+        assert_eq!(vec![Priority::High], *order.borrow());
+
+        TIME_USED.with_borrow_mut(|t| *t = 0.0);
+        assert!(crate::run().unwrap().budget_exhausted);
+        assert_eq!(vec![Priority::High, Priority::Normal], *order.borrow());
+
+        TIME_USED.with_borrow_mut(|t| *t = 0.0);
+        assert!(crate::run().unwrap().budget_exhausted);
+        assert_eq!(
+            vec![Priority::High, Priority::Normal, Priority::Low],
+            order.take()
+        );
+    }
+
+    #[test]
+    fn test_woken_task_keeps_priority() {
+        init_test();
+
+        let order = Rc::new(RefCell::new(Vec::new()));
+        {
+            let order = order.clone();
+            crate::spawn(async move {
+                order.borrow_mut().push("normal");
+            })
+            .detach();
+        }
+        {
+            let order = order.clone();
+            crate::spawn_with_priority(Priority::High, async move {
+                order.borrow_mut().push("high 1");
+                yield_now().await;
+                order.borrow_mut().push("high 2");
+            })
+            .detach();
+        }
+
+        crate::run().unwrap();
+
+        assert_eq!(vec!["high 1", "high 2", "normal"], order.take());
+    }
+
+    #[rstest]
+    #[case::low_bucket(500, false)]
+    #[case::medium_bucket(3000, true)]
+    #[case::full_bucket(10000, true)]
+    fn test_default_allocation_scales_with_bucket(#[case] bucket: i32, #[case] should_run: bool) {
+        init_test();
+        BUCKET.with_borrow_mut(|b| *b = bucket);
+        TIME_USED.with_borrow_mut(|t| *t = 0.6);
+
+        let handle = spawn(async move {});
+        let report = crate::run().unwrap();
+
+        assert_eq!(should_run, !report.budget_exhausted);
+        assert_eq!(should_run, handle.is_finished());
+    }
+
+    #[test]
+    fn test_tick_time_allocation_fn() {
+        GAME_TIME.with_borrow_mut(|t| *t = 0);
+        TIME_USED.with_borrow_mut(|t| *t = 0.3);
+        BUCKET.with_borrow_mut(|b| *b = 100);
+        TICK_LIMIT.with_borrow_mut(|l| *l = 20.0);
+
+        let args = Rc::new(RefCell::new(Vec::new()));
+        {
+            let args = args.clone();
+            Builder::new()
+                .tick_time_allocation_fn(move |bucket, tick_limit| {
+                    args.borrow_mut().push((bucket, tick_limit));
+                    if bucket < 1000 {
+                        0.2
+                    } else {
+                        0.9
+                    }
+                })
+                .apply();
+        }
+
+        let handle = spawn(async move {});
+        assert!(crate::run().unwrap().budget_exhausted);
+        assert!(!handle.is_finished());
+
+        // Allocation is re-evaluated each run
+        BUCKET.with_borrow_mut(|b| *b = 2000);
+        crate::run().unwrap();
+        assert!(handle.is_finished());
+
+        assert_eq!(vec![(100, 20.0), (2000, 20.0)], args.take());
+    }
+
+    /// Steps through a script of `(game_time, cpu_used_fraction)` values
+    struct ScriptedClock {
+        script: Vec<(u32, f64)>,
+        step: Rc<Cell<usize>>,
+    }
+
+    impl ClockSource for ScriptedClock {
+        fn game_time(&self) -> u32 {
+            self.script[self.step.get()].0
+        }
+
+        fn cpu_used_fraction(&self) -> f64 {
+            self.script[self.step.get()].1
+        }
+
+        fn bucket(&self) -> i32 {
+            10000
+        }
+
+        fn tick_limit(&self) -> f64 {
+            500.0
+        }
+    }
+
+    #[test]
+    fn test_clock_source() {
+        init_test();
+        let step = Rc::new(Cell::new(0));
+        let script = vec![(10, 0.0), (12, 0.95), (13, 0.0)];
+        Builder::new()
+            .clock(ScriptedClock {
+                script,
+                step: step.clone(),
+            })
+            .apply();
+
+        let handle = spawn(async move {
+            crate::time::delay(2).await;
+            crate::current_tick()
+        });
+
+        let mut results = Vec::new();
+        for i in 0..3 {
+            step.set(i);
+            results.push(crate::run().unwrap().budget_exhausted);
+        }
+
+        // The real game time never moved, only the scripted clock did
+        assert_eq!(0, game_time());
+        assert_eq!(vec![false, true, false], results);
+        assert_eq!(13, crate::run_blocking(handle).unwrap());
+    }
+
+    /// A clock whose game time is set by hand
+    struct SimClock(Rc<Cell<u32>>);
+
+    impl ClockSource for SimClock {
+        fn game_time(&self) -> u32 {
+            self.0.get()
+        }
+
+        fn cpu_used_fraction(&self) -> f64 {
+            0.0
+        }
+
+        fn bucket(&self) -> i32 {
+            10000
+        }
+
+        fn tick_limit(&self) -> f64 {
+            500.0
+        }
+    }
+
+    #[test]
+    fn test_isolated_runtimes() {
+        init_test();
+
+        let sims = [100, 0].map(|start| {
+            let time = Rc::new(Cell::new(start));
+            let runtime = Builder::new()
+                .clock(SimClock(time.clone()))
+                .build_isolated();
+
+            let handle = runtime.handle();
+            let task = runtime
+                .handle()
+                .spawn(async move {
+                    let started = handle.game_time();
+                    handle.delay(2).await;
+                    handle.game_time() - started
+                })
+                .unwrap();
+            runtime.run().unwrap();
+            (time, runtime, task)
+        });
+        assert_eq!(0, with_runtime(ScreepsRuntime::timer_count));
+
+        let [(time_a, a, task_a), (time_b, b, task_b)] = sims;
+        time_a.set(102);
+        a.run().unwrap();
+        b.run().unwrap();
+        assert!(task_a.is_finished());
+        assert!(
+            !task_b.is_finished(),
+            "Task woke on another runtime's clock"
+        );
+
+        time_b.set(2);
+        b.run().unwrap();
+        assert_eq!(Some(2), b.run_blocking(task_b).ok());
+        assert_eq!(Some(2), a.run_blocking(task_a).ok());
+
+        drop((a, b));
+        assert!(
+            Builder::new().try_build().is_err(),
+            "Dropping an isolated runtime freed up the current runtime's slot"
+        );
+    }
+
+    #[test]
+    fn test_set_tick_time_allocation() {
+        init_test();
+        Builder::new().tick_time_allocation(0.9).apply();
+        let runtime_allocation = || with_runtime(|runtime| runtime.tick_time_allocation());
+        assert_eq!(0.9, runtime_allocation());
+
+        let handle = spawn(async move {});
+        TIME_USED.with_borrow_mut(|t| *t = 0.6);
+        with_runtime(|runtime| runtime.set_tick_time_allocation(0.5));
+        assert_eq!(0.5, runtime_allocation());
+        assert!(crate::run().unwrap().budget_exhausted);
+        assert!(!handle.is_finished());
+
+        with_runtime(|runtime| runtime.set_tick_time_allocation(0.7));
+        assert!(!crate::run().unwrap().budget_exhausted);
+        assert!(handle.is_finished());
+    }
+
+    #[rstest]
+    #[case::zero(0.0)]
+    #[case::negative(-0.5)]
+    #[case::above_one(1.5)]
+    #[case::nan(f64::NAN)]
+    #[should_panic(expected = "Tick time allocation must be in (0.0, 1.0]")]
+    fn test_set_tick_time_allocation_validates(#[case] allocation: f64) {
+        init_test();
+        with_runtime(|runtime| runtime.set_tick_time_allocation(allocation));
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_spawn_named_enters_span() {
+        use std::sync::Mutex;
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata};
+
+        /// Logs span enters and exits, and events
+        #[derive(Default)]
+        struct Recorder {
+            names: Mutex<Vec<String>>,
+            log: Arc<Mutex<Vec<String>>>,
+        }
+
+        impl tracing::Subscriber for Recorder {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                struct Name<'a>(&'a mut String);
+                impl tracing::field::Visit for Name<'_> {
+                    fn record_debug(
+                        &mut self,
+                        _: &tracing::field::Field,
+                        value: &dyn std::fmt::Debug,
+                    ) {
+                        *self.0 = format!("{value:?}");
+                    }
+                }
+
+                let mut name = String::new();
+                span.record(&mut Name(&mut name));
+                let mut names = self.names.lock().unwrap();
+                names.push(name);
+                Id::from_u64(names.len() as u64)
+            }
+
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+            fn event(&self, _: &Event<'_>) {
+                self.log.lock().unwrap().push("event".to_string());
+            }
+
+            fn enter(&self, span: &Id) {
+                let name = self.names.lock().unwrap()[span.into_u64() as usize - 1].clone();
+                self.log.lock().unwrap().push(format!("enter {name}"));
+            }
+
+            fn exit(&self, _: &Id) {
+                self.log.lock().unwrap().push("exit".to_string());
+            }
+        }
+
+        let recorder = Recorder::default();
+        let log = recorder.log.clone();
+        tracing::subscriber::with_default(recorder, || {
+            init_test();
+            crate::spawn_named("harvester", async move {
+                tracing::info!("first poll");
+                yield_now().await;
+                tracing::info!("second poll");
+            })
+            .detach();
+            crate::run().unwrap();
+        });
+
+        let mut expected = ["enter \"harvester\"", "event", "exit"].repeat(2);
+        // The span is also entered while the finished future is dropped
+        expected.extend(["enter \"harvester\"", "exit"]);
+        assert_eq!(expected, *log.lock().unwrap());
+    }
+
+    #[test]
+    fn test_min_bucket() {
+        init_test();
+        Builder::new().min_bucket(1000).apply();
+        BUCKET.with_borrow_mut(|b| *b = 999);
+
+        let handle = crate::spawn_with_priority(Priority::High, async move {});
+        crate::run().unwrap();
+        assert!(!handle.is_finished(), "Task was polled below min_bucket");
+
+        BUCKET.with_borrow_mut(|b| *b = 1000);
+        crate::run().unwrap();
+        assert!(handle.is_finished());
+
+        // Timers still fire below min_bucket
+        let handle = spawn(crate::time::delay(1));
+        crate::run().unwrap();
+        GAME_TIME.with_borrow_mut(|t| *t += 1);
+        BUCKET.with_borrow_mut(|b| *b = 0);
+        crate::run().unwrap();
+        let timers_empty =
+            with_runtime(|runtime| runtime.timers.borrow().ticks.values().all(Vec::is_empty));
+        assert!(timers_empty, "Timer was not fired below min_bucket");
+        assert!(!handle.is_finished());
+    }
+
+    #[test]
+    fn test_delay_zero_fires_same_tick() {
+        init_test();
+
+        let handle = spawn(async move {
+            for _ in 0..3 {
+                crate::time::delay(0).await;
+            }
+        });
+
+        crate::run().unwrap();
+        assert!(
+            handle.is_finished(),
+            "delay(0) registered mid-tick did not fire"
+        );
+        assert_eq!(0, with_runtime(ScreepsRuntime::timer_count));
+    }
+
+    #[test]
+    fn test_timer_bucket_capacity() {
+        init_test();
+        Builder::new().timer_bucket_capacity(4).apply();
+        spawn(async move {
+            loop {
+                crate::time::delay(1).await;
+            }
+        })
+        .detach();
+
+        // The list the timer is registered in each tick, and its capacity
+        let mut buckets = Vec::new();
+        for _ in 0..50 {
+            tick().unwrap();
+            buckets.push(with_runtime(|runtime| {
+                let timers = runtime.timers.borrow();
+                let entries = timers.ticks.values().next().unwrap();
+                (entries.as_ptr(), entries.capacity())
+            }));
+        }
+
+        buckets.dedup();
+        assert_eq!(1, buckets.len(), "Timer lists were reallocated");
+        assert!(buckets[0].1 >= 4, "Timer list wasn't pre-allocated");
+    }
+
+    #[test]
+    fn test_wake_timers_fires_only_due() {
+        init_test();
+
+        let fired = Rc::new(RefCell::new(Vec::new()));
+        for (i, when) in [3, 1, 3, 2].into_iter().enumerate() {
+            let fired = fired.clone();
+            spawn(async move {
+                crate::time::delay_until(when).await;
+                fired.borrow_mut().push(i);
+            })
+            .detach();
+        }
+        crate::run().unwrap();
+
+        let mut timers_fired = Vec::new();
+        for _ in 0..4 {
+            GAME_TIME.with_borrow_mut(|t| *t += 1);
+            timers_fired.push(crate::run().unwrap().timers_fired);
+        }
+
+        assert_eq!(vec![1, 1, 2, 0], timers_fired);
+        assert_eq!(vec![1, 3, 0, 2], fired.take());
+        assert_eq!(0, with_runtime(ScreepsRuntime::timer_count));
+    }
+
+    #[test]
+    fn test_wake_timers_does_not_allocate() {
+        init_test();
+        Builder::new().timer_bucket_capacity(100).apply();
+        for i in 0..200 {
+            spawn(async move {
+                loop {
+                    crate::time::delay(1 + i % 2).await;
+                }
+            })
+            .detach();
+        }
+        // Warm up, so that the run queues and timer lists have grown to fit
+        for _ in 0..4 {
+            tick().unwrap();
+        }
+        // Skip a tick, so that timers from more than one tick are due
+        GAME_TIME.with_borrow_mut(|t| *t += 1);
+
+        let (fired, allocated) = with_runtime(|runtime| {
+            let before = allocations();
+            let fired = runtime.wake_timers();
+            (fired, allocations() - before)
+        });
+        assert_eq!(200, fired);
+        assert_eq!(0, allocated, "Firing timers allocated");
+    }
+
+    #[test]
+    fn test_waker_registers_timer_while_firing() {
+        use std::pin::pin;
+        use std::sync::Arc;
+        use std::task::{Context, Wake};
+
+        /// A waker that immediately registers a new timer when woken
+        struct RegisteringWaker;
+
+        impl Wake for RegisteringWaker {
+            fn wake(self: Arc<Self>) {
+                let mut delay = Box::pin(crate::time::delay(1));
+                let _ = delay.as_mut().poll(&mut Context::from_waker(Waker::noop()));
+                // Leak the delay so its timer stays registered
+                std::mem::forget(delay);
+            }
+        }
+
+        init_test();
+
+        let waker = Waker::from(Arc::new(RegisteringWaker));
+        let mut delay = pin!(crate::time::delay(0));
+        assert!(delay
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending());
+
+        crate::run().unwrap();
+
+        assert_eq!(1, with_runtime(ScreepsRuntime::timer_count));
+    }
+
+    #[test]
+    fn test_woken_task_registers_new_delay() {
+        init_test();
+
+        let handle = spawn(async move {
+            for _ in 0..3 {
+                crate::time::delay(0).await;
+            }
+        });
+
+        while !handle.is_finished() {
+            tick().unwrap();
+        }
+
+        assert_eq!(0, with_runtime(ScreepsRuntime::timer_count));
+    }
+
+    #[test]
+    fn test_run_blocking_ready() {
+        init_test();
+
+        let value = 5;
+        let res = with_runtime(|runtime| runtime.run_blocking(async { value + 2 }));
+
+        assert_eq!(7, res);
+    }
+
+    #[test]
+    fn test_run_blocking_yield_now() {
+        init_test();
+
+        let has_run = Rc::new(OnceCell::new());
+        {
+            let has_run = has_run.clone();
+            spawn(async move {
+                has_run.set(()).unwrap();
+            })
+            .detach();
+        }
+
+        let res = with_runtime(|runtime| {
+            runtime.run_blocking(async {
+                yield_now().await;
+                has_run.get().is_some()
+            })
+        });
+
+        assert!(res, "Scheduled task was not polled while blocking");
+    }
+
+    #[test]
+    fn test_run_blocking_delay() {
+        init_test();
+
+        with_runtime(|runtime| runtime.run_blocking(crate::time::delay(5)));
+
+        assert_eq!(0, game_time());
+        assert_eq!(0, with_runtime(ScreepsRuntime::timer_count));
+    }
+
+    #[test]
+    #[should_panic(expected = "run_blocking deadlocked")]
+    fn test_run_blocking_deadlock() {
+        init_test();
+
+        let (_tx, rx) = crate::sync::oneshot::channel::<()>();
+        let _ = with_runtime(|runtime| runtime.run_blocking(rx));
+    }
+
+    #[test]
+    fn test_run_entrypoint_delay() {
+        init_test();
+
+        let (res, spawned) = crate::run_entrypoint(async move {
+            let spawned = spawn(crate::time::delay(1));
+            crate::time::delay(5).await;
+            crate::time::yield_tick().await;
+            (game_time(), spawned)
+        })
+        .unwrap();
+
+        assert_eq!(0, res);
+        assert!(
+            !spawned.is_finished(),
+            "Spawned task's delay was fast-forwarded"
+        );
+        assert!(!with_runtime(|runtime| runtime.fast_forward.get()));
+    }
+
+    #[test]
+    fn test_run_entrypoint_uses_whole_tick() {
+        init_test();
+        TIME_USED.with_borrow_mut(|t| *t = 0.95);
+
+        assert_eq!(Ok(3), crate::run_entrypoint(async move { 1 + 2 }));
+        assert_eq!(Err(OutOfTime), crate::block_on(async move { 1 + 2 }));
+    }
+
+    #[test]
+    fn test_time_remaining() {
+        init_test();
+        GAME_TIME.with_borrow_mut(|t| *t = 7);
+
+        let handle = spawn(async move {
+            let mut seen = vec![crate::time_remaining()];
+            TIME_USED.with_borrow_mut(|t| *t = 0.45);
+            seen.push(crate::time_remaining());
+            TIME_USED.with_borrow_mut(|t| *t = 2.0);
+            seen.push(crate::time_remaining());
+            TIME_USED.with_borrow_mut(|t| *t = 0.0);
+            (crate::current_tick(), seen)
+        });
+        crate::run().unwrap();
+
+        let (tick, seen) = crate::block_on(handle).unwrap().unwrap();
+        assert_eq!(7, tick);
+        assert_eq!(vec![1.0, 0.5, 0.0], seen);
     }
 
-    /// Attempts to poll the next scheduled task, ensuring that there is time left in the tick
-    ///
-    /// Returns [Ok(true)] if a task was successfully polled
-    /// Returns [Ok(false)] if there are no tasks ready to poll
-    /// Returns [Err] if we have run out of allocated time this tick
-    pub(crate) fn try_poll_scheduled(&self) -> Result<bool, RuntimeError> {
-        if time_used() > self.config.tick_time_allocation {
-            return Err(RuntimeError::OutOfTime);
-        }
+    #[test]
+    #[should_panic(expected = "time_remaining must be called from within a task")]
+    fn test_time_remaining_outside_task() {
+        init_test();
 
-        if let Ok(runnable) = self.scheduled.try_recv() {
-            runnable.run();
-            Ok(true)
-        } else {
-            Ok(false)
-        }
+        crate::time_remaining();
     }
 
-    fn wake_timers(&self) {
-        let game_time = game_time();
-        let mut timers = self.timers.try_lock().unwrap();
+    #[test]
+    fn test_try_new_second_runtime() {
+        init_test();
 
-        let to_fire = {
-            // Grab timers that are still in the future. `timers` is now all timers that need firing
-            let mut pending = timers.split_off(&(game_time + 1));
-            // Switcheroo pending/timers so that `timers` is all timers that are scheduled in the future
-            std::mem::swap(&mut pending, &mut timers);
-            pending
-        };
+        assert_eq!(
+            Some(RuntimeExistsError),
+            Builder::new().try_build().err(),
+            "Created a second runtime while the current one exists"
+        );
 
-        to_fire
-            .into_values()
-            .flatten()
-            .flatten()
-            .for_each(Waker::wake);
+        drop(CURRENT.with_borrow_mut(Option::take));
+        let first = Builder::new().try_build().unwrap();
+        assert!(ScreepsRuntime::try_new(Config::default()).is_err());
+
+        drop(first);
+        assert!(Builder::new().try_build().is_ok());
     }
-}
 
-type TimerMap = BTreeMap<u32, Vec<Option<Waker>>>;
+    #[test]
+    #[should_panic(expected = "Cannot create a second ScreepsRuntime")]
+    fn test_new_second_runtime_panics() {
+        init_test();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::error::RuntimeError::OutOfTime;
-    use crate::tests::*;
-    use crate::time::yield_now;
-    use crate::{spawn, with_runtime};
-    use std::cell::OnceCell;
+        Builder::new().build();
+    }
+
+    #[rstest]
+    #[case::equal(1, 1, 20, 20)]
+    #[case::weighted(3, 1, 30, 10)]
+    fn test_task_group_weights(
+        #[case] weight_a: u32,
+        #[case] weight_b: u32,
+        #[case] expected_a: u32,
+        #[case] expected_b: u32,
+    ) {
+        init_test();
+        Builder::new().max_polls_per_tick(40).apply();
+
+        let handle = with_runtime(ScreepsRuntime::handle);
+        let polls = [Rc::new(Cell::new(0)), Rc::new(Cell::new(0))];
+        for (weight, polls) in [weight_a, weight_b].into_iter().zip(&polls) {
+            let group = handle.group(weight).unwrap();
+            for _ in 0..100 {
+                let polls = polls.clone();
+                group
+                    .spawn(async move { polls.set(polls.get() + 1) })
+                    .unwrap()
+                    .detach();
+            }
+        }
+
+        crate::run().unwrap();
+
+        assert_eq!([expected_a, expected_b], polls.map(|p| p.get()));
+    }
 
     #[test]
-    fn test_block_on() {
+    fn test_abandoned_task_group_is_removed() {
         init_test();
 
-        let res = crate::block_on(async move {
-            yield_now().await;
-            1 + 2
-        })
-        .unwrap();
+        let group = with_runtime(ScreepsRuntime::handle).group(2).unwrap();
+        let task = group.spawn(async move {}).unwrap();
+        drop(group);
+        assert_eq!(2, with_runtime(|runtime| runtime.groups.borrow().len()));
 
-        assert_eq!(3, res);
+        crate::run().unwrap();
+        assert!(task.is_finished());
+        // The finished task still references its group until its handle is dropped
+        drop(task);
+
+        crate::run().unwrap();
+        assert_eq!(1, with_runtime(|runtime| runtime.groups.borrow().len()));
     }
 
     #[test]
-    fn test_spawn() {
+    fn test_handle_spawn() {
         init_test();
 
-        drop(spawn(async move {}));
+        let handle = with_runtime(ScreepsRuntime::handle);
+        let task = handle.clone().spawn(async move { 1 + 2 }).unwrap();
+        let delayed = handle.spawn_after(2, async move { 3 + 4 }).unwrap();
 
-        with_runtime(|runtime| {
-            runtime
-                .scheduled
-                .try_recv()
-                .expect("Failed to schedule task");
-        })
+        tick().unwrap();
+        assert!(task.is_finished());
+        assert!(!delayed.is_finished());
+
+        tick().unwrap();
+        tick().unwrap();
+        assert!(delayed.is_finished());
+
+        assert_eq!(3, crate::block_on(task).unwrap().unwrap());
+        assert_eq!(7, crate::block_on(delayed).unwrap().unwrap());
     }
 
     #[test]
-    fn test_run() {
+    fn test_spawn_after() {
         init_test();
 
         let has_run = Rc::new(OnceCell::new());
-        {
+        let handle = {
             let has_run = has_run.clone();
+            crate::spawn_after(3, async move {
+                has_run.set(game_time()).unwrap();
+                1 + 2
+            })
+        };
+
+        // The task waits on the timers without being scheduled for its first poll
+        let stats = with_runtime(ScreepsRuntime::stats);
+        assert_eq!(
+            0, stats.pending_count,
+            "Task was scheduled before target tick"
+        );
+        assert_eq!(1, stats.timer_count);
+
+        for _ in 0..3 {
+            tick().unwrap();
+            assert!(has_run.get().is_none(), "Task ran before target tick");
+            let polled = with_runtime(|runtime| runtime.metrics().polled);
+            assert_eq!(0, polled, "Task was polled before target tick");
+        }
+
+        tick().unwrap();
+        assert_eq!(Some(&3), has_run.get());
+        assert_eq!(3, crate::block_on(handle).unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_spawn_after_isolated() {
+        crate::testing::reset();
+        let time = Rc::new(Cell::new(10));
+        let runtime = Builder::new()
+            .clock(SimClock(time.clone()))
+            .build_isolated();
+
+        let handle = runtime.handle();
+        let task = runtime.spawn_after(3, async move { handle.game_time() });
+        for now in 10..13 {
+            time.set(now);
+            runtime.run().unwrap();
+            assert!(!task.is_finished(), "Task ran before target tick");
+        }
+
+        time.set(13);
+        runtime.run().unwrap();
+        assert_eq!(13, runtime.run_blocking(task).unwrap());
+    }
+
+    #[test]
+    fn test_handle_after_runtime_dropped() {
+        init_test();
+
+        let handle = with_runtime(ScreepsRuntime::handle);
+        crate::CURRENT.with_borrow_mut(|runtime| *runtime = None);
+
+        let result = handle.spawn(async move {});
+        assert!(matches!(result, Err(RuntimeError::Dropped)));
+    }
+
+    #[test]
+    fn test_max_scheduled() {
+        use std::sync::atomic::AtomicUsize;
+
+        init_test();
+        let overflows = Arc::new(AtomicUsize::new(0));
+        {
+            let overflows = overflows.clone();
+            Builder::new()
+                .max_scheduled(2)
+                .on_schedule_overflow(move |priority| {
+                    assert_eq!(Priority::Normal, priority);
+                    overflows.fetch_add(1, Ordering::Relaxed);
+                })
+                .apply();
+        }
+
+        let handles: Vec<_> = (0..3).map(|i| spawn(async move { i })).collect();
+        assert_eq!(1, overflows.load(Ordering::Relaxed));
+
+        // Other priorities have their own queue
+        let high = crate::spawn_with_priority(Priority::High, async move {});
+        assert_eq!(1, overflows.load(Ordering::Relaxed));
+
+        crate::run().unwrap();
+        assert!(high.is_finished());
+
+        let results: Vec<_> = handles
+            .into_iter()
+            .map(|handle| crate::block_on(handle).unwrap().ok())
+            .collect();
+        assert_eq!(vec![Some(0), Some(1), None], results);
+    }
+
+    #[test]
+    fn test_max_polls_per_tick() {
+        init_test();
+        Builder::new().max_polls_per_tick(3).apply();
+
+        let polled = Rc::new(Cell::new(0));
+        for _ in 0..10 {
+            let polled = polled.clone();
             spawn(async move {
-                has_run.set(()).unwrap();
+                polled.set(polled.get() + 1);
             })
             .detach();
         }
 
-        // task hasn't run yet
-        assert!(has_run.get().is_none());
+        crate::run().unwrap();
+        assert_eq!(3, polled.get());
+        assert_eq!(7, with_runtime(ScreepsRuntime::pending_count));
 
         crate::run().unwrap();
+        assert_eq!(6, polled.get());
+    }
 
-        // Future has been run
-        assert!(has_run.get().is_some());
+    #[test]
+    fn test_stats() {
+        init_test();
+
+        for _ in 0..2 {
+            spawn(async move {}).detach();
+        }
+        crate::spawn_with_priority(Priority::High, async move {}).detach();
+        for ticks in [5, 3, 3] {
+            spawn(crate::time::delay(ticks)).detach();
+        }
+
+        let stats = with_runtime(ScreepsRuntime::stats);
+        assert_eq!(6, stats.pending_count);
+        assert_eq!(0, stats.timer_count);
+        assert_eq!(None, stats.next_timer_tick);
+
+        crate::run().unwrap();
+
+        let stats = with_runtime(ScreepsRuntime::stats);
+        assert_eq!(0, stats.pending_count);
+        assert_eq!(3, stats.timer_count);
+        assert_eq!(Some(3), stats.next_timer_tick);
+
+        with_runtime(|runtime| {
+            assert_eq!(stats.pending_count, runtime.pending_count());
+            assert_eq!(stats.timer_count, runtime.timer_count());
+            assert_eq!(stats.next_timer_tick, runtime.next_timer_tick());
+        });
     }
 
     #[test]
-    fn test_nested_spawn() {
+    fn test_has_work_idle() {
         init_test();
 
-        let has_run = Rc::new(OnceCell::new());
-        {
-            let has_run = has_run.clone();
-            spawn(async move {
-                let result = spawn(async move { 1 + 2 }).await;
+        crate::run().unwrap();
+        with_runtime(|runtime| {
+            assert!(!runtime.has_work());
+            assert_eq!(None, runtime.next_timer_tick());
+        });
+    }
 
-                assert_eq!(3, result);
+    #[test]
+    fn test_has_work() {
+        init_test();
 
-                has_run.set(()).unwrap();
+        spawn(crate::time::delay(2)).detach();
+        with_runtime(|runtime| {
+            assert!(runtime.has_work());
+            // Checking doesn't take the task off the queue
+            assert!(runtime.has_work());
+            assert_eq!(1, runtime.pending_count());
+        });
+
+        tick().unwrap();
+        with_runtime(|runtime| {
+            assert!(!runtime.has_work(), "Timer isn't due yet");
+            assert_eq!(Some(2), runtime.next_timer_tick());
+        });
+
+        GAME_TIME.with_borrow_mut(|t| *t = 2);
+        with_runtime(|runtime| assert!(runtime.has_work()));
+        crate::run().unwrap();
+        with_runtime(|runtime| {
+            assert!(!runtime.has_work());
+            assert_eq!(None, runtime.next_timer_tick());
+        });
+    }
+
+    #[test]
+    fn test_pause() {
+        init_test();
+
+        let delayed = spawn(crate::time::delay(1));
+        tick().unwrap();
+        with_runtime(ScreepsRuntime::pause);
+
+        let spawned = spawn(async move {});
+        for _ in 0..3 {
+            tick().unwrap();
+        }
+        assert!(!delayed.is_finished(), "Timer fired while paused");
+        assert!(!spawned.is_finished(), "Task was polled while paused");
+        with_runtime(|runtime| {
+            assert_eq!(1, runtime.pending_count());
+            assert_eq!(1, runtime.timer_count());
+            assert!(runtime.is_paused());
+            runtime.resume();
+        });
+
+        let report = crate::run().unwrap();
+        assert_eq!(
+            1, report.timers_fired,
+            "Overdue timer didn't fire on resume"
+        );
+        assert!(delayed.is_finished());
+        assert!(spawned.is_finished());
+    }
+
+    #[test]
+    fn test_spawn_with_budget() {
+        init_test();
+
+        let budgeted_polls = Rc::new(Cell::new(0));
+        {
+            let budgeted_polls = budgeted_polls.clone();
+            crate::spawn_with_budget(0.1, async move {
+                loop {
+                    budgeted_polls.set(budgeted_polls.get() + 1);
+                    TIME_USED.with_borrow_mut(|t| *t += 0.06);
+                    yield_now().await;
+                }
             })
             .detach();
         }
+        let other = spawn(async move {
+            for _ in 0..5 {
+                TIME_USED.with_borrow_mut(|t| *t += 0.01);
+                yield_now().await;
+            }
+        });
 
-        // task hasn't run yet
-        assert!(has_run.get().is_none());
+        crate::run().unwrap();
+        // The second poll takes the task over its budget, so it sits out the rest of the tick
+        assert_eq!(2, budgeted_polls.get());
+        assert!(
+            other.is_finished(),
+            "Other tasks stopped with the budgeted task"
+        );
 
+        // Next tick it gets a fresh budget
+        GAME_TIME.with_borrow_mut(|t| *t += 1);
+        TIME_USED.with_borrow_mut(|t| *t = 0.0);
         crate::run().unwrap();
+        assert_eq!(4, budgeted_polls.get());
+    }
 
-        // Future has been run
-        assert!(has_run.get().is_some());
+    #[rstest]
+    #[case(0.1, 1)]
+    #[case(0.9, 8)]
+    fn test_run_with_allocation(#[case] allocation: f64, #[case] expected_polls: usize) {
+        init_test();
+
+        for _ in 0..10 {
+            spawn(async move { TIME_USED.with_borrow_mut(|t| *t += 0.125) }).detach();
+        }
+
+        let configured = with_runtime(ScreepsRuntime::tick_time_allocation);
+        let report = with_runtime(|runtime| runtime.run_with_allocation(allocation)).unwrap();
+        assert_eq!(expected_polls, report.polled);
+        assert!(report.budget_exhausted);
+        assert_eq!(
+            configured,
+            with_runtime(ScreepsRuntime::tick_time_allocation)
+        );
     }
 
     #[test]
-    fn test_respects_time_remaining() {
+    #[should_panic(expected = "Tick time allocation must be in (0.0, 1.0]")]
+    fn test_run_with_allocation_out_of_range() {
         init_test();
+        with_runtime(|runtime| runtime.run_with_allocation(1.5)).unwrap();
+    }
 
-        let has_run = Rc::new(OnceCell::new());
+    #[test]
+    fn test_metrics() {
+        init_test();
+        Builder::new().on_task_panic(drop).apply();
+
+        spawn(async move {
+            crate::time::delay(1).await;
+            yield_now().await;
+        })
+        .detach();
+        spawn(async move { panic!("task failed") }).detach();
+        let aborted = spawn(crate::time::delay(5));
+
+        tick().unwrap();
+        aborted.abort();
+        tick().unwrap();
+
+        let metrics = with_runtime(ScreepsRuntime::metrics);
+        assert_eq!(3, metrics.tasks_spawned);
+        assert_eq!(1, metrics.tasks_completed);
+        assert_eq!(1, metrics.tasks_aborted);
+        assert_eq!(1, metrics.tasks_panicked);
+        assert_eq!(1, metrics.timers_fired);
+        // Three polls of the delayed task, one of the panicking task, then the aborted task is
+        // polled once more to resolve the abort
+        assert_eq!(6, metrics.polled);
+
+        // Unlike stats, the totals don't reset once the work is done
+        tick().unwrap();
+        assert_eq!(metrics, with_runtime(ScreepsRuntime::metrics));
+    }
+
+    #[test]
+    fn test_task_panic_is_caught() {
+        init_test();
+
+        let panics = Rc::new(RefCell::new(Vec::new()));
         {
+            let panics = panics.clone();
+            Builder::new()
+                .on_task_panic(move |payload| {
+                    let msg = payload.downcast_ref::<&str>().unwrap().to_string();
+                    panics.borrow_mut().push(msg);
+                })
+                .apply();
+        }
+
+        spawn(async move {
+            yield_now().await;
+            panic!("task failed");
+        })
+        .detach();
+        let normal = spawn(async move {
+            yield_now().await;
+            1 + 2
+        });
+        let joined = spawn(async move { panic!("joined task failed") });
+
+        crate::run().unwrap();
+
+        // The panic of a task that still has its JoinHandle is returned from the handle instead
+        assert_eq!(vec!["task failed"], panics.take());
+
+        let result = crate::block_on(normal).unwrap();
+        assert_eq!(3, result.unwrap());
+        assert!(crate::block_on(joined).unwrap().unwrap_err().is_panic());
+        assert_eq!(2, with_runtime(ScreepsRuntime::metrics).tasks_panicked);
+    }
+
+    /// Spawn a task waiting on a timer, a task that panics after yielding, and a task scheduled
+    /// behind the panicking one, returning the handles of the other two tasks
+    fn spawn_around_panic() -> (JoinHandle<u32>, JoinHandle<u32>) {
+        let delayed = spawn(async move {
+            crate::time::delay(1).await;
+            crate::current_tick()
+        });
+        spawn(async move {
+            yield_now().await;
+            panic!("task failed");
+        })
+        .detach();
+        let after = spawn(async move {
+            yield_now().await;
+            crate::current_tick()
+        });
+        (delayed, after)
+    }
+
+    #[test]
+    fn test_panic_policy_continue() {
+        init_test();
+        let (delayed, after) = spawn_around_panic();
+
+        crate::run().unwrap();
+        assert!(after.is_finished(), "Tick stopped at the panic");
+
+        GAME_TIME.with_borrow_mut(|t| *t += 1);
+        crate::run().unwrap();
+        assert_eq!(1, crate::block_on(delayed).unwrap().unwrap());
+        assert_eq!(0, crate::block_on(after).unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_panic_policy_abort() {
+        init_test();
+        Builder::new().panic_policy(PanicPolicy::Abort).apply();
+        let (delayed, after) = spawn_around_panic();
+
+        let res = panic::catch_unwind(crate::run);
+        assert!(res.is_err(), "Panic didn't unwind out of run");
+        assert!(!after.is_finished());
+
+        // The runtime picks up where it left off once the panic has been caught
+        GAME_TIME.with_borrow_mut(|t| *t += 1);
+        crate::run().unwrap();
+        assert_eq!(1, crate::block_on(delayed).unwrap().unwrap());
+        assert_eq!(1, crate::block_on(after).unwrap().unwrap());
+
+        // A panic unwinding out of block_on doesn't break later blocks either
+        let res = panic::catch_unwind(|| crate::block_on(async { panic!("entrypoint failed") }));
+        assert!(res.is_err());
+        assert_eq!(Ok(3), crate::block_on(async { 1 + 2 }));
+    }
+
+    #[test]
+    fn test_shutdown() {
+        init_test();
+
+        let has_run = Rc::new(OnceCell::new());
+        let handle = {
             let has_run = has_run.clone();
             spawn(async move {
+                yield_now().await;
                 has_run.set(()).unwrap();
             })
-            .detach();
-        }
+        };
+        let delayed = spawn(crate::time::delay(5));
 
-        TIME_USED.with_borrow_mut(|t| *t = 0.95);
+        with_runtime(ScreepsRuntime::shutdown);
 
-        // task hasn't run yet
-        assert!(has_run.get().is_none());
+        assert!(handle.is_finished());
+        assert!(has_run.get().is_some(), "Scheduled task was not run");
+        assert!(delayed.is_finished(), "Delayed task was not cancelled");
+        assert_eq!(0, with_runtime(ScreepsRuntime::timer_count));
 
-        assert_eq!(Err(OutOfTime), crate::run());
+        let late = spawn(async move {});
+        crate::run().unwrap();
 
-        // Check future still hasn't run
-        assert!(has_run.get().is_none());
+        let result = crate::block_on(async move {});
+        assert!(matches!(result, Err(RuntimeError::ShutDown)));
+        assert!(
+            late.is_finished(),
+            "Task spawned after shutdown was scheduled"
+        );
+    }
+
+    #[test]
+    fn test_shutdown_with_yield_loop() {
+        init_test();
+
+        let endless = spawn(async move {
+            loop {
+                yield_now().await;
+            }
+        });
+        let finite = spawn(async move {
+            for _ in 0..3 {
+                yield_now().await;
+            }
+        });
+
+        with_runtime(ScreepsRuntime::shutdown);
+
+        assert!(finite.is_finished(), "Task was cancelled before finishing");
+        assert!(endless.is_finished(), "Endless task was not cancelled");
+        assert_eq!(0, with_runtime(ScreepsRuntime::pending_count));
+    }
+
+    #[test]
+    fn test_shutdown_timeout() {
+        init_test();
+
+        let quick = spawn(crate::time::delay(1));
+        let slow = spawn(crate::time::delay(5));
+
+        with_runtime(|runtime| runtime.shutdown_timeout(2));
+
+        for _ in 0..3 {
+            tick().unwrap();
+        }
+
+        assert!(quick.is_finished());
+        assert!(slow.is_finished(), "Slow task was not cancelled");
+        assert_eq!(0, with_runtime(ScreepsRuntime::timer_count));
     }
 }
@@ -0,0 +1,1380 @@
+//! Utilities for working with [Stream]s
+
+use crate::time::{delay, Delay};
+use futures_core::Stream;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+/// A set of futures that are driven together, yielding their outputs in the order they complete
+///
+/// Unlike [spawning](crate::spawn) each future as its own task, the futures stay grouped together
+/// and are only polled while the [FuturesUnordered] itself is polled. Each future has its own
+/// waker, so only the futures that have been woken are polled again.
+///
+/// Futures can be [pushed](FuturesUnordered::push) at any time, including between polls while
+/// other futures are still pending. The [Stream] yields [None] once it is empty.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::stream::FuturesUnordered;
+/// # use screeps_async::time::delay;
+/// screeps_async::spawn(async move {
+///     let mut creeps = FuturesUnordered::new();
+///     for ticks in [10, 5, 20] {
+///         creeps.push(async move {
+///             delay(ticks).await;
+///             ticks
+///         });
+///     }
+///
+///     while let Some(ticks) = creeps.next().await {
+///         // respawn the creep that finished
+///     }
+/// }).detach();
+/// ```
+pub struct FuturesUnordered<F> {
+    /// Slots holding the futures, [None] if the slot is free
+    futures: Vec<Option<Pin<Box<F>>>>,
+    /// The waker for each slot, reused along with the slot
+    wakers: Vec<Arc<MemberWaker>>,
+    /// Indices of free slots in [Self::futures]
+    free: Vec<usize>,
+    shared: Arc<Shared>,
+}
+
+/// State shared between a [FuturesUnordered] and the wakers of each of its futures
+#[derive(Default)]
+struct Shared {
+    /// Slots whose futures have been woken and are waiting to be polled
+    ready: Mutex<VecDeque<usize>>,
+    /// Waker of the task polling the [FuturesUnordered]
+    waker: Mutex<Option<Waker>>,
+}
+
+struct MemberWaker {
+    index: usize,
+    /// Whether this slot is already in [Shared::ready], so it is only polled once per wake
+    queued: AtomicBool,
+    shared: Arc<Shared>,
+}
+
+impl Wake for MemberWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if self.queued.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        self.shared.ready.lock().unwrap().push_back(self.index);
+        let waker = self.shared.waker.lock().unwrap().take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<F: Future> FuturesUnordered<F> {
+    /// Construct an empty [FuturesUnordered]
+    pub fn new() -> Self {
+        Self {
+            futures: Vec::new(),
+            wakers: Vec::new(),
+            free: Vec::new(),
+            shared: Arc::new(Shared::default()),
+        }
+    }
+
+    /// Add a future to the set. It is polled the next time the set is polled
+    pub fn push(&mut self, future: F) {
+        let future = Some(Box::pin(future));
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.futures[index] = future;
+                index
+            }
+            None => {
+                let index = self.futures.len();
+                self.futures.push(future);
+                self.wakers.push(Arc::new(MemberWaker {
+                    index,
+                    queued: AtomicBool::new(false),
+                    shared: self.shared.clone(),
+                }));
+                index
+            }
+        };
+
+        // New futures haven't been polled yet, so they start out ready
+        self.wakers[index].wake_by_ref();
+    }
+
+    /// The number of futures that haven't completed yet
+    pub fn len(&self) -> usize {
+        self.futures.len() - self.free.len()
+    }
+
+    /// Whether there are no futures left in the set
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Wait for the next future in the set to complete, returning its output.
+    ///
+    /// Returns [None] if the set is empty
+    pub async fn next(&mut self) -> Option<F::Output> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    /// Iterate over the futures that haven't completed yet
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &F> {
+        self.futures
+            .iter()
+            .flatten()
+            .map(|future| future.as_ref().get_ref())
+    }
+}
+
+impl<F: Future> Default for FuturesUnordered<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F: Future> FromIterator<F> for FuturesUnordered<F> {
+    fn from_iter<I: IntoIterator<Item = F>>(iter: I) -> Self {
+        let mut set = Self::new();
+        iter.into_iter().for_each(|future| set.push(future));
+        set
+    }
+}
+
+// Futures are boxed, so the set itself never needs to be pinned
+impl<F> Unpin for FuturesUnordered<F> {}
+
+impl<F: Future> Stream for FuturesUnordered<F> {
+    type Item = F::Output;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.is_empty() {
+            return Poll::Ready(None);
+        }
+
+        self.shared
+            .waker
+            .lock()
+            .unwrap()
+            .replace(cx.waker().clone());
+
+        // Only poll the futures that were ready when we started, so a future that wakes itself
+        // while being polled can't keep us here forever
+        let budget = self.shared.ready.lock().unwrap().len();
+        for _ in 0..budget {
+            let Some(index) = self.shared.ready.lock().unwrap().pop_front() else {
+                break;
+            };
+
+            let member = self.wakers[index].clone();
+            member.queued.store(false, Ordering::Relaxed);
+            let Some(future) = &mut self.futures[index] else {
+                continue; // Woken after it completed
+            };
+
+            let waker = Waker::from(member);
+            if let Poll::Ready(output) = future.as_mut().poll(&mut Context::from_waker(&waker)) {
+                self.futures[index] = None;
+                self.free.push(index);
+                return Poll::Ready(Some(output));
+            }
+        }
+
+        if !self.shared.ready.lock().unwrap().is_empty() {
+            // Some futures were woken while polling, come back for them
+            cx.waker().wake_by_ref();
+        }
+
+        Poll::Pending
+    }
+}
+
+/// What a [Throttle] does with items that arrive while it is suppressing output
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+pub enum ThrottlePolicy {
+    /// Hold on to the most recent item, yielding it once the throttle window ends.
+    /// Older items that arrived during the window are discarded
+    #[default]
+    Latest,
+    /// Discard every item that arrives during the throttle window
+    Drop,
+}
+
+/// A [Stream] that yields at most one item every `min_period` ticks. Created by [throttle]
+pub struct Throttle<S: Stream> {
+    stream: S,
+    min_period: u32,
+    policy: ThrottlePolicy,
+    /// Ends the current throttle window, [None] if items can be yielded straight away
+    window: Option<Delay>,
+    /// The item held back by [ThrottlePolicy::Latest]
+    held: Option<S::Item>,
+    /// Whether the inner stream has finished
+    done: bool,
+}
+
+impl<S: Stream> Throttle<S> {
+    /// Set what happens to items that arrive during the throttle window.
+    /// Defaults to [ThrottlePolicy::Latest]
+    pub fn policy(mut self, policy: ThrottlePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// The minimum number of ticks between each item
+    pub fn min_period(&self) -> u32 {
+        self.min_period
+    }
+
+    /// Wait for the next item, returning [None] once the inner stream has finished
+    pub async fn next(&mut self) -> Option<S::Item>
+    where
+        Self: Unpin,
+    {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl<S: Stream> Stream for Throttle<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // SAFETY: `stream` is never moved out of `self`, so it stays pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut stream = unsafe { Pin::new_unchecked(&mut this.stream) };
+
+        if let Some(window) = &mut this.window {
+            // Keep pulling from the inner stream so items are held or dropped as they arrive
+            // rather than piling up behind us
+            while !this.done {
+                match stream.as_mut().poll_next(cx) {
+                    Poll::Ready(Some(item)) => {
+                        if this.policy == ThrottlePolicy::Latest {
+                            this.held = Some(item);
+                        }
+                    }
+                    Poll::Ready(None) => this.done = true,
+                    Poll::Pending => break,
+                }
+            }
+
+            if this.done && this.held.is_none() {
+                return Poll::Ready(None);
+            }
+            if Pin::new(window).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.window = None;
+
+            if let Some(item) = this.held.take() {
+                this.window = Some(delay(this.min_period));
+                return Poll::Ready(Some(item));
+            }
+        }
+
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        match stream.poll_next(cx) {
+            Poll::Ready(Some(item)) => {
+                this.window = Some(delay(this.min_period));
+                Poll::Ready(Some(item))
+            }
+            Poll::Ready(None) => {
+                this.done = true;
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Throttle `stream` so that, after yielding an item, no further items are yielded until
+/// `min_period` ticks have passed.
+///
+/// Items that arrive during the throttle window are handled according to the
+/// [policy](Throttle::policy): by default the latest one is held and yielded once the window ends.
+/// The inner stream finishing ends the [Throttle] as soon as there is no held item left to yield.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::stream::{throttle, ThrottlePolicy};
+/// let (tx, rx) = screeps_async::sync::mpsc::unbounded::<u32>();
+/// screeps_async::spawn(async move {
+///     // process at most one construction request per tick
+///     let mut requests = throttle(rx, 1).policy(ThrottlePolicy::Drop);
+///     while let Some(request) = requests.next().await {
+///         // place the construction site
+///     }
+/// }).detach();
+/// ```
+///
+/// # Panics
+///
+/// This function panics if `min_period` is zero
+pub fn throttle<S: Stream>(stream: S, min_period: u32) -> Throttle<S> {
+    assert!(min_period > 0, "Throttle period must be non-zero");
+    Throttle {
+        stream,
+        min_period,
+        policy: ThrottlePolicy::default(),
+        window: None,
+        held: None,
+        done: false,
+    }
+}
+
+/// A [Stream] that interleaves the items of two streams. Created by [merge]
+pub struct Merge<A, B> {
+    first: A,
+    second: B,
+    first_done: bool,
+    second_done: bool,
+    /// Whether to poll `second` before `first` next time, so neither is always favoured
+    second_first: bool,
+}
+
+impl<A, B> Merge<A, B>
+where
+    A: Stream,
+    B: Stream<Item = A::Item>,
+{
+    /// Wait for the next item, returning [None] once both streams have finished
+    pub async fn next(&mut self) -> Option<A::Item>
+    where
+        Self: Unpin,
+    {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+/// Poll `stream` unless it has already finished, marking it as `done` once it does
+fn poll_merged<S: Stream>(
+    stream: Pin<&mut S>,
+    done: &mut bool,
+    cx: &mut Context<'_>,
+) -> Option<S::Item> {
+    if *done {
+        return None;
+    }
+
+    match stream.poll_next(cx) {
+        Poll::Ready(Some(item)) => Some(item),
+        Poll::Ready(None) => {
+            *done = true;
+            None
+        }
+        Poll::Pending => None,
+    }
+}
+
+impl<A, B> Stream for Merge<A, B>
+where
+    A: Stream,
+    B: Stream<Item = A::Item>,
+{
+    type Item = A::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // SAFETY: neither stream is ever moved out of `self`, so they stay pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut first = unsafe { Pin::new_unchecked(&mut this.first) };
+        let mut second = unsafe { Pin::new_unchecked(&mut this.second) };
+
+        let second_first = this.second_first;
+        this.second_first = !second_first;
+        let item = if second_first {
+            poll_merged(second.as_mut(), &mut this.second_done, cx)
+                .or_else(|| poll_merged(first.as_mut(), &mut this.first_done, cx))
+        } else {
+            poll_merged(first.as_mut(), &mut this.first_done, cx)
+                .or_else(|| poll_merged(second.as_mut(), &mut this.second_done, cx))
+        };
+
+        match item {
+            Some(item) => Poll::Ready(Some(item)),
+            None if this.first_done && this.second_done => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Merge two streams of the same item type into one, yielding items from either as they arrive.
+///
+/// The streams take turns being polled first, so a stream that always has an item ready can't
+/// starve the other. The merged stream only finishes once both streams have finished.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::stream::merge;
+/// enum Event {
+///     Hostile(u32),
+///     Construction(u32),
+/// }
+///
+/// let (hostiles_tx, hostiles) = screeps_async::sync::mpsc::unbounded::<Event>();
+/// let (sites_tx, sites) = screeps_async::sync::mpsc::unbounded::<Event>();
+/// screeps_async::spawn(async move {
+///     let mut events = merge(hostiles, sites);
+///     while let Some(event) = events.next().await {
+///         match event {
+///             Event::Hostile(id) => { /* defend */ }
+///             Event::Construction(id) => { /* send a builder */ }
+///         }
+///     }
+/// }).detach();
+/// ```
+pub fn merge<A, B>(first: A, second: B) -> Merge<A, B>
+where
+    A: Stream,
+    B: Stream<Item = A::Item>,
+{
+    Merge {
+        first,
+        second,
+        first_done: false,
+        second_done: false,
+        second_first: false,
+    }
+}
+
+/// A [Stream] that runs the futures produced by another stream, a bounded number at a time.
+/// Created by [buffer_unordered]
+pub struct BufferUnordered<S: Stream> {
+    stream: S,
+    in_flight: FuturesUnordered<S::Item>,
+    limit: usize,
+    /// Whether the inner stream has finished
+    done: bool,
+}
+
+impl<S> BufferUnordered<S>
+where
+    S: Stream,
+    S::Item: Future,
+{
+    /// The maximum number of futures run at once
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Wait for the next output, returning [None] once the inner stream has finished and every
+    /// future it produced has completed
+    pub async fn next(&mut self) -> Option<<S::Item as Future>::Output>
+    where
+        Self: Unpin,
+    {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl<S> Stream for BufferUnordered<S>
+where
+    S: Stream,
+    S::Item: Future,
+{
+    type Item = <S::Item as Future>::Output;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // SAFETY: `stream` is never moved out of `self`, so it stays pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut stream = unsafe { Pin::new_unchecked(&mut this.stream) };
+
+        // Top up the futures in flight before polling them, so new ones start right away
+        while !this.done && this.in_flight.len() < this.limit {
+            match stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(future)) => this.in_flight.push(future),
+                Poll::Ready(None) => this.done = true,
+                Poll::Pending => break,
+            }
+        }
+
+        match Pin::new(&mut this.in_flight).poll_next(cx) {
+            Poll::Ready(Some(output)) => Poll::Ready(Some(output)),
+            // Nothing in flight, so we're only done once the inner stream is too
+            Poll::Ready(None) if this.done => Poll::Ready(None),
+            Poll::Ready(None) | Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Run the futures produced by `stream`, with at most `limit` of them in flight at once, yielding
+/// their outputs in the order they complete.
+///
+/// A new future is only pulled from `stream` once one of the futures in flight has completed,
+/// which bounds how much work is started each tick. Once `stream` finishes, the futures still in
+/// flight are driven to completion before the [BufferUnordered] finishes too.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::stream::buffer_unordered;
+/// # use std::future::Future;
+/// # use std::pin::Pin;
+/// let (tx, rooms) = screeps_async::sync::mpsc::unbounded::<Pin<Box<dyn Future<Output = u32>>>>();
+/// screeps_async::spawn(async move {
+///     // compute at most 3 paths at a time
+///     let mut paths = buffer_unordered(rooms, 3);
+///     while let Some(length) = paths.next().await {
+///         // use the path
+///     }
+/// }).detach();
+/// ```
+///
+/// # Panics
+///
+/// This function panics if `limit` is zero
+pub fn buffer_unordered<S>(stream: S, limit: usize) -> BufferUnordered<S>
+where
+    S: Stream,
+    S::Item: Future,
+{
+    assert!(limit > 0, "buffer_unordered limit must be non-zero");
+    BufferUnordered {
+        stream,
+        in_flight: FuturesUnordered::new(),
+        limit,
+        done: false,
+    }
+}
+
+/// A future that runs a closure's future for each item of a stream, a bounded number at a time.
+/// Created by [for_each_concurrent]
+pub struct ForEachConcurrent<S, F, Fut> {
+    stream: S,
+    f: F,
+    in_flight: FuturesUnordered<Fut>,
+    limit: usize,
+    /// Whether the stream has finished
+    done: bool,
+}
+
+impl<S, F, Fut> Future for ForEachConcurrent<S, F, Fut>
+where
+    S: Stream,
+    F: FnMut(S::Item) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // SAFETY: `stream` is never moved out of `self`, so it stays pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut stream = unsafe { Pin::new_unchecked(&mut this.stream) };
+
+        loop {
+            // Start futures for new items before polling, so they start right away
+            while !this.done && this.in_flight.len() < this.limit {
+                match stream.as_mut().poll_next(cx) {
+                    Poll::Ready(Some(item)) => this.in_flight.push((this.f)(item)),
+                    Poll::Ready(None) => this.done = true,
+                    Poll::Pending => break,
+                }
+            }
+
+            match Pin::new(&mut this.in_flight).poll_next(cx) {
+                // A slot freed up, so try to fill it
+                Poll::Ready(Some(())) => continue,
+                Poll::Ready(None) if this.done => return Poll::Ready(()),
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Call `f` on each item of `stream` and run the returned futures, with at most `limit` of them
+/// in flight at once. Resolves once `stream` has finished and every future has completed.
+///
+/// Like [buffer_unordered], but for when only the side effects of the futures matter. A new item
+/// is only pulled from `stream` once one of the futures in flight has completed, which caps how
+/// much work is started each tick. With a `limit` of `1`, items are processed one after another.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::stream::for_each_concurrent;
+/// # async fn build_road(room: String) {}
+/// let (tx, rooms) = screeps_async::sync::mpsc::unbounded::<String>();
+/// screeps_async::spawn(async move {
+///     // plan roads in at most 2 rooms at a time
+///     for_each_concurrent(rooms, 2, |room| build_road(room)).await;
+/// }).detach();
+/// ```
+///
+/// # Panics
+///
+/// This function panics if `limit` is zero
+pub fn for_each_concurrent<S, F, Fut>(stream: S, limit: usize, f: F) -> ForEachConcurrent<S, F, Fut>
+where
+    S: Stream,
+    F: FnMut(S::Item) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    assert!(limit > 0, "for_each_concurrent limit must be non-zero");
+    ForEachConcurrent {
+        stream,
+        f,
+        in_flight: FuturesUnordered::new(),
+        limit,
+        done: false,
+    }
+}
+
+/// A [Stream] that yields a single item and then finishes. Created by [once]
+#[derive(Debug, Clone)]
+pub struct Once<T> {
+    value: Option<T>,
+}
+
+impl<T> Once<T> {
+    /// Take the item, returning [None] if it has already been taken
+    pub async fn next(&mut self) -> Option<T> {
+        self.value.take()
+    }
+}
+
+impl<T> Unpin for Once<T> {}
+
+impl<T> Stream for Once<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(self.value.take())
+    }
+}
+
+/// Create a [Stream] that yields `value` once and then finishes.
+///
+/// Handy for feeding a single item into combinators like [merge] that expect a stream.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::stream::{merge, once};
+/// let (tx, requests) = screeps_async::sync::mpsc::unbounded::<u32>();
+/// screeps_async::spawn(async move {
+///     // handle the startup request before any that arrive later
+///     let mut requests = merge(once(0), requests);
+///     while let Some(request) = requests.next().await {
+///         // handle the request
+///     }
+/// }).detach();
+/// ```
+pub fn once<T>(value: T) -> Once<T> {
+    Once { value: Some(value) }
+}
+
+/// A [Stream] that yields the result of calling a closure, forever. Created by [repeat_with]
+#[derive(Debug, Clone)]
+pub struct RepeatWith<F> {
+    f: F,
+}
+
+impl<T, F: FnMut() -> T> RepeatWith<F> {
+    /// Call the closure for the next item. Never returns [None]
+    pub async fn next(&mut self) -> Option<T> {
+        Some((self.f)())
+    }
+}
+
+impl<F> Unpin for RepeatWith<F> {}
+
+impl<T, F: FnMut() -> T> Stream for RepeatWith<F> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(Some((self.f)()))
+    }
+}
+
+/// Create a [Stream] that yields `f()` every time it is polled, and never finishes.
+///
+/// The stream is always ready, so looping over it on its own never yields to other tasks. Combine
+/// it with [take] to produce a fixed number of items. Don't [throttle] it though: a [Throttle]
+/// keeps pulling items from its inner stream during the throttle window, which never ends for a
+/// stream that is always ready. To produce an item every few ticks, yield [delay]s instead and
+/// drive them with [buffer_unordered].
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::stream::{buffer_unordered, repeat_with};
+/// # use screeps_async::time::delay;
+/// screeps_async::spawn(async move {
+///     // check for hostiles every 5 ticks
+///     let mut scans = buffer_unordered(repeat_with(|| delay(5)), 1);
+///     while scans.next().await.is_some() {
+///         // look for hostiles
+///     }
+/// }).detach();
+/// ```
+pub fn repeat_with<T, F: FnMut() -> T>(f: F) -> RepeatWith<F> {
+    RepeatWith { f }
+}
+
+/// A [Stream] that yields at most a given number of items from another stream. Created by
+/// [take]
+pub struct Take<S> {
+    stream: S,
+    remaining: usize,
+}
+
+impl<S: Stream> Take<S> {
+    /// Wait for the next item, returning [None] once the limit is reached or the inner stream
+    /// has finished
+    pub async fn next(&mut self) -> Option<S::Item>
+    where
+        Self: Unpin,
+    {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl<S: Stream> Stream for Take<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // SAFETY: `stream` is never moved out of `self`, so it stays pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        if this.remaining == 0 {
+            return Poll::Ready(None);
+        }
+
+        let item = unsafe { Pin::new_unchecked(&mut this.stream) }.poll_next(cx);
+        match item {
+            Poll::Ready(Some(_)) => this.remaining -= 1,
+            // Don't poll a finished stream again
+            Poll::Ready(None) => this.remaining = 0,
+            Poll::Pending => {}
+        }
+        item
+    }
+}
+
+/// Yield the first `n` items of `stream`, then finish. The inner stream isn't polled again once
+/// `n` items have been yielded.
+pub fn take<S: Stream>(stream: S, n: usize) -> Take<S> {
+    Take {
+        stream,
+        remaining: n,
+    }
+}
+
+/// A [Stream] that yields items from another stream until a future completes. Created by
+/// [take_until]
+pub struct TakeUntil<S, F> {
+    stream: S,
+    until: F,
+    /// Whether `until` has completed or the inner stream has finished
+    done: bool,
+}
+
+impl<S: Stream, F: Future> TakeUntil<S, F> {
+    /// Wait for the next item, returning [None] once the future has completed or the inner stream
+    /// has finished
+    pub async fn next(&mut self) -> Option<S::Item>
+    where
+        Self: Unpin,
+    {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl<S: Stream, F: Future> Stream for TakeUntil<S, F> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // SAFETY: neither `stream` nor `until` is ever moved out of `self`, so they stay pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        // Check the future first so that nothing is yielded once it has completed
+        if unsafe { Pin::new_unchecked(&mut this.until) }
+            .poll(cx)
+            .is_ready()
+        {
+            this.done = true;
+            return Poll::Ready(None);
+        }
+
+        let item = unsafe { Pin::new_unchecked(&mut this.stream) }.poll_next(cx);
+        if let Poll::Ready(None) = item {
+            this.done = true;
+        }
+        item
+    }
+}
+
+/// Yield items from `stream` until `until` completes, then finish.
+///
+/// `until` is checked before each item, so if it is already complete the stream finishes without
+/// yielding anything. Once either `until` has completed or `stream` has finished, neither is
+/// polled again. The output of `until` is discarded.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::stream::take_until;
+/// # use screeps_async::sync::CancellationToken;
+/// let token = CancellationToken::new();
+/// let (tx, readings) = screeps_async::sync::mpsc::unbounded::<u32>();
+/// screeps_async::spawn(async move {
+///     let mut readings = take_until(readings, token.cancelled());
+///     while let Some(reading) = readings.next().await {
+///         // record the sensor reading
+///     }
+///     // cancelled, or the sensor went away
+/// }).detach();
+/// ```
+pub fn take_until<S: Stream, F: Future>(stream: S, until: F) -> TakeUntil<S, F> {
+    TakeUntil {
+        stream,
+        until,
+        done: false,
+    }
+}
+
+/// A [Stream] that waits for a future to produce a stream, then yields that stream's items.
+/// Created by [flatten_stream]
+pub struct FlattenStream<F: Future> {
+    state: FlattenState<F, F::Output>,
+}
+
+enum FlattenState<F, S> {
+    /// Waiting for the future to produce the stream. Boxed so that the [FlattenStream] is [Unpin]
+    /// as long as the stream is, even for futures from `async` blocks
+    Future(Pin<Box<F>>),
+    Stream(S),
+}
+
+impl<F, S: Unpin> Unpin for FlattenState<F, S> {}
+
+impl<F> FlattenStream<F>
+where
+    F: Future,
+    F::Output: Stream,
+{
+    /// Wait for the next item, returning [None] once the produced stream has finished
+    pub async fn next(&mut self) -> Option<<F::Output as Stream>::Item>
+    where
+        Self: Unpin,
+    {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl<F> Stream for FlattenStream<F>
+where
+    F: Future,
+    F::Output: Stream,
+{
+    type Item = <F::Output as Stream>::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // SAFETY: the stream is never moved out of `self`, so it stays pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        loop {
+            match &mut this.state {
+                FlattenState::Future(future) => {
+                    match future.as_mut().poll(cx) {
+                        // Poll the new stream straight away, so it registers for wakeups
+                        Poll::Ready(stream) => this.state = FlattenState::Stream(stream),
+                        Poll::Pending => return Poll::Pending,
+                    }
+                }
+                FlattenState::Stream(stream) => {
+                    return unsafe { Pin::new_unchecked(stream) }.poll_next(cx);
+                }
+            }
+        }
+    }
+}
+
+/// Turn a future that resolves to a stream into a stream of that stream's items.
+///
+/// The future is driven first, then the stream it produced. Saves writing a state machine by
+/// hand for things that need an initial await before they can start yielding items. The future
+/// is boxed until it completes.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::stream::flatten_stream;
+/// # use screeps_async::sync::mpsc;
+/// async fn subscribe() -> mpsc::Receiver<u32> {
+///     let (tx, events) = mpsc::unbounded();
+///     // register `tx` with the event source
+///     events
+/// }
+///
+/// screeps_async::spawn(async move {
+///     let mut events = flatten_stream(subscribe());
+///     while let Some(event) = events.next().await {
+///         // handle the event
+///     }
+/// }).detach();
+/// ```
+pub fn flatten_stream<F>(future: F) -> FlattenStream<F>
+where
+    F: Future,
+    F::Output: Stream,
+{
+    FlattenStream {
+        state: FlattenState::Future(Box::pin(future)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spawn;
+    use crate::tests::{game_time, init_test, tick};
+    use crate::time::{delay, yield_now};
+    use rstest::rstest;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    type BoxFuture = Pin<Box<dyn Future<Output = u32>>>;
+
+    #[test]
+    fn once_yields_one_item() {
+        init_test();
+
+        let items = crate::run_blocking(async move {
+            let mut stream = once("spawn");
+            let mut items = Vec::new();
+            while let Some(item) =
+                std::future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await
+            {
+                items.push(item);
+            }
+            items
+        });
+        assert_eq!(vec!["spawn"], items);
+    }
+
+    #[test]
+    fn repeat_with_take_yields_n_items() {
+        init_test();
+
+        let calls = Rc::new(Cell::new(0));
+        let items = {
+            let calls = calls.clone();
+            crate::run_blocking(async move {
+                let mut stream = take(
+                    repeat_with(move || {
+                        calls.set(calls.get() + 1);
+                        calls.get()
+                    }),
+                    3,
+                );
+                let mut items = Vec::new();
+                while let Some(item) = stream.next().await {
+                    items.push(item);
+                }
+                items
+            })
+        };
+        assert_eq!(vec![1, 2, 3], items);
+        assert_eq!(
+            3,
+            calls.get(),
+            "Closure was called after the limit was reached"
+        );
+    }
+
+    #[test]
+    fn repeat_with_delays() {
+        init_test();
+
+        let handle = spawn(async move {
+            let mut stream = take(buffer_unordered(repeat_with(|| delay(2)), 1), 3);
+            let mut items = Vec::new();
+            while stream.next().await.is_some() {
+                items.push(game_time());
+            }
+            items
+        });
+        while !handle.is_finished() {
+            tick().unwrap();
+        }
+
+        assert_eq!(vec![2, 4, 6], crate::run_blocking(handle).unwrap());
+    }
+
+    #[test]
+    fn outputs_in_completion_order() {
+        init_test();
+
+        let handle = spawn(async move {
+            let mut set: FuturesUnordered<_> = [3, 1, 2]
+                .into_iter()
+                .map(|ticks| async move {
+                    delay(ticks).await;
+                    ticks
+                })
+                .collect();
+
+            let mut outputs = Vec::new();
+            while let Some(ticks) = set.next().await {
+                outputs.push((ticks, game_time()));
+            }
+            outputs
+        });
+
+        while !handle.is_finished() {
+            tick().unwrap();
+        }
+
+        let outputs = crate::block_on(handle).unwrap().unwrap();
+        assert_eq!(vec![(1, 1), (2, 2), (3, 3)], outputs);
+    }
+
+    #[test]
+    fn only_woken_futures_are_polled() {
+        init_test();
+
+        let polls = Rc::new(Cell::new(0));
+        let handle = {
+            let polls = polls.clone();
+            spawn(async move {
+                let mut set = FuturesUnordered::<BoxFuture>::new();
+                set.push(Box::pin(std::future::poll_fn(move |_| {
+                    polls.set(polls.get() + 1);
+                    Poll::<u32>::Pending
+                })));
+                set.push(Box::pin(async move {
+                    for _ in 0..3 {
+                        yield_now().await;
+                    }
+                    delay(2).await;
+                    0
+                }));
+
+                set.next().await
+            })
+        };
+
+        for _ in 0..3 {
+            tick().unwrap();
+        }
+
+        assert!(handle.is_finished());
+        assert_eq!(
+            1,
+            polls.get(),
+            "Future that was never woken was polled again"
+        );
+    }
+
+    #[test]
+    fn push_between_polls() {
+        init_test();
+
+        let outputs = Rc::new(RefCell::new(Vec::new()));
+        {
+            let outputs = outputs.clone();
+            spawn(async move {
+                let mut set = FuturesUnordered::<BoxFuture>::new();
+                set.push(Box::pin(async { 1 }));
+                set.push(Box::pin(async {
+                    delay(1).await;
+                    2
+                }));
+
+                while let Some(output) = set.next().await {
+                    outputs.borrow_mut().push(output);
+                    if output == 1 {
+                        set.push(Box::pin(async { 3 }));
+                    }
+                }
+            })
+            .detach();
+        }
+
+        tick().unwrap();
+        tick().unwrap();
+
+        assert_eq!(vec![1, 3, 2], outputs.take());
+    }
+
+    #[test]
+    fn flatten_stream_after_delay() {
+        init_test();
+
+        let handle = spawn(async move {
+            let mut stream = flatten_stream(async move {
+                delay(2).await;
+                once(game_time())
+            });
+            let mut outputs = Vec::new();
+            while let Some(item) = stream.next().await {
+                outputs.push((item, game_time()));
+            }
+            outputs
+        });
+        while !handle.is_finished() {
+            tick().unwrap();
+        }
+
+        assert_eq!(vec![(2, 2)], crate::block_on(handle).unwrap().unwrap());
+    }
+
+    #[test]
+    fn take_until_delay() {
+        init_test();
+
+        let (tx, rx) = crate::sync::mpsc::unbounded();
+        spawn(async move {
+            for item in 0..5 {
+                // Fails once the stream has ended and dropped the receiver
+                tx.try_send(item).ok();
+                delay(1).await;
+            }
+        })
+        .detach();
+
+        let handle = spawn(async move {
+            let mut stream = take_until(rx, delay(3));
+            let mut outputs = Vec::new();
+            while let Some(item) = stream.next().await {
+                outputs.push((item, game_time()));
+            }
+            (outputs, game_time())
+        });
+        while !handle.is_finished() {
+            tick().unwrap();
+        }
+
+        let (outputs, ended) = crate::block_on(handle).unwrap().unwrap();
+        assert_eq!(vec![(0, 0), (1, 1), (2, 2)], outputs);
+        assert_eq!(3, ended, "Stream didn't end when the delay fired");
+    }
+
+    #[test]
+    fn take_until_ready_yields_nothing() {
+        init_test();
+
+        let polled = Rc::new(Cell::new(false));
+        let outputs = {
+            let polled = polled.clone();
+            crate::run_blocking(async move {
+                let items = repeat_with(move || polled.set(true));
+                let mut stream = take_until(items, std::future::ready(()));
+                let mut outputs = Vec::new();
+                while let Some(item) = stream.next().await {
+                    outputs.push(item);
+                }
+                outputs
+            })
+        };
+        assert!(outputs.is_empty());
+        assert!(
+            !polled.get(),
+            "Stream was polled after the future completed"
+        );
+    }
+
+    /// Send 1 on tick 0, 2 on tick 1 and 3 on tick 4 through a stream throttled to one item every 3
+    /// ticks, returning each item along with the tick it was yielded on
+    fn run_throttled(policy: ThrottlePolicy) -> Vec<(u32, u32)> {
+        init_test();
+
+        let (tx, rx) = crate::sync::mpsc::unbounded();
+        spawn(async move {
+            for (item, when) in [(1, 0), (2, 1), (3, 4)] {
+                crate::time::delay_until(when).await;
+                tx.try_send(item).unwrap();
+            }
+        })
+        .detach();
+
+        let handle = spawn(async move {
+            let mut throttled = throttle(rx, 3).policy(policy);
+            let mut outputs = Vec::new();
+            while let Some(item) = throttled.next().await {
+                outputs.push((item, game_time()));
+            }
+            outputs
+        });
+
+        while !handle.is_finished() {
+            tick().unwrap();
+        }
+        crate::block_on(handle).unwrap().unwrap()
+    }
+
+    #[test]
+    fn throttle_holds_latest() {
+        assert_eq!(
+            vec![(1, 0), (2, 3), (3, 6)],
+            run_throttled(ThrottlePolicy::Latest)
+        );
+    }
+
+    #[test]
+    fn throttle_drops_during_window() {
+        assert_eq!(vec![(1, 0), (3, 4)], run_throttled(ThrottlePolicy::Drop));
+    }
+
+    #[test]
+    fn merge_interleaves_intervals() {
+        init_test();
+
+        let handle = spawn(async move {
+            let mut merged = merge(crate::time::interval(2), crate::time::interval(3));
+            let mut fired = Vec::new();
+            while fired.len() < 6 {
+                fired.push(merged.next().await.unwrap());
+            }
+            fired
+        });
+
+        while !handle.is_finished() {
+            tick().unwrap();
+        }
+        assert_eq!(
+            vec![2, 3, 4, 6, 6, 8],
+            crate::block_on(handle).unwrap().unwrap()
+        );
+    }
+
+    #[test]
+    fn merge_alternates_ready_streams() {
+        init_test();
+
+        let (first_tx, first) = crate::sync::mpsc::unbounded();
+        let (second_tx, second) = crate::sync::mpsc::unbounded();
+        for i in 0..3 {
+            first_tx.try_send(("first", i)).unwrap();
+            second_tx.try_send(("second", i)).unwrap();
+        }
+
+        let handle = spawn(async move {
+            let mut merged = merge(first, second);
+            let mut items = Vec::new();
+            while let Some(item) = merged.next().await {
+                items.push(item);
+            }
+            items
+        });
+
+        tick().unwrap();
+        drop(first_tx);
+        tick().unwrap();
+        assert!(!handle.is_finished(), "Ended before both streams finished");
+
+        second_tx.try_send(("second", 3)).unwrap();
+        drop(second_tx);
+        tick().unwrap();
+
+        let expected = vec![
+            ("first", 0),
+            ("second", 0),
+            ("first", 1),
+            ("second", 1),
+            ("first", 2),
+            ("second", 2),
+            ("second", 3),
+        ];
+        assert_eq!(expected, crate::block_on(handle).unwrap().unwrap());
+    }
+
+    #[test]
+    fn buffer_unordered_caps_concurrency() {
+        init_test();
+
+        let in_flight = Rc::new(Cell::new(0));
+        let max_in_flight = Rc::new(Cell::new(0));
+        let (tx, rx) = crate::sync::mpsc::unbounded::<BoxFuture>();
+        for ticks in [3, 1, 2, 1, 1] {
+            let in_flight = in_flight.clone();
+            let max_in_flight = max_in_flight.clone();
+            tx.try_send(Box::pin(async move {
+                in_flight.set(in_flight.get() + 1);
+                max_in_flight.set(max_in_flight.get().max(in_flight.get()));
+                delay(ticks).await;
+                in_flight.set(in_flight.get() - 1);
+                ticks
+            }))
+            .unwrap();
+        }
+        // The source ends while futures are still in flight
+        drop(tx);
+
+        let handle = spawn(async move {
+            let mut buffered = buffer_unordered(rx, 2);
+            let mut outputs = Vec::new();
+            while let Some(ticks) = buffered.next().await {
+                outputs.push((ticks, game_time()));
+            }
+            outputs
+        });
+
+        while !handle.is_finished() {
+            tick().unwrap();
+        }
+
+        assert_eq!(2, max_in_flight.get());
+        let outputs = crate::block_on(handle).unwrap().unwrap();
+        assert_eq!(vec![(1, 1), (3, 3), (2, 3), (1, 4), (1, 4)], outputs);
+    }
+
+    #[rstest]
+    #[case::sequential(1, vec![(3, 3), (2, 5), (1, 6)], 6)]
+    #[case::concurrent(3, vec![(1, 1), (2, 2), (3, 3)], 3)]
+    fn for_each_concurrent_limits(
+        #[case] limit: usize,
+        #[case] expected: Vec<(u32, u32)>,
+        #[case] finished_at: u32,
+    ) {
+        init_test();
+
+        let (tx, rx) = crate::sync::mpsc::unbounded::<u32>();
+        for ticks in [3, 2, 1] {
+            tx.try_send(ticks).unwrap();
+        }
+        drop(tx);
+
+        let done = Rc::new(RefCell::new(Vec::new()));
+        let handle = {
+            let done = done.clone();
+            spawn(async move {
+                for_each_concurrent(rx, limit, |ticks| {
+                    let done = done.clone();
+                    async move {
+                        delay(ticks).await;
+                        done.borrow_mut().push((ticks, game_time()));
+                    }
+                })
+                .await;
+                game_time()
+            })
+        };
+
+        while !handle.is_finished() {
+            tick().unwrap();
+        }
+
+        assert_eq!(expected, done.take());
+        assert_eq!(finished_at, crate::block_on(handle).unwrap().unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "buffer_unordered limit must be non-zero")]
+    fn buffer_unordered_zero_limit_panics() {
+        let (_tx, rx) = crate::sync::mpsc::unbounded::<BoxFuture>();
+        drop(buffer_unordered(rx, 0));
+    }
+}
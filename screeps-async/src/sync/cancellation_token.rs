@@ -0,0 +1,197 @@
+use crate::sync::{WaitKey, WakerList};
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::{Rc, Weak};
+use std::task::{Context, Poll};
+
+/// A token that can be used to cooperatively cancel a group of tasks
+///
+/// Cloned tokens share the same state, so cancelling one cancels them all. Tokens created with
+/// [child_token](CancellationToken::child_token) are cancelled along with their parent, but
+/// cancelling a child doesn't affect its parent. This makes it easy to cancel a whole subsystem,
+/// e.g. all tasks for a room, at once.
+///
+/// # Examples
+/// ```
+/// # use screeps_async::sync::CancellationToken;
+/// # use screeps_async::time::delay;
+/// # screeps_async::initialize();
+/// let room_token = CancellationToken::new();
+/// let token = room_token.child_token();
+/// screeps_async::spawn(async move {
+///     screeps_async::select! {
+///         _ = token.cancelled() => {
+///             // the room was lost, clean up
+///         }
+///         _ = delay(100) => {
+///             // do periodic room work
+///         }
+///     }
+/// }).detach();
+///
+/// room_token.cancel();
+/// ```
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    state: Rc<RefCell<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    cancelled: bool,
+    /// Queue of futures waiting for cancellation
+    waiters: WakerList,
+    /// Tokens created by [CancellationToken::child_token]. Weak so dropped children don't leak
+    children: Vec<Weak<RefCell<State>>>,
+}
+
+impl CancellationToken {
+    /// Construct a new, uncancelled [CancellationToken]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a token that is cancelled when this token is cancelled.
+    ///
+    /// If this token has already been cancelled, the child starts out cancelled
+    pub fn child_token(&self) -> CancellationToken {
+        let child = CancellationToken::new();
+        let mut state = self.state.borrow_mut();
+        if state.cancelled {
+            child.state.borrow_mut().cancelled = true;
+        } else {
+            // Clean up after children that have been dropped
+            state.children.retain(|child| child.strong_count() > 0);
+            state.children.push(Rc::downgrade(&child.state));
+        }
+
+        child
+    }
+
+    /// Cancel this token and all of its children, waking any tasks waiting on
+    /// [cancelled](Self::cancelled).
+    ///
+    /// Does nothing if the token has already been cancelled
+    pub fn cancel(&self) {
+        Self::cancel_state(&self.state);
+    }
+
+    fn cancel_state(state: &Rc<RefCell<State>>) {
+        let (mut waiters, children) = {
+            let mut state = state.borrow_mut();
+            if state.cancelled {
+                return;
+            }
+
+            state.cancelled = true;
+            (
+                std::mem::take(&mut state.waiters),
+                std::mem::take(&mut state.children),
+            )
+        };
+
+        // The borrow must be released before waking in case the woken task uses this token
+        waiters.wake_all();
+        for child in children.iter().filter_map(Weak::upgrade) {
+            Self::cancel_state(&child);
+        }
+    }
+
+    /// Check whether this token has been cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.state.borrow().cancelled
+    }
+
+    /// Wait until this token is cancelled. Completes immediately if it already has been
+    pub fn cancelled(&self) -> WaitForCancellation<'_> {
+        WaitForCancellation {
+            token: self,
+            key: None,
+        }
+    }
+}
+
+/// A [Future] that completes once a [CancellationToken] is cancelled.
+/// See [CancellationToken::cancelled]
+///
+/// Dropping this future before it completes removes it from the token's queue
+pub struct WaitForCancellation<'a> {
+    token: &'a CancellationToken,
+    /// Our place in the queue, once we have had to wait
+    key: Option<WaitKey>,
+}
+
+impl Future for WaitForCancellation<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let token = self.token;
+        let mut state = token.state.borrow_mut();
+        if state.cancelled {
+            // Our key went away along with the rest of the queue
+            self.key = None;
+            return Poll::Ready(());
+        }
+
+        match self.key {
+            Some(key) => {
+                state.waiters.update(key, cx);
+            }
+            None => self.key = Some(state.waiters.register(cx)),
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for WaitForCancellation<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            self.token.state.borrow_mut().waiters.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cancelling_parent_cancels_children() {
+        let parent = CancellationToken::new();
+        let child = parent.child_token();
+        let grandchild = child.child_token();
+        let sibling = parent.child_token();
+
+        child.cancel();
+        assert!(child.is_cancelled());
+        assert!(grandchild.is_cancelled());
+        assert!(
+            !parent.is_cancelled(),
+            "Cancelling a child cancelled its parent"
+        );
+        assert!(!sibling.is_cancelled());
+
+        parent.cancel();
+        assert!(sibling.is_cancelled());
+        assert!(parent.child_token().is_cancelled());
+    }
+
+    #[test]
+    fn parked_task_wakes_on_cancel() {
+        crate::tests::init_test();
+
+        let parent = CancellationToken::new();
+        let handle = {
+            let token = parent.child_token();
+            crate::spawn(async move { token.cancelled().await })
+        };
+
+        crate::run().unwrap();
+        assert!(!handle.is_finished());
+
+        parent.cancel();
+        crate::run().unwrap();
+        assert!(handle.is_finished(), "Parked task was not woken");
+    }
+}
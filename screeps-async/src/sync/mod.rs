@@ -1,7 +1,25 @@
 //! Synchronization primitives for async contexts
 
+mod waker_list;
+pub use waker_list::*;
+
+mod cancellation_token;
+pub use cancellation_token::*;
+
 mod mutex;
 pub use mutex::*;
 
+pub mod mpsc;
+pub mod oneshot;
+
+mod notify;
+pub use notify::*;
+
+mod once_cell;
+pub use once_cell::*;
+
 mod rwlock;
 pub use rwlock::*;
+
+mod semaphore;
+pub use semaphore::*;
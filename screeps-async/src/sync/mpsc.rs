@@ -0,0 +1,486 @@
+//! A multi-producer, single-consumer channel for sending values between tasks
+//!
+//! The [Receiver] can be used either by awaiting [Receiver::recv] or as a [Stream]
+//!
+//! # Examples
+//! ```
+//! # use screeps_async::sync::mpsc;
+//! # screeps_async::initialize();
+//! let (tx, mut rx) = mpsc::unbounded();
+//! for i in 0..3 {
+//!     let tx = tx.clone();
+//!     screeps_async::spawn(async move {
+//!         let _ = tx.send(i).await;
+//!     }).detach();
+//! }
+//!
+//! screeps_async::spawn(async move {
+//!     while let Some(work) = rx.recv().await {
+//!         // dispatch work
+//!     }
+//! }).detach();
+//! ```
+
+use crate::sync::{WaitKey, WakerList};
+use futures_core::Stream;
+use std::cell::{Cell, RefCell};
+use std::fmt::{Debug, Display, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+/// Create a bounded channel that can hold at most `capacity` values at once.
+///
+/// Once the channel is full, [Sender::send] will wait until the [Receiver] makes room
+///
+/// # Panics
+///
+/// This function panics if `capacity` is zero
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "Channel capacity must be non-zero");
+    from_flume(flume::bounded(capacity))
+}
+
+/// Create a channel with no limit on the number of values it can hold
+pub fn unbounded<T>() -> (Sender<T>, Receiver<T>) {
+    from_flume(flume::unbounded())
+}
+
+fn from_flume<T>(
+    (sender, receiver): (flume::Sender<T>, flume::Receiver<T>),
+) -> (Sender<T>, Receiver<T>) {
+    let shared = Rc::new(Shared {
+        receiver: RefCell::new(None),
+        senders: RefCell::new(WakerList::new()),
+        waiting_senders: Cell::new(0),
+    });
+
+    let sender = Sender {
+        inner: sender,
+        shared: shared.clone(),
+    };
+    let receiver = Receiver {
+        inner: receiver,
+        shared,
+    };
+
+    (sender, receiver)
+}
+
+/// Wakers for tasks waiting on either end of the channel.
+///
+/// flume's own async support assumes a multithreaded executor, so we handle waking ourselves
+struct Shared {
+    /// Waker of the task waiting for a value
+    receiver: RefCell<Option<Waker>>,
+    /// Wakers of tasks waiting for room in a bounded channel
+    senders: RefCell<WakerList>,
+    /// Number of [SendFuture]s that are queued in `senders`, or have been woken but not sent yet.
+    /// New sends queue up behind them rather than taking the room they were woken for
+    waiting_senders: Cell<usize>,
+}
+
+impl Shared {
+    fn wake_receiver(&self) {
+        let waker = self.receiver.take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    fn wake_sender(&self) {
+        let next = self.senders.borrow_mut().pop();
+        if let Some((_, waker)) = next {
+            waker.wake();
+        }
+    }
+
+    fn wake_all_senders(&self) {
+        loop {
+            let next = self.senders.borrow_mut().pop();
+            match next {
+                Some((_, waker)) => waker.wake(),
+                None => break,
+            }
+        }
+    }
+}
+
+/// Error returned by [Sender::send] when the [Receiver] has been dropped.
+///
+/// Contains the value that failed to send
+#[derive(Eq, PartialEq, Copy, Clone)]
+pub struct SendError<T>(pub T);
+
+impl<T> Debug for SendError<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<T> Display for SendError<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Receiver has been dropped")
+    }
+}
+
+impl<T> std::error::Error for SendError<T> {}
+
+/// Error returned by [Sender::try_send]
+#[derive(Eq, PartialEq, Copy, Clone)]
+pub enum TrySendError<T> {
+    /// The channel has no room for the value
+    Full(T),
+    /// The [Receiver] has been dropped
+    Closed(T),
+}
+
+impl<T> TrySendError<T> {
+    /// Consume the error, returning the value that failed to send
+    pub fn into_inner(self) -> T {
+        match self {
+            TrySendError::Full(value) | TrySendError::Closed(value) => value,
+        }
+    }
+}
+
+impl<T> Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "Full(..)"),
+            TrySendError::Closed(_) => write!(f, "Closed(..)"),
+        }
+    }
+}
+
+impl<T> Display for TrySendError<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "Channel is full"),
+            TrySendError::Closed(_) => write!(f, "Receiver has been dropped"),
+        }
+    }
+}
+
+impl<T> std::error::Error for TrySendError<T> {}
+
+/// The sending half of a channel. May be cloned to send from multiple tasks
+pub struct Sender<T> {
+    inner: flume::Sender<T>,
+    shared: Rc<Shared>,
+}
+
+impl<T> Sender<T> {
+    /// Send `value` to the [Receiver], waiting for room in the channel if it is full.
+    ///
+    /// Resolves to a [SendError] if the [Receiver] has been dropped
+    pub fn send(&self, value: T) -> SendFuture<'_, T> {
+        SendFuture {
+            sender: self,
+            value: Some(value),
+            key: None,
+        }
+    }
+
+    /// Attempt to send `value` to the [Receiver] without waiting
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        match self.inner.try_send(value) {
+            Ok(()) => {
+                self.shared.wake_receiver();
+                Ok(())
+            }
+            Err(flume::TrySendError::Full(value)) => Err(TrySendError::Full(value)),
+            Err(flume::TrySendError::Disconnected(value)) => Err(TrySendError::Closed(value)),
+        }
+    }
+
+    /// Check whether the [Receiver] has been dropped
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_disconnected()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.inner.sender_count() == 1 {
+            // Last sender, wake the receiver so it sees the channel is closed
+            self.shared.wake_receiver();
+        }
+    }
+}
+
+/// A [Future] that resolves once the value has been sent. See [Sender::send]
+pub struct SendFuture<'a, T> {
+    sender: &'a Sender<T>,
+    value: Option<T>,
+    /// Our place in the queue, once we have had to wait for room
+    key: Option<WaitKey>,
+}
+
+// We never pin-project `value`, so this future is always safe to move
+impl<T> Unpin for SendFuture<'_, T> {}
+
+impl<T> Future for SendFuture<'_, T> {
+    type Output = Result<(), SendError<T>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let shared = &self.sender.shared;
+        match self.key {
+            Some(key) if shared.senders.borrow_mut().update(key, cx) => {
+                // Still waiting for our turn, don't jump the queue
+                return Poll::Pending;
+            }
+            None if shared.waiting_senders.get() > 0 && !self.sender.is_closed() => {
+                // Other senders are waiting for room, so wait behind them
+                self.key = Some(shared.senders.borrow_mut().register(cx));
+                shared.waiting_senders.set(shared.waiting_senders.get() + 1);
+                return Poll::Pending;
+            }
+            _ => {}
+        }
+
+        let value = self
+            .value
+            .take()
+            .expect("SendFuture polled after completion");
+
+        match self.sender.try_send(value) {
+            Ok(()) => {
+                if self.stop_waiting() && !self.sender.inner.is_full() {
+                    // There's still room, so let the next sender have it
+                    self.sender.shared.wake_sender();
+                }
+                Poll::Ready(Ok(()))
+            }
+            Err(TrySendError::Closed(value)) => {
+                self.stop_waiting();
+                Poll::Ready(Err(SendError(value)))
+            }
+            Err(TrySendError::Full(value)) => {
+                let shared = &self.sender.shared;
+                if self.key.is_none() {
+                    shared.waiting_senders.set(shared.waiting_senders.get() + 1);
+                }
+                self.value = Some(value);
+                self.key = Some(shared.senders.borrow_mut().register(cx));
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> SendFuture<'_, T> {
+    /// Leave the waiting senders, returning whether we were one of them
+    fn stop_waiting(&mut self) -> bool {
+        let waited = self.key.take().is_some();
+        if waited {
+            let waiting = &self.sender.shared.waiting_senders;
+            waiting.set(waiting.get() - 1);
+        }
+        waited
+    }
+}
+
+impl<T> Drop for SendFuture<'_, T> {
+    fn drop(&mut self) {
+        let Some(key) = self.key else {
+            return;
+        };
+        self.stop_waiting();
+
+        let removed = self.sender.shared.senders.borrow_mut().remove(key);
+        if !removed {
+            // We were woken because there is room, but won't be using it. Let someone else
+            self.sender.shared.wake_sender();
+        }
+    }
+}
+
+/// The receiving half of a channel
+///
+/// Values can be received by awaiting [Receiver::recv], or by using the [Receiver] as a [Stream].
+/// Once all [Sender]s have been dropped and the channel is empty, the [Receiver] yields [None]
+pub struct Receiver<T> {
+    inner: flume::Receiver<T>,
+    shared: Rc<Shared>,
+}
+
+impl<T> Receiver<T> {
+    /// Receive the next value from the channel.
+    ///
+    /// Resolves to [None] once all [Sender]s have been dropped and there are no more values
+    pub fn recv(&mut self) -> Recv<'_, T> {
+        Recv { receiver: self }
+    }
+
+    /// Attempt to receive a value without waiting.
+    ///
+    /// Returns [None] if the channel is currently empty or closed
+    pub fn try_recv(&mut self) -> Option<T> {
+        let value = self.inner.try_recv().ok()?;
+        self.shared.wake_sender();
+        Some(value)
+    }
+
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        match self.inner.try_recv() {
+            Ok(value) => {
+                self.shared.wake_sender();
+                Poll::Ready(Some(value))
+            }
+            Err(flume::TryRecvError::Disconnected) => Poll::Ready(None),
+            Err(flume::TryRecvError::Empty) => {
+                self.shared.receiver.replace(Some(cx.waker().clone()));
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_recv(cx)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver.take();
+        // Let any waiting senders know there's no one listening anymore
+        self.shared.wake_all_senders();
+    }
+}
+
+/// A [Future] that resolves to the next value in the channel. See [Receiver::recv]
+pub struct Recv<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Future for Recv<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spawn;
+    use crate::tests::init_test;
+    use crate::time::yield_now;
+
+    #[test]
+    fn multiple_senders() {
+        init_test();
+
+        let (tx, mut rx) = unbounded();
+        for i in 0..3 {
+            let tx = tx.clone();
+            spawn(async move {
+                yield_now().await;
+                tx.send(i).await.unwrap();
+            })
+            .detach();
+        }
+        drop(tx);
+
+        let received = crate::block_on(async move {
+            let mut received = Vec::new();
+            while let Some(value) = rx.recv().await {
+                received.push(value);
+            }
+            received
+        })
+        .unwrap();
+
+        assert_eq!(vec![0, 1, 2], received);
+    }
+
+    #[test]
+    fn sender_drop_terminates() {
+        init_test();
+
+        let (tx, mut rx) = unbounded::<()>();
+        let handle = spawn(async move { rx.recv().await });
+
+        crate::run().unwrap();
+        assert!(!handle.is_finished());
+
+        drop(tx);
+        crate::run().unwrap();
+
+        let result = crate::block_on(handle).unwrap().unwrap();
+        assert_eq!(None, result);
+    }
+
+    #[test]
+    fn bounded_send_waits_for_room() {
+        init_test();
+
+        let (tx, mut rx) = channel(1);
+        let handle = spawn(async move {
+            tx.send(1).await.unwrap();
+            tx.send(2).await.unwrap();
+        });
+
+        crate::run().unwrap();
+        assert!(!handle.is_finished(), "Sent to a full channel");
+
+        assert_eq!(Some(1), rx.try_recv());
+        crate::run().unwrap();
+        assert!(handle.is_finished(), "Sender was not woken");
+        assert_eq!(Some(2), rx.try_recv());
+    }
+
+    #[test]
+    fn woken_sender_keeps_its_turn() {
+        init_test();
+
+        let (tx, mut rx) = channel(1);
+        tx.try_send(0).unwrap();
+        let handle = {
+            let tx = tx.clone();
+            spawn(async move { tx.send(1).await.unwrap() })
+        };
+        crate::run().unwrap();
+
+        // Makes room and wakes the waiting sender, which hasn't run yet
+        assert_eq!(Some(0), rx.try_recv());
+        let mut late = std::pin::pin!(tx.send(2));
+        let poll = late.as_mut().poll(&mut Context::from_waker(Waker::noop()));
+        assert!(poll.is_pending(), "New sender took the woken sender's room");
+
+        crate::run().unwrap();
+        assert!(handle.is_finished());
+        assert_eq!(Some(1), rx.try_recv());
+    }
+
+    #[test]
+    #[should_panic(expected = "Channel capacity must be non-zero")]
+    fn zero_capacity_panics() {
+        drop(channel::<()>(0));
+    }
+
+    #[test]
+    fn receiver_drop_closes() {
+        let (tx, rx) = unbounded();
+        drop(rx);
+
+        assert!(tx.is_closed());
+        assert_eq!(Err(TrySendError::Closed(1)), tx.try_send(1));
+    }
+}
@@ -1,12 +1,15 @@
-use std::cell::{Cell, UnsafeCell};
+use crate::sync::{WaitKey, WakerList};
+use std::cell::{Cell, RefCell, UnsafeCell};
 use std::future::Future;
 use std::ops::{Deref, DerefMut};
 use std::pin::Pin;
-use std::task::{Context, Poll, Waker};
+use std::task::{Context, Poll};
 
 /// An async mutex
 ///
-/// Locks will be acquired in the order they are requested
+/// Locks will be acquired in the order they are requested. When a [MutexGuard] is dropped,
+/// the lock is handed directly to the task that has been waiting the longest, so tasks that
+/// are not yet waiting cannot jump the queue.
 ///
 /// # Examples
 /// ```
@@ -27,8 +30,10 @@ pub struct Mutex<T> {
     state: Cell<bool>,
     /// Wrapped value
     data: UnsafeCell<T>,
-    /// Queue of futures to wake when a lock is released
-    wakers: UnsafeCell<Vec<Waker>>,
+    /// Queue of futures waiting to acquire the lock
+    waiters: RefCell<WakerList>,
+    /// Waiter the lock has been handed off to, but which hasn't been polled yet
+    granted: Cell<Option<WaitKey>>,
 }
 
 impl<T> Mutex<T> {
@@ -37,7 +42,8 @@ impl<T> Mutex<T> {
         Self {
             state: Cell::new(false),
             data: UnsafeCell::new(val),
-            wakers: UnsafeCell::new(Vec::new()),
+            waiters: RefCell::new(WakerList::new()),
+            granted: Cell::new(None),
         }
     }
 
@@ -62,9 +68,15 @@ impl<T> Mutex<T> {
     }
 
     fn unlock(&self) {
-        self.state.set(false);
-        let wakers = unsafe { &mut *self.wakers.get() };
-        wakers.drain(..).for_each(Waker::wake);
+        let next = self.waiters.borrow_mut().pop();
+        match next {
+            Some((key, waker)) => {
+                // Keep the mutex locked and hand it off to the next waiter
+                self.granted.set(Some(key));
+                waker.wake();
+            }
+            None => self.state.set(false),
+        }
     }
 }
 
@@ -115,37 +127,67 @@ impl<T> Drop for MutexGuard<'_, T> {
 }
 
 /// A [Future] that blocks until the [Mutex] can be locked, then returns the [MutexGuard]
+///
+/// Dropping this future before it completes removes it from the [Mutex]'s queue
 pub struct MutexLockFuture<'a, T> {
     mutex: &'a Mutex<T>,
+    /// Our place in the queue, once we have had to wait
+    key: Option<WaitKey>,
 }
 
 impl<'a, T> MutexLockFuture<'a, T> {
     fn new(mutex: &'a Mutex<T>) -> Self {
-        Self { mutex }
+        Self { mutex, key: None }
     }
 }
 
 impl<'a, T> Future for MutexLockFuture<'a, T> {
     type Output = MutexGuard<'a, T>;
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        if let Some(val) = self.mutex.try_lock() {
-            return Poll::Ready(val);
-        }
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mutex = self.mutex;
+        let Some(key) = self.key else {
+            if let Some(guard) = mutex.try_lock() {
+                return Poll::Ready(guard);
+            }
 
-        unsafe {
-            (*self.mutex.wakers.get()).push(cx.waker().clone());
+            self.key = Some(mutex.waiters.borrow_mut().register(cx));
+            return Poll::Pending;
+        };
+
+        if mutex.granted.get() == Some(key) {
+            mutex.granted.set(None);
+            self.key = None;
+            return Poll::Ready(MutexGuard::new(mutex));
         }
 
+        mutex.waiters.borrow_mut().update(key, cx);
         Poll::Pending
     }
 }
 
+impl<T> Drop for MutexLockFuture<'_, T> {
+    fn drop(&mut self) {
+        let Some(key) = self.key else {
+            return;
+        };
+
+        if self.mutex.granted.get() == Some(key) {
+            // We were handed the lock but never took it, pass it on
+            self.mutex.granted.set(None);
+            self.mutex.unlock();
+        } else {
+            self.mutex.waiters.borrow_mut().remove(key);
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::time::delay_ticks;
     use std::rc::Rc;
+    use std::task::Waker;
 
     #[test]
     fn single_lock() {
@@ -202,6 +244,57 @@ mod test {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn contending_locks_are_fifo() {
+        crate::tests::init_test();
+
+        let mutex = Rc::new(Mutex::new(vec![]));
+        let _guard = mutex.try_lock().unwrap();
+
+        for i in 0..2 {
+            let mutex = mutex.clone();
+            crate::spawn(async move {
+                mutex.lock().await.push(i);
+            })
+            .detach();
+        }
+
+        // Both tasks are now waiting on the lock
+        crate::run().unwrap();
+        drop(_guard);
+
+        // A task that comes along later should not jump the queue
+        {
+            let mutex = mutex.clone();
+            crate::spawn(async move {
+                mutex.lock().await.push(2);
+            })
+            .detach();
+        }
+
+        crate::run().unwrap();
+
+        let expected = vec![0, 1, 2];
+        let actual = Rc::into_inner(mutex).unwrap().into_inner();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn dropped_future_leaves_queue() {
+        let mutex = Mutex::new(());
+        let guard = mutex.try_lock().unwrap();
+
+        {
+            let mut fut = std::pin::pin!(mutex.lock());
+            let mut cx = Context::from_waker(Waker::noop());
+            assert!(fut.as_mut().poll(&mut cx).is_pending());
+        }
+
+        drop(guard);
+        // If the dropped future was still queued, the lock would have been handed off to it
+        assert!(mutex.try_lock().is_some());
+    }
+
     #[test]
     fn handles_dropped_futures() {
         crate::tests::init_test();
@@ -233,7 +326,7 @@ mod test {
         }
 
         crate::tests::tick().unwrap();
-        drop(to_drop);
+        crate::block_on(to_drop.cancel()).unwrap();
         crate::tests::tick().unwrap();
 
         let expected = vec![0, 2];
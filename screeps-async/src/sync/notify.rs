@@ -0,0 +1,223 @@
+use crate::sync::{WaitKey, WakerList};
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Notifies a single task, or all waiting tasks, that an event has occurred
+///
+/// A [Notify] carries no data, it can be used as a building block for condition-variable style
+/// patterns where tasks wait for some state to change.
+///
+/// [notify_one](Notify::notify_one) wakes the task that has been waiting the longest, or stores a
+/// permit if no task is waiting so that the next call to [notified](Notify::notified) completes
+/// immediately. [notify_waiters](Notify::notify_waiters) wakes all currently waiting tasks
+/// without storing a permit.
+///
+/// # Examples
+/// ```
+/// # use std::rc::Rc;
+/// # use screeps_async::sync::Notify;
+/// # screeps_async::initialize();
+/// let notify = Rc::new(Notify::new());
+/// {
+///     let notify = notify.clone();
+///     screeps_async::spawn(async move {
+///         notify.notified().await;
+///         // the spawn is ready, assign it work
+///     }).detach();
+/// }
+///
+/// notify.notify_one();
+/// ```
+pub struct Notify {
+    /// Whether a [notify_one](Notify::notify_one) was stored because no one was waiting
+    permit: Cell<bool>,
+    /// Queue of futures waiting to be notified
+    waiters: RefCell<WakerList>,
+    /// Waiters woken by [notify_one](Notify::notify_one), but which haven't been polled yet
+    granted: RefCell<Vec<WaitKey>>,
+}
+
+impl Notify {
+    /// Construct a new [Notify] with no stored permit
+    pub fn new() -> Self {
+        Self {
+            permit: Cell::new(false),
+            waiters: RefCell::new(WakerList::new()),
+            granted: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Wait for a notification.
+    ///
+    /// Completes immediately if a permit was stored by a previous call to
+    /// [notify_one](Self::notify_one), consuming it. A task only counts as waiting once the
+    /// returned future has been polled.
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            key: None,
+        }
+    }
+
+    /// Notify the task that has been waiting the longest.
+    ///
+    /// If no task is waiting, a permit is stored so that the next call to
+    /// [notified](Self::notified) completes immediately. At most one permit is stored at a time
+    pub fn notify_one(&self) {
+        let next = self.waiters.borrow_mut().pop();
+        match next {
+            Some((key, waker)) => {
+                self.granted.borrow_mut().push(key);
+                waker.wake();
+            }
+            None => self.permit.set(true),
+        }
+    }
+
+    /// Notify all tasks that are currently waiting.
+    ///
+    /// Unlike [notify_one](Self::notify_one), no permit is stored if no task is waiting
+    pub fn notify_waiters(&self) {
+        loop {
+            let next = self.waiters.borrow_mut().pop();
+            match next {
+                Some((_, waker)) => waker.wake(),
+                None => break,
+            }
+        }
+    }
+
+    /// Remove `key` from the list of granted waiters, returning whether it was present
+    fn take_grant(&self, key: WaitKey) -> bool {
+        let mut granted = self.granted.borrow_mut();
+        match granted.iter().position(|k| *k == key) {
+            Some(idx) => {
+                granted.swap_remove(idx);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A [Future] that completes once a [Notify] has been notified. See [Notify::notified]
+///
+/// Dropping this future before it completes removes it from the [Notify]'s queue
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    /// Our place in the queue, once we have had to wait
+    key: Option<WaitKey>,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let notify = self.notify;
+        let Some(key) = self.key else {
+            if notify.permit.replace(false) {
+                return Poll::Ready(());
+            }
+
+            self.key = Some(notify.waiters.borrow_mut().register(cx));
+            return Poll::Pending;
+        };
+
+        if notify.waiters.borrow_mut().update(key, cx) {
+            return Poll::Pending;
+        }
+
+        // We've been woken by one of the notify methods
+        notify.take_grant(key);
+        self.key = None;
+        Poll::Ready(())
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let Some(key) = self.key else {
+            return;
+        };
+
+        if self.notify.take_grant(key) {
+            // We were chosen by notify_one but never saw it, pass it on
+            self.notify.notify_one();
+        } else {
+            self.notify.waiters.borrow_mut().remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::rc::Rc;
+
+    fn spawn_waiter(notify: &Rc<Notify>) -> crate::job::JoinHandle<()> {
+        let notify = notify.clone();
+        crate::spawn(async move { notify.notified().await })
+    }
+
+    #[test]
+    fn notify_one_stores_permit() {
+        crate::tests::init_test();
+
+        let notify = Rc::new(Notify::new());
+        notify.notify_one();
+        notify.notify_one();
+
+        let first = spawn_waiter(&notify);
+        let second = spawn_waiter(&notify);
+        crate::run().unwrap();
+
+        assert!(first.is_finished(), "Stored permit was not consumed");
+        assert!(!second.is_finished(), "More than one permit was stored");
+
+        notify.notify_one();
+        crate::run().unwrap();
+        assert!(second.is_finished());
+    }
+
+    #[test]
+    fn notify_one_wakes_single_waiter() {
+        crate::tests::init_test();
+
+        let notify = Rc::new(Notify::new());
+        let first = spawn_waiter(&notify);
+        let second = spawn_waiter(&notify);
+        crate::run().unwrap();
+
+        notify.notify_one();
+        crate::run().unwrap();
+
+        assert!(first.is_finished(), "Longest waiter was not notified");
+        assert!(!second.is_finished());
+    }
+
+    #[test]
+    fn notify_waiters_wakes_only_current_waiters() {
+        crate::tests::init_test();
+
+        let notify = Rc::new(Notify::new());
+        let waiters: Vec<_> = (0..3).map(|_| spawn_waiter(&notify)).collect();
+        crate::run().unwrap();
+
+        notify.notify_waiters();
+        crate::run().unwrap();
+        assert!(waiters.iter().all(|w| w.is_finished()));
+
+        // No permit was stored for later waiters
+        let late = spawn_waiter(&notify);
+        crate::run().unwrap();
+        assert!(!late.is_finished());
+    }
+}
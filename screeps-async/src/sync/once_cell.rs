@@ -0,0 +1,230 @@
+use crate::sync::{WaitKey, WakerList};
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// A cell that is initialized at most once by an async initializer
+///
+/// When several tasks race to initialize the cell via [get_or_init](OnceCell::get_or_init),
+/// only the first initializer is run. The other tasks wait for it to finish, then all of them
+/// see the same value. If the running initializer is dropped before it completes, one of the
+/// waiting tasks runs its own initializer instead.
+///
+/// # Examples
+/// ```
+/// # use std::rc::Rc;
+/// # use screeps_async::sync::OnceCell;
+/// # screeps_async::initialize();
+/// let cost_matrix = Rc::new(OnceCell::new());
+/// for _ in 0..3 {
+///     let cost_matrix = cost_matrix.clone();
+///     screeps_async::spawn(async move {
+///         let matrix = cost_matrix
+///             .get_or_init(async {
+///                 // compute the cost matrix, possibly over several ticks
+///                 vec![0u8; 2500]
+///             })
+///             .await;
+///     }).detach();
+/// }
+/// ```
+pub struct OnceCell<T> {
+    value: std::cell::OnceCell<T>,
+    /// Whether an initializer is currently running
+    initializing: Cell<bool>,
+    /// Queue of futures waiting for the running initializer to finish
+    waiters: RefCell<WakerList>,
+}
+
+impl<T> OnceCell<T> {
+    /// Construct a new, uninitialized [OnceCell]
+    pub fn new() -> Self {
+        Self {
+            value: std::cell::OnceCell::new(),
+            initializing: Cell::new(false),
+            waiters: RefCell::new(WakerList::new()),
+        }
+    }
+
+    /// Get the value of the cell, or [None] if it hasn't been initialized yet
+    pub fn get(&self) -> Option<&T> {
+        self.value.get()
+    }
+
+    /// Get the value of the cell, initializing it with `init` if it hasn't been initialized yet.
+    ///
+    /// If another task is already initializing the cell, waits for that task to finish instead of
+    /// running `init`.
+    pub async fn get_or_init<F>(&self, init: F) -> &T
+    where
+        F: Future<Output = T>,
+    {
+        let mut init = Some(init);
+        loop {
+            if let Some(value) = self.value.get() {
+                return value;
+            }
+
+            if !self.initializing.get() {
+                let init = init.take().expect("initializer was already run");
+                return self.initialize(init).await;
+            }
+
+            Wait {
+                cell: self,
+                key: None,
+            }
+            .await;
+        }
+    }
+
+    /// Consumes the cell, returning the wrapped value
+    pub fn into_inner(self) -> Option<T> {
+        self.value.into_inner()
+    }
+
+    async fn initialize<F>(&self, init: F) -> &T
+    where
+        F: Future<Output = T>,
+    {
+        struct Guard<'a, T>(&'a OnceCell<T>);
+
+        impl<T> Drop for Guard<'_, T> {
+            fn drop(&mut self) {
+                // Wake everyone, even if the initializer was cancelled, so that a waiter can take over
+                self.0.initializing.set(false);
+                self.0.wake_all();
+            }
+        }
+
+        self.initializing.set(true);
+        let _guard = Guard(self);
+
+        let value = init.await;
+        let _ = self.value.set(value);
+        self.value.get().unwrap()
+    }
+
+    fn wake_all(&self) {
+        loop {
+            let next = self.waiters.borrow_mut().pop();
+            match next {
+                Some((_, waker)) => waker.wake(),
+                None => break,
+            }
+        }
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Resolves once the running initializer of a [OnceCell] has finished or been dropped
+struct Wait<'a, T> {
+    cell: &'a OnceCell<T>,
+    /// Our place in the queue, once we have had to wait
+    key: Option<WaitKey>,
+}
+
+impl<T> Future for Wait<'_, T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let cell = self.cell;
+        if !cell.initializing.get() {
+            self.key = None;
+            return Poll::Ready(());
+        }
+
+        match self.key {
+            Some(key) => {
+                cell.waiters.borrow_mut().update(key, cx);
+            }
+            None => self.key = Some(cell.waiters.borrow_mut().register(cx)),
+        }
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Wait<'_, T> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            self.cell.waiters.borrow_mut().remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::time::delay_ticks;
+    use std::rc::Rc;
+
+    #[test]
+    fn racing_initializers_run_once() {
+        crate::tests::init_test();
+
+        let cell = Rc::new(OnceCell::new());
+        let inits = Rc::new(Cell::new(0));
+        let handles: Vec<_> = (0..2)
+            .map(|i| {
+                let cell = cell.clone();
+                let inits = inits.clone();
+                crate::spawn(async move {
+                    *cell
+                        .get_or_init(async move {
+                            inits.set(inits.get() + 1);
+                            // Give the other task a chance to try initializing
+                            delay_ticks(1).await;
+                            i
+                        })
+                        .await
+                })
+            })
+            .collect();
+
+        for _ in 0..3 {
+            crate::tests::tick().unwrap();
+        }
+
+        assert_eq!(1, inits.get(), "Initializer ran more than once");
+        for handle in handles {
+            assert!(handle.is_finished());
+            assert_eq!(0, crate::block_on(handle).unwrap().unwrap());
+        }
+        assert_eq!(Some(&0), cell.get());
+    }
+
+    #[test]
+    fn waiter_takes_over_cancelled_initializer() {
+        crate::tests::init_test();
+
+        let cell = Rc::new(OnceCell::new());
+        let first = {
+            let cell = cell.clone();
+            crate::spawn(async move {
+                cell.get_or_init(async {
+                    delay_ticks(10).await;
+                    0
+                })
+                .await;
+            })
+        };
+        let second = {
+            let cell = cell.clone();
+            crate::spawn(async move { *cell.get_or_init(async { 1 }).await })
+        };
+
+        crate::tests::tick().unwrap();
+        assert!(!second.is_finished(), "Waiter did not wait for initializer");
+
+        first.abort();
+        crate::tests::tick().unwrap();
+
+        assert_eq!(1, crate::block_on(second).unwrap().unwrap());
+    }
+}
@@ -0,0 +1,206 @@
+//! A channel for sending a single value between tasks
+//!
+//! # Examples
+//! ```
+//! # use screeps_async::sync::oneshot;
+//! # screeps_async::initialize();
+//! let (tx, rx) = oneshot::channel();
+//! screeps_async::spawn(async move {
+//!     // compute something expensive
+//!     let _ = tx.send(42);
+//! }).detach();
+//!
+//! screeps_async::spawn(async move {
+//!     assert_eq!(Ok(42), rx.await);
+//! }).detach();
+//! ```
+
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+/// Create a new oneshot channel, returning the [Sender] and [Receiver] halves
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Rc::new(RefCell::new(Inner {
+        value: None,
+        waker: None,
+        sender_dropped: false,
+        receiver_dropped: false,
+    }));
+
+    let sender = Sender {
+        inner: inner.clone(),
+    };
+    let receiver = Receiver { inner };
+
+    (sender, receiver)
+}
+
+struct Inner<T> {
+    value: Option<T>,
+    /// Waker of the task awaiting the [Receiver]
+    waker: Option<Waker>,
+    sender_dropped: bool,
+    receiver_dropped: bool,
+}
+
+/// Error returned by awaiting a [Receiver]
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[non_exhaustive]
+pub enum RecvError {
+    /// The [Sender] was dropped without sending a value
+    Closed,
+}
+
+impl Display for RecvError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecvError::Closed => {
+                write!(f, "Sender was dropped without sending a value")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RecvError {}
+
+/// The sending half of a oneshot channel. See [channel]
+pub struct Sender<T> {
+    inner: Rc<RefCell<Inner<T>>>,
+}
+
+impl<T> Sender<T> {
+    /// Send `value` to the [Receiver], waking the task awaiting it.
+    ///
+    /// Returns `value` back as an [Err] if the [Receiver] has already been dropped
+    pub fn send(self, value: T) -> Result<(), T> {
+        let waker = {
+            let mut inner = self.inner.borrow_mut();
+            if inner.receiver_dropped {
+                return Err(value);
+            }
+
+            inner.value = Some(value);
+            inner.waker.take()
+        };
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+
+        Ok(())
+    }
+
+    /// Check whether the [Receiver] has been dropped
+    pub fn is_closed(&self) -> bool {
+        self.inner.borrow().receiver_dropped
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut inner = self.inner.borrow_mut();
+            inner.sender_dropped = true;
+            inner.waker.take()
+        };
+
+        // Wake the receiver so it can observe that the channel was closed.
+        // If a value was sent, it was already woken and this does nothing
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// The receiving half of a oneshot channel. See [channel]
+///
+/// This type implements [Future] which resolves to the sent value, or to [RecvError::Closed] if the
+/// [Sender] was dropped without sending anything
+pub struct Receiver<T> {
+    inner: Rc<RefCell<Inner<T>>>,
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.inner.borrow_mut();
+        if let Some(value) = inner.value.take() {
+            return Poll::Ready(Ok(value));
+        }
+
+        if inner.sender_dropped {
+            return Poll::Ready(Err(RecvError::Closed));
+        }
+
+        inner.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.borrow_mut().receiver_dropped = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spawn;
+    use crate::tests::init_test;
+
+    #[test]
+    fn send_before_await() {
+        init_test();
+
+        let (tx, rx) = channel();
+        tx.send(1).unwrap();
+
+        let result = crate::block_on(rx).unwrap();
+        assert_eq!(Ok(1), result);
+    }
+
+    #[test]
+    fn await_before_send() {
+        init_test();
+
+        let (tx, rx) = channel();
+        let handle = spawn(rx);
+        spawn(async move {
+            tx.send(1).unwrap();
+        })
+        .detach();
+
+        // Receiver should be woken and complete within the same tick
+        let result = crate::block_on(handle).unwrap().unwrap();
+        assert_eq!(Ok(1), result);
+    }
+
+    #[test]
+    fn dropped_sender() {
+        init_test();
+
+        let (tx, rx) = channel::<()>();
+        let handle = spawn(rx);
+
+        crate::run().unwrap();
+        drop(tx);
+
+        let result = crate::block_on(handle).unwrap().unwrap();
+        assert_eq!(Err(RecvError::Closed), result);
+    }
+
+    #[test]
+    fn dropped_receiver() {
+        let (tx, rx) = channel();
+        drop(rx);
+
+        assert!(tx.is_closed());
+        assert_eq!(Err(1), tx.send(1));
+    }
+}
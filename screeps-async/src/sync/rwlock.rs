@@ -1,4 +1,5 @@
-use std::cell::{Ref, RefCell, RefMut, UnsafeCell};
+use crate::sync::{WaitKey, WakerList};
+use std::cell::{Cell, RefCell, UnsafeCell};
 use std::future::Future;
 use std::ops::{Deref, DerefMut};
 use std::pin::Pin;
@@ -7,24 +8,62 @@ use std::task::{Context, Poll, Waker};
 
 /// An async RwLock
 ///
-/// Locks will be acquired in the order they are requested. When any task is waiting
-/// on a [write](RwLock::write) lock, no new [read](RwLock::read) locks can be acquired
+/// Allows either any number of readers or a single writer at a time. The lock is writer
+/// preferring, so writers can't be starved by a steady stream of readers:
+///
+/// - No new [read](RwLock::read) locks are acquired while a writer holds the lock or any task is
+///   waiting on a [write](RwLock::write) lock. Readers that are already holding the lock keep it.
+/// - When the lock is released, it is handed to the writer that has been waiting the longest.
+/// - Once no writers are waiting, all waiting readers are let in together.
+///
+/// The lock is handed directly to waiting tasks, so tasks that are not yet waiting cannot jump the
+/// queue. Readers can be held back for as long as writers keep queueing up, so this lock is best
+/// suited to data that is read often and written rarely.
+///
+/// # Examples
+/// ```
+/// # use std::rc::Rc;
+/// # use screeps_async::sync::RwLock;
+/// # screeps_async::initialize();
+/// let lock = Rc::new(RwLock::new(0));
+/// {
+///     let lock = lock.clone();
+///     screeps_async::spawn(async move {
+///         *lock.write().await += 1;
+///     }).detach();
+/// }
+/// screeps_async::spawn(async move {
+///     let val = lock.read().await;
+/// }).detach();
+/// ```
 pub struct RwLock<T> {
-    /// Inner RwLock
-    inner: RefCell<T>,
-    /// Queue of futures to wake when a write lock is released
-    read_wakers: UnsafeCell<Vec<Waker>>,
-    /// Queue of futures to wake when a read lock is released
-    write_wakers: UnsafeCell<Vec<Waker>>,
+    /// Number of read locks currently held, including those handed to a waiting reader
+    readers: Cell<usize>,
+    /// Whether the write lock is currently held, including when handed to a waiting writer
+    writer: Cell<bool>,
+    /// Wrapped value
+    data: UnsafeCell<T>,
+    /// Queue of futures waiting to acquire a read lock
+    read_waiters: RefCell<WakerList>,
+    /// Queue of futures waiting to acquire the write lock
+    write_waiters: RefCell<WakerList>,
+    /// Readers the lock has been handed off to, but which haven't been polled yet
+    granted_readers: RefCell<Vec<WaitKey>>,
+    /// Writer the lock has been handed off to, but which hasn't been polled yet
+    granted_writer: Cell<Option<WaitKey>>,
 }
 
 impl<T> RwLock<T> {
     /// Construct a new [RwLock] wrapping `val`
     pub fn new(val: T) -> Self {
         Self {
-            inner: RefCell::new(val),
-            read_wakers: UnsafeCell::new(Vec::new()),
-            write_wakers: UnsafeCell::new(Vec::new()),
+            readers: Cell::new(0),
+            writer: Cell::new(false),
+            data: UnsafeCell::new(val),
+            read_waiters: RefCell::new(WakerList::new()),
+            write_waiters: RefCell::new(WakerList::new()),
+            granted_readers: RefCell::new(Vec::new()),
+            granted_writer: Cell::new(None),
         }
     }
 
@@ -32,8 +71,9 @@ impl<T> RwLock<T> {
     pub fn read(&self) -> RwLockFuture<'_, T, RwLockReadGuard<'_, T>> {
         RwLockFuture {
             lock: self,
-            borrow: Self::try_read,
+            guard: RwLockReadGuard::new,
             is_writer: false,
+            key: None,
         }
     }
 
@@ -42,15 +82,16 @@ impl<T> RwLock<T> {
     /// Returns [None] if the value is currently mutably borrowed or
     /// a task is waiting on a mutable reference.
     pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
-        unsafe { RwLockReadGuard::new(self) }
+        self.try_lock(false).then(|| RwLockReadGuard::new(self))
     }
 
     /// Block until the wrapped value can be mutably borrowed
     pub fn write(&self) -> RwLockFuture<'_, T, RwLockWriteGuard<'_, T>> {
         RwLockFuture {
             lock: self,
-            borrow: Self::try_write,
+            guard: RwLockWriteGuard::new,
             is_writer: true,
+            key: None,
         }
     }
 
@@ -58,12 +99,12 @@ impl<T> RwLock<T> {
     ///
     /// Returns [None] if the value is already borrowed (mutably or immutably)
     pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
-        RwLockWriteGuard::new(self)
+        self.try_lock(true).then(|| RwLockWriteGuard::new(self))
     }
 
     /// Consumes this [RwLock] and returns ownership of the wrapped value
     pub fn into_inner(self) -> T {
-        self.inner.into_inner()
+        self.data.into_inner()
     }
 
     /// Convenience method to consume [`Rc<RwLock<T>>`] and return the wrapped value
@@ -76,36 +117,113 @@ impl<T> RwLock<T> {
 }
 
 impl<T> RwLock<T> {
-    unsafe fn unlock(&self) {
-        let wakers = &mut *self.write_wakers.get();
-        wakers.drain(..).for_each(Waker::wake);
+    /// Take a read or write lock if the fairness policy allows it right now
+    fn try_lock(&self, is_writer: bool) -> bool {
+        if self.writer.get() {
+            return false;
+        }
+
+        if is_writer {
+            if self.readers.get() > 0 {
+                return false;
+            }
+            self.writer.set(true);
+        } else {
+            if !self.write_waiters.borrow().is_empty() {
+                return false; // Cannot take new reads if a writer is waiting
+            }
+            self.readers.set(self.readers.get() + 1);
+        }
+
+        true
+    }
+
+    /// Release a read or write lock, handing the lock on to any waiters that can now take it
+    fn unlock(&self, is_writer: bool) {
+        if is_writer {
+            self.writer.set(false);
+        } else {
+            self.readers.set(self.readers.get() - 1);
+        }
+
+        self.grant_waiters();
+    }
+
+    /// Hand the lock to the longest waiting writer if it is free, or to all waiting readers if no
+    /// writer holds or is waiting for the lock
+    fn grant_waiters(&self) {
+        if self.writer.get() {
+            return;
+        }
+
+        let mut wakers = Vec::new();
+        if self.readers.get() == 0 {
+            let next = self.write_waiters.borrow_mut().pop();
+            if let Some((key, waker)) = next {
+                self.writer.set(true);
+                self.granted_writer.set(Some(key));
+                wakers.push(waker);
+            }
+        }
+
+        if !self.writer.get() && self.write_waiters.borrow().is_empty() {
+            let mut waiters = self.read_waiters.borrow_mut();
+            let mut granted = self.granted_readers.borrow_mut();
+            while let Some((key, waker)) = waiters.pop() {
+                self.readers.set(self.readers.get() + 1);
+                granted.push(key);
+                wakers.push(waker);
+            }
+        }
+
+        // Wake after releasing the borrows in case a waker polls the lock right away
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    /// Remove `key` from the waiters the lock has been handed to, returning whether it was present
+    fn take_grant(&self, key: WaitKey, is_writer: bool) -> bool {
+        if is_writer {
+            if self.granted_writer.get() == Some(key) {
+                self.granted_writer.set(None);
+                return true;
+            }
+            return false;
+        }
 
-        let wakers = &mut *self.read_wakers.get();
-        wakers.drain(..).for_each(Waker::wake);
+        let mut granted = self.granted_readers.borrow_mut();
+        match granted.iter().position(|k| *k == key) {
+            Some(idx) => {
+                granted.swap_remove(idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn waiters(&self, is_writer: bool) -> &RefCell<WakerList> {
+        if is_writer {
+            &self.write_waiters
+        } else {
+            &self.read_waiters
+        }
     }
 }
 
 /// An RAII guard that releases a read lock when dropped
 pub struct RwLockReadGuard<'a, T> {
     inner: &'a RwLock<T>,
-    data: Ref<'a, T>,
 }
 
 impl<'a, T> RwLockReadGuard<'a, T> {
-    unsafe fn new(lock: &'a RwLock<T>) -> Option<Self> {
-        if !(*lock.write_wakers.get()).is_empty() {
-            return None; // Cannot take new reads if a writer is waiting
-        }
-
-        let data = lock.inner.try_borrow().ok()?;
-
-        Some(RwLockReadGuard { data, inner: lock })
+    /// The caller must already hold a read lock on `lock`
+    fn new(lock: &'a RwLock<T>) -> Self {
+        Self { inner: lock }
     }
 }
 
 impl<T> Drop for RwLockReadGuard<'_, T> {
     fn drop(&mut self) {
-        unsafe { self.inner.unlock() }
+        self.inner.unlock(false);
     }
 }
 
@@ -113,21 +231,19 @@ impl<T> Deref for RwLockReadGuard<'_, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        &self.data
+        unsafe { &*self.inner.data.get() }
     }
 }
 
 /// An RAII guard that releases the write lock when dropped
 pub struct RwLockWriteGuard<'a, T> {
     inner: &'a RwLock<T>,
-    data: RefMut<'a, T>,
 }
 
 impl<'a, T> RwLockWriteGuard<'a, T> {
-    fn new(lock: &'a RwLock<T>) -> Option<Self> {
-        let data = lock.inner.try_borrow_mut().ok()?;
-
-        Some(Self { inner: lock, data })
+    /// The caller must already hold the write lock on `lock`
+    fn new(lock: &'a RwLock<T>) -> Self {
+        Self { inner: lock }
     }
 
     /// Immediately drop the guard and release the write lock
@@ -148,7 +264,7 @@ impl<'a, T> RwLockWriteGuard<'a, T> {
 
 impl<T> Drop for RwLockWriteGuard<'_, T> {
     fn drop(&mut self) {
-        unsafe { self.inner.unlock() }
+        self.inner.unlock(true);
     }
 }
 
@@ -156,44 +272,69 @@ impl<T> Deref for RwLockWriteGuard<'_, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        &self.data
+        unsafe { &*self.inner.data.get() }
     }
 }
 
 impl<T> DerefMut for RwLockWriteGuard<'_, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.data
+        unsafe { &mut *self.inner.data.get() }
     }
 }
 
 /// A [Future] that blocks until the [RwLock] can be acquired.
+///
+/// Dropping this future before it completes removes it from the [RwLock]'s queue
 pub struct RwLockFuture<'a, T, G> {
     lock: &'a RwLock<T>,
-    borrow: fn(&'a RwLock<T>) -> Option<G>,
+    /// Wraps the lock in a guard once it has been acquired
+    guard: fn(&'a RwLock<T>) -> G,
     is_writer: bool,
+    /// Our place in the queue, once we have had to wait
+    key: Option<WaitKey>,
 }
 
 impl<T, G> Future for RwLockFuture<'_, T, G> {
     type Output = G;
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        if let Some(guard) = (self.borrow)(self.lock) {
-            return Poll::Ready(guard);
-        }
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let lock = self.lock;
+        let Some(key) = self.key else {
+            if lock.try_lock(self.is_writer) {
+                return Poll::Ready((self.guard)(lock));
+            }
 
-        let wakers = if self.is_writer {
-            self.lock.write_wakers.get()
-        } else {
-            self.lock.read_wakers.get()
+            self.key = Some(lock.waiters(self.is_writer).borrow_mut().register(cx));
+            return Poll::Pending;
         };
-        let wakers = unsafe { &mut *wakers };
 
-        wakers.push(cx.waker().clone());
+        if lock.take_grant(key, self.is_writer) {
+            self.key = None;
+            return Poll::Ready((self.guard)(lock));
+        }
 
+        lock.waiters(self.is_writer).borrow_mut().update(key, cx);
         Poll::Pending
     }
 }
 
+impl<T, G> Drop for RwLockFuture<'_, T, G> {
+    fn drop(&mut self) {
+        let Some(key) = self.key else {
+            return;
+        };
+
+        if self.lock.take_grant(key, self.is_writer) {
+            // We were handed the lock but never took it, pass it on
+            self.lock.unlock(self.is_writer);
+        } else {
+            self.lock.waiters(self.is_writer).borrow_mut().remove(key);
+            // A writer leaving the queue may let the readers behind it in
+            self.lock.grant_waiters();
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -312,4 +453,76 @@ mod test {
 
         assert_eq!(2, lock.into_inner_rc());
     }
+
+    #[test]
+    fn pending_writer_goes_before_pending_readers() {
+        crate::tests::init_test();
+
+        let lock = Rc::new(RwLock::new(vec![]));
+        let reader = lock.try_read().unwrap();
+
+        let spawn_reader = |name| {
+            let lock = lock.clone();
+            crate::spawn(async move {
+                let guard = lock.read().await;
+                (name, guard.clone())
+            })
+        };
+
+        let writer = {
+            let lock = lock.clone();
+            crate::spawn(async move { lock.write().await.push("writer") })
+        };
+        crate::run().unwrap();
+        // Compatible with the read lock that is held, but must queue behind the waiting writer
+        let readers = [spawn_reader("first"), spawn_reader("second")];
+        crate::run().unwrap();
+        assert!(readers.iter().all(|r| !r.is_finished()));
+        assert!(
+            lock.try_read().is_none(),
+            "New reader jumped a waiting writer"
+        );
+
+        drop(reader);
+        crate::run().unwrap();
+        assert!(writer.is_finished());
+
+        for (reader, name) in readers.into_iter().zip(["first", "second"]) {
+            let res = crate::block_on(reader).unwrap().unwrap();
+            assert_eq!((name, vec!["writer"]), res);
+        }
+    }
+
+    #[test]
+    fn dropped_writer_lets_readers_in() {
+        crate::tests::init_test();
+
+        let lock = Rc::new(RwLock::new(()));
+        let reader = lock.try_read().unwrap();
+
+        let writer = {
+            let lock = lock.clone();
+            crate::spawn(async move {
+                let _guard = lock.write().await;
+            })
+        };
+        crate::run().unwrap();
+        let waiting_reader = {
+            let lock = lock.clone();
+            crate::spawn(async move {
+                let _guard = lock.read().await;
+            })
+        };
+        crate::run().unwrap();
+        assert!(!waiting_reader.is_finished());
+
+        crate::block_on(writer.cancel()).unwrap();
+        crate::run().unwrap();
+        assert!(
+            waiting_reader.is_finished(),
+            "Reader still waiting on a writer that went away"
+        );
+        drop(reader);
+        assert!(lock.try_write().is_some());
+    }
 }
@@ -0,0 +1,238 @@
+use crate::sync::{WaitKey, WakerList};
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// An async counting semaphore
+///
+/// Permits are handed out in the order they are requested. When a [SemaphorePermit] is dropped,
+/// its permit is handed directly to the task that has been waiting the longest.
+///
+/// # Examples
+/// ```
+/// # use std::rc::Rc;
+/// # use screeps_async::sync::Semaphore;
+/// # screeps_async::initialize();
+/// // Allow at most 2 tasks to search for paths at once
+/// let semaphore = Rc::new(Semaphore::new(2));
+/// for _ in 0..5 {
+///     let semaphore = semaphore.clone();
+///     screeps_async::spawn(async move {
+///         let _permit = semaphore.acquire().await;
+///         // do pathfinding
+///     }).detach();
+/// }
+/// ```
+pub struct Semaphore {
+    /// Number of permits available to be acquired
+    permits: Cell<usize>,
+    /// Queue of futures waiting for a permit
+    waiters: RefCell<WakerList>,
+    /// Waiters that have been handed a permit, but haven't been polled yet
+    granted: RefCell<Vec<WaitKey>>,
+}
+
+impl Semaphore {
+    /// Construct a new [Semaphore] with `permits` permits available
+    pub fn new(permits: usize) -> Self {
+        Self {
+            permits: Cell::new(permits),
+            waiters: RefCell::new(WakerList::new()),
+            granted: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Acquire a permit, waiting until one is available.
+    ///
+    /// Returns a permit that is released when dropped
+    pub fn acquire(&self) -> Acquire<'_> {
+        Acquire {
+            semaphore: self,
+            key: None,
+        }
+    }
+
+    /// Try to acquire a permit.
+    ///
+    /// Returns [None] if there are no permits available at this time
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        let permits = self.permits.get();
+        if permits == 0 {
+            return None;
+        }
+
+        self.permits.set(permits - 1);
+        Some(SemaphorePermit { semaphore: self })
+    }
+
+    /// The number of permits currently available
+    pub fn available_permits(&self) -> usize {
+        self.permits.get()
+    }
+
+    fn release(&self) {
+        let next = self.waiters.borrow_mut().pop();
+        match next {
+            Some((key, waker)) => {
+                // Hand the permit off to the next waiter
+                self.granted.borrow_mut().push(key);
+                waker.wake();
+            }
+            None => self.permits.set(self.permits.get() + 1),
+        }
+    }
+
+    /// Remove `key` from the list of granted waiters, returning whether it was present
+    fn take_grant(&self, key: WaitKey) -> bool {
+        let mut granted = self.granted.borrow_mut();
+        match granted.iter().position(|k| *k == key) {
+            Some(idx) => {
+                granted.swap_remove(idx);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// An RAII guard that returns its permit to the [Semaphore] when dropped
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl SemaphorePermit<'_> {
+    /// Immediately drops the permit, returning it to the [Semaphore].
+    ///
+    /// This function is equivalent to calling [`drop`] on the permit but is more self-documenting.
+    pub fn release(self) {
+        drop(self);
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.release();
+    }
+}
+
+/// A [Future] that blocks until a permit can be acquired from a [Semaphore]
+///
+/// Dropping this future before it completes removes it from the [Semaphore]'s queue
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    /// Our place in the queue, once we have had to wait
+    key: Option<WaitKey>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = SemaphorePermit<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let semaphore = self.semaphore;
+        let Some(key) = self.key else {
+            if let Some(permit) = semaphore.try_acquire() {
+                return Poll::Ready(permit);
+            }
+
+            self.key = Some(semaphore.waiters.borrow_mut().register(cx));
+            return Poll::Pending;
+        };
+
+        if semaphore.take_grant(key) {
+            self.key = None;
+            return Poll::Ready(SemaphorePermit { semaphore });
+        }
+
+        semaphore.waiters.borrow_mut().update(key, cx);
+        Poll::Pending
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let Some(key) = self.key else {
+            return;
+        };
+
+        if self.semaphore.take_grant(key) {
+            // We were handed a permit but never took it, pass it on
+            self.semaphore.release();
+        } else {
+            self.semaphore.waiters.borrow_mut().remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn try_acquire_all_permits() {
+        let semaphore = Semaphore::new(2);
+
+        let first = semaphore.try_acquire().unwrap();
+        let _second = semaphore.try_acquire().unwrap();
+        assert!(semaphore.try_acquire().is_none());
+
+        first.release();
+        assert_eq!(1, semaphore.available_permits());
+        assert!(semaphore.try_acquire().is_some());
+    }
+
+    #[test]
+    fn waiter_proceeds_after_release() {
+        crate::tests::init_test();
+
+        let semaphore = Rc::new(Semaphore::new(2));
+        let first = semaphore.try_acquire().unwrap();
+        let _second = semaphore.try_acquire().unwrap();
+
+        let handle = {
+            let semaphore = semaphore.clone();
+            crate::spawn(async move {
+                let _permit = semaphore.acquire().await;
+            })
+        };
+
+        crate::run().unwrap();
+        assert!(
+            !handle.is_finished(),
+            "Task acquired a permit that wasn't available"
+        );
+
+        first.release();
+        crate::run().unwrap();
+        assert!(handle.is_finished(), "Task did not acquire released permit");
+
+        // The waiter's permit was returned, not leaked
+        assert_eq!(1, semaphore.available_permits());
+    }
+
+    #[test]
+    fn waiters_are_fifo() {
+        crate::tests::init_test();
+
+        let semaphore = Rc::new(Semaphore::new(1));
+        let permit = semaphore.try_acquire().unwrap();
+
+        let order = Rc::new(RefCell::new(Vec::new()));
+        for i in 0..3 {
+            let semaphore = semaphore.clone();
+            let order = order.clone();
+            crate::spawn(async move {
+                let _permit = semaphore.acquire().await;
+                order.borrow_mut().push(i);
+            })
+            .detach();
+        }
+
+        crate::run().unwrap();
+        permit.release();
+        crate::run().unwrap();
+
+        assert_eq!(vec![0, 1, 2], order.take());
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::VecDeque;
+use std::task::{Context, Poll, Waker};
+
+/// Identifies an entry in a [WakerList]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct WaitKey(u64);
+
+/// A FIFO queue of [Waker]s belonging to futures waiting on some resource.
+///
+/// This is the building block behind the primitives in this module, and can be used to build
+/// your own. Entries are removed from the list when they are woken, so a future can tell whether
+/// it has been woken by checking whether its [WaitKey] is still queued. [poll_wait](Self::poll_wait)
+/// takes care of this, as well as of futures being polled again with a different [Waker] before
+/// they are woken.
+///
+/// A future that is dropped while still queued must [remove](Self::remove) its key, or a later
+/// [wake_one](Self::wake_one) would be wasted on it.
+///
+/// # Examples
+/// ```
+/// # use screeps_async::sync::{WaitKey, WakerList};
+/// # use std::cell::{Cell, RefCell};
+/// # use std::future::Future;
+/// # use std::pin::Pin;
+/// # use std::task::{Context, Poll};
+/// /// A flag that tasks can wait to be raised
+/// #[derive(Default)]
+/// struct Flag {
+///     raised: Cell<bool>,
+///     waiters: RefCell<WakerList>,
+/// }
+///
+/// impl Flag {
+///     fn raise(&self) {
+///         self.raised.set(true);
+///         self.waiters.borrow_mut().wake_all();
+///     }
+///
+///     fn wait(&self) -> Wait<'_> {
+///         Wait { flag: self, key: None }
+///     }
+/// }
+///
+/// struct Wait<'a> {
+///     flag: &'a Flag,
+///     key: Option<WaitKey>,
+/// }
+///
+/// impl Future for Wait<'_> {
+///     type Output = ();
+///
+///     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+///         if self.flag.raised.get() {
+///             return Poll::Ready(());
+///         }
+///         let flag = self.flag;
+///         flag.waiters.borrow_mut().poll_wait(&mut self.key, cx)
+///     }
+/// }
+///
+/// impl Drop for Wait<'_> {
+///     fn drop(&mut self) {
+///         if let Some(key) = self.key {
+///             self.flag.waiters.borrow_mut().remove(key);
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct WakerList {
+    next_key: u64,
+    wakers: VecDeque<(WaitKey, Waker)>,
+}
+
+impl WakerList {
+    /// Construct an empty [WakerList]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the waker from `cx` to the back of the queue
+    pub fn register(&mut self, cx: &Context<'_>) -> WaitKey {
+        let key = WaitKey(self.next_key);
+        self.next_key += 1;
+        self.wakers.push_back((key, cx.waker().clone()));
+        key
+    }
+
+    /// Replace the waker stored for `key` with the waker from `cx`, keeping its place in the queue.
+    ///
+    /// Returns `false` if `key` is no longer queued (it has been woken or removed)
+    pub fn update(&mut self, key: WaitKey, cx: &Context<'_>) -> bool {
+        match self.wakers.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, waker)) => {
+                waker.clone_from(cx.waker());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wait in the queue, for a future whose place in it is tracked by `key`.
+    ///
+    /// On the first poll (`key` is [None]) the future is [registered](Self::register) at the back
+    /// of the queue. On later polls, the future is ready once it has been woken, or else the
+    /// stored waker is [updated](Self::update) in case the future moved to another task. This
+    /// means spurious polls neither complete the future nor lose its place in the queue. `key` is
+    /// reset to [None] once the future is ready.
+    pub fn poll_wait(&mut self, key: &mut Option<WaitKey>, cx: &Context<'_>) -> Poll<()> {
+        match *key {
+            None => {
+                *key = Some(self.register(cx));
+                Poll::Pending
+            }
+            Some(queued) if self.update(queued, cx) => Poll::Pending,
+            Some(_) => {
+                *key = None;
+                Poll::Ready(())
+            }
+        }
+    }
+
+    /// Remove `key` from the queue without waking it.
+    ///
+    /// Returns `false` if `key` was not queued
+    pub fn remove(&mut self, key: WaitKey) -> bool {
+        match self.wakers.iter().position(|(k, _)| *k == key) {
+            Some(idx) => {
+                self.wakers.remove(idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether `key` is still queued
+    pub fn contains(&self, key: WaitKey) -> bool {
+        self.wakers.iter().any(|(k, _)| *k == key)
+    }
+
+    /// The number of futures queued
+    pub fn len(&self) -> usize {
+        self.wakers.len()
+    }
+
+    /// Whether no futures are queued
+    pub fn is_empty(&self) -> bool {
+        self.wakers.is_empty()
+    }
+
+    /// Remove the first entry from the queue, returning its key and [Waker].
+    ///
+    /// The caller is responsible for waking the returned [Waker]. This allows waking
+    /// after any borrows protecting this list have been released.
+    pub fn pop(&mut self) -> Option<(WaitKey, Waker)> {
+        self.wakers.pop_front()
+    }
+
+    /// Wake the future that has been waiting the longest, returning its key, or [None] if the
+    /// queue is empty.
+    ///
+    /// The waker is called while `self` is still borrowed. The runtime's wakers only schedule
+    /// their task, so this is fine for them, but use [pop](Self::pop) if a waker might use this
+    /// list.
+    pub fn wake_one(&mut self) -> Option<WaitKey> {
+        let (key, waker) = self.pop()?;
+        waker.wake();
+        Some(key)
+    }
+
+    /// Wake every queued future, in the order they were queued, returning how many were woken.
+    ///
+    /// Like [wake_one](Self::wake_one), the wakers are called while `self` is still borrowed
+    pub fn wake_all(&mut self) -> usize {
+        let woken = self.wakers.len();
+        for (_, waker) in self.wakers.drain(..) {
+            waker.wake();
+        }
+        woken
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::Wake;
+
+    /// Counts how many times it has been woken
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl CountingWaker {
+        fn count(&self) -> usize {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Waits in a [WakerList] until woken, leaving it when dropped
+    struct Wait<'a> {
+        list: &'a RefCell<WakerList>,
+        key: Option<WaitKey>,
+    }
+
+    impl Future for Wait<'_> {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            let list = self.list;
+            list.borrow_mut().poll_wait(&mut self.key, cx)
+        }
+    }
+
+    impl Drop for Wait<'_> {
+        fn drop(&mut self) {
+            if let Some(key) = self.key {
+                self.list.borrow_mut().remove(key);
+            }
+        }
+    }
+
+    fn poll(wait: Pin<&mut Wait<'_>>, waker: &Arc<CountingWaker>) -> Poll<()> {
+        let waker = Waker::from(waker.clone());
+        wait.poll(&mut Context::from_waker(&waker))
+    }
+
+    #[test]
+    fn wakes_in_fifo_order() {
+        let list = RefCell::new(WakerList::new());
+        let wakers: Vec<_> = (0..3).map(|_| Arc::new(CountingWaker::default())).collect();
+        let mut waits: Vec<_> = (0..3)
+            .map(|_| {
+                Box::pin(Wait {
+                    list: &list,
+                    key: None,
+                })
+            })
+            .collect();
+        for (wait, waker) in waits.iter_mut().zip(&wakers) {
+            assert!(poll(wait.as_mut(), waker).is_pending());
+        }
+
+        assert_eq!(waits[0].key, list.borrow_mut().wake_one());
+        assert_eq!(
+            vec![1, 0, 0],
+            wakers.iter().map(|w| w.count()).collect::<Vec<_>>()
+        );
+        assert_eq!(2, list.borrow_mut().wake_all());
+        assert_eq!(
+            vec![1, 1, 1],
+            wakers.iter().map(|w| w.count()).collect::<Vec<_>>()
+        );
+
+        for (wait, waker) in waits.iter_mut().zip(&wakers) {
+            assert!(poll(wait.as_mut(), waker).is_ready());
+        }
+    }
+
+    #[test]
+    fn registration_replacement() {
+        let list = RefCell::new(WakerList::new());
+        let first = Arc::new(CountingWaker::default());
+        let second = Arc::new(CountingWaker::default());
+        let mut wait = Box::pin(Wait {
+            list: &list,
+            key: None,
+        });
+
+        assert!(poll(wait.as_mut(), &first).is_pending());
+        // A spurious poll from another task replaces the waker instead of queueing again
+        assert!(poll(wait.as_mut(), &second).is_pending());
+        assert_eq!(1, list.borrow().len());
+
+        list.borrow_mut().wake_one();
+        assert_eq!(0, first.count(), "Woke the replaced waker");
+        assert_eq!(1, second.count());
+        assert!(poll(wait.as_mut(), &second).is_ready());
+        assert_eq!(None, wait.key);
+    }
+
+    #[test]
+    fn removal_on_drop() {
+        let list = RefCell::new(WakerList::new());
+        let dropped_waker = Arc::new(CountingWaker::default());
+        let kept_waker = Arc::new(CountingWaker::default());
+
+        let mut dropped = Box::pin(Wait {
+            list: &list,
+            key: None,
+        });
+        let mut kept = Box::pin(Wait {
+            list: &list,
+            key: None,
+        });
+        assert!(poll(dropped.as_mut(), &dropped_waker).is_pending());
+        assert!(poll(kept.as_mut(), &kept_waker).is_pending());
+        let dropped_key = dropped.key.unwrap();
+
+        drop(dropped);
+        assert!(!list.borrow().contains(dropped_key));
+
+        // The wakeup goes to the remaining waiter rather than being wasted
+        assert_eq!(kept.key, list.borrow_mut().wake_one());
+        assert_eq!(0, dropped_waker.count());
+        assert_eq!(1, kept_waker.count());
+        assert!(list.borrow().is_empty());
+    }
+}
@@ -0,0 +1,572 @@
+//! Utilities for working with tasks
+//!
+//! See [task_local](crate::task_local) for declaring task-local storage
+
+use crate::error::JoinError;
+use crate::job::JoinHandle;
+use crate::stream::FuturesUnordered;
+use futures_core::Stream;
+use std::cell::RefCell;
+use std::fmt::{Debug, Display, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+/// A key for task-local data. Declared with the [task_local](crate::task_local) macro.
+///
+/// Unlike [std::thread::LocalKey], a value is only available while it is in scope through
+/// [scope](LocalKey::scope) or [sync_scope](LocalKey::sync_scope).
+pub struct LocalKey<T: 'static> {
+    #[doc(hidden)]
+    pub inner: std::thread::LocalKey<RefCell<Option<T>>>,
+}
+
+impl<T: 'static> LocalKey<T> {
+    /// Set the value of this task-local to `value` while `future` is running.
+    ///
+    /// The value is swapped in each time `future` is polled and swapped back out when it yields,
+    /// so other tasks polled in between never see it.
+    pub fn scope<F: Future>(&'static self, value: T, future: F) -> TaskLocalFuture<T, F> {
+        TaskLocalFuture {
+            local: self,
+            slot: Some(value),
+            future: Some(future),
+        }
+    }
+
+    /// Set the value of this task-local to `value` while `f` is running
+    pub fn sync_scope<F, R>(&'static self, value: T, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let mut slot = Some(value);
+        self.enter(&mut slot, f)
+    }
+
+    /// Access the current value of this task-local.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if called outside a [scope](Self::scope) of this task-local
+    pub fn with<F, R>(&'static self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        self.try_with(f)
+            .expect("cannot access a task-local value outside of its scope")
+    }
+
+    /// Access the current value of this task-local.
+    ///
+    /// Returns [AccessError] if called outside a [scope](Self::scope) of this task-local
+    pub fn try_with<F, R>(&'static self, f: F) -> Result<R, AccessError>
+    where
+        F: FnOnce(&T) -> R,
+    {
+        self.inner
+            .with_borrow(|value| value.as_ref().map(f).ok_or(AccessError))
+    }
+
+    /// Swap the contents of `slot` into the task-local while `f` runs, swapping it back out after.
+    fn enter<F, R>(&'static self, slot: &mut Option<T>, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        struct Guard<'a, T: 'static> {
+            local: &'static LocalKey<T>,
+            slot: &'a mut Option<T>,
+        }
+
+        impl<T: 'static> Drop for Guard<'_, T> {
+            fn drop(&mut self) {
+                // Restore the previous value, even if `f` panicked
+                self.local
+                    .inner
+                    .with_borrow_mut(|value| std::mem::swap(self.slot, value));
+            }
+        }
+
+        self.inner
+            .with_borrow_mut(|value| std::mem::swap(slot, value));
+        let _guard = Guard { local: self, slot };
+
+        f()
+    }
+}
+
+impl<T: 'static> Debug for LocalKey<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.pad("LocalKey { .. }")
+    }
+}
+
+/// A [Future] that sets a task-local value while it is polled. See [LocalKey::scope]
+pub struct TaskLocalFuture<T: 'static, F> {
+    local: &'static LocalKey<T>,
+    /// The task-local value while the future is not being polled
+    slot: Option<T>,
+    future: Option<F>,
+}
+
+impl<T: 'static, F: Future> Future for TaskLocalFuture<T, F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `future` is never moved out of, only dropped in place via Pin::set
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+        this.local.enter(&mut this.slot, || {
+            let Some(fut) = future.as_mut().as_pin_mut() else {
+                panic!("TaskLocalFuture polled after completion");
+            };
+            let res = fut.poll(cx);
+            if res.is_ready() {
+                // Drop the future while the value is still in scope, in case it is used on drop
+                future.set(None);
+            }
+            res
+        })
+    }
+}
+
+/// Error returned by [LocalKey::try_with] when the task-local hasn't been set
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct AccessError;
+
+impl Display for AccessError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "task-local value not set")
+    }
+}
+
+impl std::error::Error for AccessError {}
+
+/// A group of [spawned](crate::spawn) tasks that can be awaited together
+///
+/// Unlike [FuturesUnordered], each member is a real task driven by the runtime, so it keeps making
+/// progress even while nothing is waiting on the set. [join_next](JoinSet::join_next) yields the
+/// tasks' outputs in the order they complete.
+///
+/// Dropping a [JoinSet] [aborts](JoinSet::abort_all) all of its tasks.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::task::JoinSet;
+/// # use screeps_async::time::delay;
+/// screeps_async::spawn(async move {
+///     let mut set = JoinSet::new();
+///     for ticks in [10, 5, 20] {
+///         set.spawn(async move {
+///             delay(ticks).await;
+///             ticks
+///         });
+///     }
+///
+///     while let Some(res) = set.join_next().await {
+///         // collect what each source produced
+///     }
+/// }).detach();
+/// ```
+pub struct JoinSet<T> {
+    tasks: FuturesUnordered<JoinHandle<T>>,
+}
+
+impl<T: 'static> JoinSet<T> {
+    /// Construct an empty [JoinSet]
+    pub fn new() -> Self {
+        Self {
+            tasks: FuturesUnordered::new(),
+        }
+    }
+
+    /// [Spawn](crate::spawn) `future` on the current runtime as a member of this set
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the current runtime is not set
+    pub fn spawn<F>(&mut self, future: F)
+    where
+        F: Future<Output = T> + 'static,
+    {
+        self.tasks.push(crate::spawn(future));
+    }
+
+    /// The number of tasks whose output hasn't been retrieved yet
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Whether there are no tasks left in the set
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Wait for the next task in the set to complete, returning its output.
+    ///
+    /// Tasks that were aborted resolve to [JoinError::Cancelled], and tasks that panicked to
+    /// [JoinError::Panicked].
+    /// Returns [None] if the set is empty
+    pub async fn join_next(&mut self) -> Option<Result<T, JoinError>> {
+        self.tasks.next().await
+    }
+
+    /// [Abort](JoinHandle::abort) all tasks in the set.
+    ///
+    /// The tasks stay in the set, so [join_next](Self::join_next) still yields each of them once,
+    /// as [JoinError::Cancelled] unless it had already completed
+    pub fn abort_all(&self) {
+        self.tasks.iter().for_each(JoinHandle::abort);
+    }
+}
+
+impl<T: 'static> Default for JoinSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for JoinSet<T> {
+    fn drop(&mut self) {
+        self.tasks.iter().for_each(JoinHandle::abort);
+    }
+}
+
+type ScopedFuture<'env> = Pin<Box<dyn Future<Output = ()> + 'env>>;
+
+/// Run `f` with a [Scope] for spawning futures that borrow from outside of it.
+///
+/// Unlike [spawn](crate::spawn), the spawned futures don't need to be `'static`. They are polled
+/// by the future returned from [scope] rather than as tasks of their own, and it only resolves,
+/// to the output of the future returned by `f`, once every spawned future has completed too.
+/// Dropping it before then drops all of the spawned futures, so they can never outlive what they
+/// borrow.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::task::scope;
+/// # use screeps_async::time::delay;
+/// # use std::cell::Cell;
+/// screeps_async::spawn(async move {
+///     let spawns = ["Spawn1", "Spawn2"];
+///     let energy = Cell::new(0);
+///
+///     let (spawns, energy) = (&spawns, &energy);
+///     scope(|s| async move {
+///         for (ticks, _name) in spawns.iter().enumerate() {
+///             s.spawn(async move {
+///                 delay(ticks as u32).await;
+///                 energy.set(energy.get() + 300);
+///             });
+///         }
+///     })
+///     .await;
+///
+///     // Every spawned future has finished with `energy` by now
+///     assert_eq!(600, energy.get());
+/// }).detach();
+/// ```
+pub fn scope<'env, F, Fut>(f: F) -> ScopeFuture<'env, Fut>
+where
+    F: FnOnce(Scope<'env>) -> Fut,
+    Fut: Future + 'env,
+{
+    let scope = Scope {
+        shared: Rc::new(ScopeShared {
+            spawned: RefCell::new(Vec::new()),
+            waker: RefCell::new(None),
+        }),
+    };
+    let shared = scope.shared.clone();
+
+    ScopeFuture {
+        body: Some(f(scope)),
+        output: None,
+        children: FuturesUnordered::new(),
+        shared,
+    }
+}
+
+/// Spawns futures that are driven by a [scope]. Cheap to clone
+pub struct Scope<'env> {
+    shared: Rc<ScopeShared<'env>>,
+}
+
+struct ScopeShared<'env> {
+    /// Futures spawned since the [ScopeFuture] was last polled
+    spawned: RefCell<Vec<ScopedFuture<'env>>>,
+    /// Waker of the task polling the [ScopeFuture]
+    waker: RefCell<Option<Waker>>,
+}
+
+impl<'env> Scope<'env> {
+    /// Spawn `future` onto this scope. It is first polled the next time the scope is.
+    ///
+    /// Futures spawned after the [scope] has resolved are never polled
+    pub fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + 'env,
+    {
+        self.shared.spawned.borrow_mut().push(Box::pin(future));
+        if let Some(waker) = self.shared.waker.borrow().as_ref() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+impl Clone for Scope<'_> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Debug for Scope<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.pad("Scope { .. }")
+    }
+}
+
+/// Future returned by [scope]
+pub struct ScopeFuture<'env, Fut: Future> {
+    /// The future returned by the closure passed to [scope], [None] once it has completed
+    body: Option<Fut>,
+    output: Option<Fut::Output>,
+    children: FuturesUnordered<ScopedFuture<'env>>,
+    shared: Rc<ScopeShared<'env>>,
+}
+
+impl<Fut: Future> Future for ScopeFuture<'_, Fut> {
+    type Output = Fut::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `body` is never moved out of, only dropped in place via Pin::set
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut body = unsafe { Pin::new_unchecked(&mut this.body) };
+
+        this.shared.waker.replace(Some(cx.waker().clone()));
+
+        if let Some(fut) = body.as_mut().as_pin_mut() {
+            if let Poll::Ready(output) = fut.poll(cx) {
+                this.output = Some(output);
+                body.set(None);
+            }
+        }
+
+        // Keep going while futures complete, since they may have spawned more futures
+        loop {
+            let spawned = this.shared.spawned.take();
+            spawned
+                .into_iter()
+                .for_each(|future| this.children.push(future));
+
+            match Pin::new(&mut this.children).poll_next(cx) {
+                Poll::Ready(Some(())) => {}
+                _ if this.shared.spawned.borrow().is_empty() => break,
+                _ => {}
+            }
+        }
+
+        if this.children.is_empty() {
+            if let Some(output) = this.output.take() {
+                // Nothing can be polled once we resolve, so don't wake us for new futures
+                this.shared.waker.take();
+                return Poll::Ready(output);
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{scope, JoinSet};
+    use crate::spawn;
+    use crate::tests::{init_test, tick};
+    use crate::time::{delay, yield_now};
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    crate::task_local! {
+        static CREEP: String;
+    }
+
+    #[test]
+    fn interleaved_tasks_see_own_value() {
+        init_test();
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        for name in ["harvester", "upgrader"] {
+            let seen = seen.clone();
+            spawn(CREEP.scope(name.to_string(), async move {
+                for _ in 0..2 {
+                    CREEP.with(|creep| seen.borrow_mut().push(creep.clone()));
+                    yield_now().await;
+                }
+            }))
+            .detach();
+        }
+
+        crate::run().unwrap();
+
+        let expected = ["harvester", "upgrader", "harvester", "upgrader"];
+        assert_eq!(expected.map(String::from).to_vec(), seen.take());
+        assert!(CREEP.try_with(|_| ()).is_err(), "Value leaked out of scope");
+    }
+
+    #[test]
+    fn nested_scopes() {
+        let outer = CREEP.sync_scope("outer".to_string(), || {
+            let inner = CREEP.sync_scope("inner".to_string(), || CREEP.with(String::clone));
+            assert_eq!("inner", inner);
+            CREEP.with(String::clone)
+        });
+
+        assert_eq!("outer", outer);
+    }
+
+    #[test]
+    fn join_set_yields_in_completion_order() {
+        init_test();
+
+        let handle = spawn(async move {
+            let mut set = JoinSet::new();
+            for ticks in [3, 1, 2] {
+                set.spawn(async move {
+                    delay(ticks).await;
+                    ticks
+                });
+            }
+            assert_eq!(3, set.len());
+
+            let mut results = Vec::new();
+            while let Some(res) = set.join_next().await {
+                results.push(res.unwrap());
+            }
+            assert!(set.is_empty());
+            results
+        });
+
+        for _ in 0..5 {
+            tick().unwrap();
+        }
+        assert_eq!(vec![1, 2, 3], crate::block_on(handle).unwrap().unwrap());
+    }
+
+    #[test]
+    fn abort_all_cancels_members() {
+        init_test();
+
+        let mut set = JoinSet::new();
+        set.spawn(async move { 1 });
+        set.spawn(async move {
+            delay(5).await;
+            2
+        });
+
+        tick().unwrap();
+        set.abort_all();
+        tick().unwrap();
+
+        let results = crate::block_on(async move {
+            let mut results = Vec::new();
+            while let Some(res) = set.join_next().await {
+                results.push(res.ok());
+            }
+            results
+        })
+        .unwrap();
+        assert_eq!(vec![Some(1), None], results);
+    }
+
+    #[test]
+    fn dropping_set_aborts_members() {
+        init_test();
+
+        let polled = Rc::new(RefCell::new(false));
+        let mut set = JoinSet::new();
+        {
+            let polled = polled.clone();
+            set.spawn(async move { *polled.borrow_mut() = true });
+        }
+
+        drop(set);
+        crate::run().unwrap();
+        assert!(
+            !*polled.borrow(),
+            "Task was polled after its set was dropped"
+        );
+    }
+
+    #[test]
+    fn scope_children_borrow_locals() {
+        init_test();
+
+        let handle = spawn(async move {
+            let names = vec!["harvester", "upgrader", "builder"];
+            let seen = RefCell::new(Vec::new());
+
+            let (names, seen) = (&names, &seen);
+            let count = scope(|s| async move {
+                for (ticks, name) in names.iter().enumerate().rev() {
+                    s.spawn(async move {
+                        delay(ticks as u32).await;
+                        seen.borrow_mut().push(*name);
+                    });
+                }
+                names.len()
+            })
+            .await;
+
+            assert_eq!(3, count);
+            seen.take()
+        });
+
+        for _ in 0..4 {
+            tick().unwrap();
+        }
+        assert_eq!(
+            vec!["harvester", "upgrader", "builder"],
+            crate::block_on(handle).unwrap().unwrap()
+        );
+    }
+
+    #[test]
+    fn scope_waits_for_nested_children() {
+        init_test();
+
+        let done = Rc::new(Cell::new(false));
+        let handle = {
+            let done = done.clone();
+            spawn(async move {
+                let finished = Cell::new(0);
+                let finished = &finished;
+                scope(|s| async move {
+                    let inner = s.clone();
+                    s.spawn(async move {
+                        yield_now().await;
+                        inner.spawn(async move {
+                            delay(2).await;
+                            finished.set(finished.get() + 1);
+                        });
+                        finished.set(finished.get() + 1);
+                    });
+                })
+                .await;
+
+                done.set(true);
+                finished.get()
+            })
+        };
+
+        tick().unwrap();
+        tick().unwrap();
+        assert!(!done.get(), "Scope resolved before its children");
+
+        tick().unwrap();
+        assert_eq!(2, crate::block_on(handle).unwrap().unwrap());
+    }
+}
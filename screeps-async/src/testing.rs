@@ -0,0 +1,201 @@
+//! Mocked game state for running the runtime off the Screeps server
+//!
+//! Only available when the default `screeps` feature is disabled or the `testing` feature is
+//! enabled. The runtime then reads the game time and CPU usage from here instead of from the game
+//! bindings, so async logic can be tested deterministically. Like the rest of the runtime, the
+//! mocked state is per thread.
+
+use crate::error::RuntimeError;
+use crate::runtime::{Builder, TickReport};
+use crate::CURRENT;
+use std::cell::RefCell;
+
+thread_local! {
+    pub(crate) static GAME_TIME: RefCell<u32> = const { RefCell::new(0) };
+    pub(crate) static TIME_USED: RefCell<f64> = const { RefCell::new(0.0) };
+    pub(crate) static BUCKET: RefCell<i32> = const { RefCell::new(10000) };
+    pub(crate) static TICK_LIMIT: RefCell<f64> = const { RefCell::new(500.0) };
+}
+
+/// The mocked game time. Defaults to `0`
+pub fn game_time() -> u32 {
+    GAME_TIME.with_borrow(|t| *t)
+}
+
+/// Set the mocked game time
+pub fn set_game_time(time: u32) {
+    GAME_TIME.with_borrow_mut(|t| *t = time);
+}
+
+/// The mocked fraction of the tick limit used so far this tick. Defaults to `0.0`
+pub fn time_used() -> f64 {
+    TIME_USED.with_borrow(|t| *t)
+}
+
+/// Set the mocked fraction of the tick limit used so far this tick
+pub fn set_time_used(used: f64) {
+    TIME_USED.with_borrow_mut(|t| *t = used);
+}
+
+/// The mocked CPU bucket. Defaults to `10000`
+pub fn bucket() -> i32 {
+    BUCKET.with_borrow(|b| *b)
+}
+
+/// Set the mocked CPU bucket
+pub fn set_bucket(bucket: i32) {
+    BUCKET.with_borrow_mut(|b| *b = bucket);
+}
+
+/// The mocked tick limit. Defaults to `500.0`
+pub fn tick_limit() -> f64 {
+    TICK_LIMIT.with_borrow(|l| *l)
+}
+
+/// Set the mocked tick limit
+pub fn set_tick_limit(limit: f64) {
+    TICK_LIMIT.with_borrow_mut(|l| *l = limit);
+}
+
+/// Reset all mocked game state to the defaults
+pub fn reset() {
+    set_game_time(0);
+    set_time_used(0.0);
+    set_bucket(10000);
+    set_tick_limit(500.0);
+}
+
+/// A harness for driving the runtime tick by tick from tests
+///
+/// Creating a [TestRuntime] resets the mocked game state and installs a fresh runtime as the
+/// [current runtime](crate::CURRENT), so [spawn](crate::spawn) and friends work as normal.
+/// Dropping it removes the runtime again.
+///
+/// There can only be one runtime per thread, so only one [TestRuntime] should exist on a thread at
+/// a time. Creating a second one replaces the runtime of the first. Rust runs each test on its own
+/// thread, so tests don't interfere with each other.
+///
+/// # Examples
+/// ```
+/// use screeps_async::testing::TestRuntime;
+///
+/// let runtime = TestRuntime::new();
+/// let handle = screeps_async::spawn(async {
+///     screeps_async::time::delay(2).await;
+/// });
+///
+/// runtime.tick().unwrap();
+/// runtime.tick().unwrap();
+/// runtime.run().unwrap();
+/// assert!(handle.is_finished());
+/// ```
+pub struct TestRuntime {
+    _private: (),
+}
+
+impl TestRuntime {
+    /// Install a runtime with the default config
+    pub fn new() -> Self {
+        Self::from_builder(Builder::new())
+    }
+
+    /// Install a runtime configured by `builder`
+    pub fn from_builder(builder: Builder) -> Self {
+        reset();
+        builder.apply();
+        Self { _private: () }
+    }
+
+    /// The mocked game time
+    pub fn game_time(&self) -> u32 {
+        game_time()
+    }
+
+    /// Set the mocked game time
+    pub fn set_game_time(&self, time: u32) {
+        set_game_time(time);
+    }
+
+    /// Set the mocked fraction of the tick limit used so far this tick
+    pub fn set_time_used(&self, used: f64) {
+        set_time_used(used);
+    }
+
+    /// Set the mocked CPU bucket
+    pub fn set_bucket(&self, bucket: i32) {
+        set_bucket(bucket);
+    }
+
+    /// Move on to the next tick, resetting the time used this tick
+    pub fn advance_tick(&self) {
+        set_game_time(game_time().wrapping_add(1));
+        set_time_used(0.0);
+    }
+
+    /// Run the runtime for the current tick. See [run](crate::run)
+    pub fn run(&self) -> Result<TickReport, RuntimeError> {
+        crate::run()
+    }
+
+    /// [Run](Self::run) the current tick, then [advance](Self::advance_tick) to the next one.
+    ///
+    /// The tick is still advanced if [run](Self::run) returns an error
+    pub fn tick(&self) -> Result<TickReport, RuntimeError> {
+        let res = self.run();
+        self.advance_tick();
+        res
+    }
+}
+
+impl Default for TestRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for TestRuntime {
+    fn drop(&mut self) {
+        let runtime = CURRENT.with_borrow_mut(Option::take);
+        // Drop outside the borrow since cancelled tasks may try to use the runtime
+        drop(runtime);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::delay;
+
+    #[test]
+    fn drives_runtime_across_ticks() {
+        let runtime = TestRuntime::new();
+        runtime.set_game_time(100);
+
+        let handle = crate::spawn(async {
+            delay(2).await;
+            crate::current_tick()
+        });
+
+        runtime.tick().unwrap();
+        runtime.tick().unwrap();
+        assert!(!handle.is_finished());
+
+        runtime.set_time_used(1.0);
+        let report = runtime.run().unwrap();
+        assert!(report.budget_exhausted, "Ran with no time left");
+
+        // The task was woken last tick, but is only polled now that there's time for it
+        runtime.advance_tick();
+        runtime.run().unwrap();
+        assert_eq!(103, crate::run_blocking(handle).unwrap());
+    }
+
+    #[test]
+    fn drop_removes_runtime() {
+        drop(TestRuntime::new());
+        assert!(CURRENT.with_borrow(Option::is_none));
+
+        let _runtime = TestRuntime::new();
+        assert!(CURRENT.with_borrow(Option::is_some));
+    }
+}
@@ -1,72 +1,570 @@
 //! Utilities for tracking time
 
-use crate::utils::game_time;
-use crate::with_runtime;
+use crate::error::Elapsed;
+use crate::runtime::{ClockSource, GameClock, Handle, TimerMap, TimerTarget};
+use crate::{with_runtime, CURRENT};
+use futures_core::Stream;
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::future::Future;
+use std::ops::{Add, Sub};
 use std::pin::Pin;
+use std::rc::Weak;
 use std::task::{Context, Poll};
 
-/// Future returned by [delay_ticks]
+/// Call `f` with the current runtime's [clock](crate::runtime::Builder::clock),
+/// falling back to the [GameClock] if there is no current runtime
+fn with_clock<R>(f: impl FnOnce(&dyn ClockSource) -> R) -> R {
+    CURRENT.with_borrow(|runtime| match runtime {
+        Some(runtime) => f(runtime.clock()),
+        None => f(&GameClock),
+    })
+}
+
+fn game_time() -> u32 {
+    with_clock(|clock| clock.game_time())
+}
+
+/// A game tick, like [std::time::Instant] but for game time (`screeps::game::time()`)
+///
+/// Using [Tick] for absolute times and [Ticks] for durations keeps the two from being mixed up.
+/// Adding a duration to a [Tick] gives a later [Tick], and subtracting two [Tick]s gives the
+/// [Ticks] between them.
+///
+/// Game ticks wrap around at [u32::MAX], so arithmetic and comparisons wrap around too: of two
+/// ticks, the one at most `i32::MAX` ticks after the other counts as the later one. Two ticks
+/// exactly `2^31` apart are each that far after the other, so they aren't ordered at all and
+/// [partial_cmp](PartialOrd::partial_cmp) returns `None`. This is why [Tick] implements
+/// [PartialOrd] but not [Ord], since the order isn't transitive over longer spans. Don't sort
+/// ticks that are further apart than that.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::time::{delay_until, Tick, Ticks};
+/// screeps_async::spawn(async move {
+///     let started = Tick::now();
+///     delay_until(started + 10).await;
+///     assert!(Tick::now() - started >= Ticks(10));
+/// }).detach();
+/// ```
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
+pub struct Tick(pub u32);
+
+impl Tick {
+    /// How far apart two ticks are when neither counts as the later one. See [Tick]
+    const HALF_RANGE: u32 = 1 << 31;
+}
+
+impl PartialOrd for Tick {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match self.0.wrapping_sub(other.0) {
+            0 => Some(Ordering::Equal),
+            Self::HALF_RANGE => None,
+            diff if diff < Self::HALF_RANGE => Some(Ordering::Greater),
+            _ => Some(Ordering::Less),
+        }
+    }
+}
+
+impl Tick {
+    /// The current game tick
+    pub fn now() -> Self {
+        Tick(game_time())
+    }
+}
+
+impl From<u32> for Tick {
+    fn from(tick: u32) -> Self {
+        Tick(tick)
+    }
+}
+
+impl From<Tick> for u32 {
+    fn from(tick: Tick) -> Self {
+        tick.0
+    }
+}
+
+impl Add<u32> for Tick {
+    type Output = Tick;
+
+    fn add(self, ticks: u32) -> Self::Output {
+        Tick(self.0.wrapping_add(ticks))
+    }
+}
+
+impl Add<Ticks> for Tick {
+    type Output = Tick;
+
+    fn add(self, ticks: Ticks) -> Self::Output {
+        self + ticks.0
+    }
+}
+
+impl Sub for Tick {
+    type Output = Ticks;
+
+    /// The number of ticks from `earlier` until `self`, wrapping around past [u32::MAX], or zero
+    /// if `earlier` is actually later (see [Tick])
+    fn sub(self, earlier: Tick) -> Self::Output {
+        if self >= earlier {
+            Ticks(self.0.wrapping_sub(earlier.0))
+        } else {
+            Ticks(0)
+        }
+    }
+}
+
+/// A number of game ticks, like [std::time::Duration]. See [Tick]
+#[derive(Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Copy, Clone)]
+pub struct Ticks(pub u32);
+
+impl From<u32> for Ticks {
+    fn from(ticks: u32) -> Self {
+        Ticks(ticks)
+    }
+}
+
+/// How long to wait, in whole game ticks. Accepted by [delay] and [timeout]
+///
+/// Timers only ever fire between polls of the runtime, so there is no way to wait for a fraction of
+/// a tick. A plain `u32` or [Ticks] converts to [Deadline::Ticks], so `delay(5)`,
+/// `delay(Ticks(5))` and `delay(Deadline::Ticks(5))` are the same. A [Tick] converts to
+/// [Deadline::At].
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Deadline {
+    /// Wait until this many ticks from now
+    Ticks(u32),
+    /// Wait until later this tick, once the runtime has polled all other scheduled tasks.
+    /// Equivalent to `Ticks(0)`.
+    ///
+    /// To let other tasks run without waiting for them all, use [yield_now] instead
+    ThisTick,
+    /// Wait until the given game tick. If it has already passed, this is the same as
+    /// [ThisTick](Deadline::ThisTick)
+    ///
+    /// Game ticks wrap around at [u32::MAX], and timers keep working across the wrap. To tell a
+    /// tick that has passed from one still to come, a tick at most `i32::MAX` ticks before the
+    /// current one counts as passed, so deadlines can be at most `i32::MAX` ticks away.
+    At(Tick),
+}
+
+impl Deadline {
+    /// The game tick this deadline is reached on, counting from now
+    fn when(self) -> u32 {
+        self.when_from(game_time())
+    }
+
+    /// The game tick this deadline is reached on, counting from game tick `now`
+    pub(crate) fn when_from(self, now: u32) -> u32 {
+        match self {
+            Deadline::Ticks(ticks) => now.wrapping_add(ticks),
+            Deadline::ThisTick => now,
+            Deadline::At(tick) => tick.0,
+        }
+    }
+}
+
+impl From<u32> for Deadline {
+    fn from(ticks: u32) -> Self {
+        Deadline::Ticks(ticks)
+    }
+}
+
+impl From<Ticks> for Deadline {
+    fn from(ticks: Ticks) -> Self {
+        Deadline::Ticks(ticks.0)
+    }
+}
+
+impl From<Tick> for Deadline {
+    fn from(tick: Tick) -> Self {
+        Deadline::At(tick)
+    }
+}
+
+/// Future returned by [delay], [delay_ticks] and [delay_until]
+///
+/// Dropping the [Delay] before it completes removes its waker from the runtime.
+/// A [Delay] can be [reset](Delay::reset) to a new deadline instead of creating a new one, which
+/// is handy for watchdog timers awaited by reference in a [select](crate::select) loop.
 pub struct Delay {
     when: u32,
-    timer_index: usize,
+    /// The runtime to register with, or [None] for the current runtime
+    handle: Option<Handle>,
+    /// Set once this [Delay] has registered its waker with a runtime
+    timer: Option<TimerRegistration>,
+}
+
+pub(crate) struct TimerRegistration {
+    pub(crate) id: u64,
+    /// The timers of the runtime this [Delay] was registered with.
+    ///
+    /// Weak so that a parked task doesn't keep the timer map alive after the runtime is dropped
+    pub(crate) timers: Weak<RefCell<TimerMap>>,
 }
 
 impl Delay {
     fn new(when: u32) -> Self {
-        with_runtime(|runtime| {
-            let mut timer_map = runtime.timers.try_lock().unwrap();
-            let wakers = timer_map.entry(when).or_default();
+        Delay {
+            when,
+            handle: None,
+            timer: None,
+        }
+    }
 
-            let timer_index = wakers.len();
-            wakers.push(None); // Store an empty waker to ensure len() is incremented for the next delay
-            Delay { when, timer_index }
-        })
+    /// A [Delay] that registers with `handle`'s runtime instead of the current one
+    pub(crate) fn with_handle(handle: Handle, when: u32) -> Self {
+        Delay {
+            when,
+            handle: Some(handle),
+            timer: None,
+        }
+    }
+
+    /// The game tick this [Delay] completes on
+    pub fn deadline(&self) -> Tick {
+        Tick(self.when)
+    }
+
+    /// Re-arm this [Delay] to complete at `deadline`, counting from now.
+    ///
+    /// This works whether or not the [Delay] has already completed. Its old timer is removed, and
+    /// a new one is registered the next time it is polled.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use screeps_async::sync::mpsc;
+    /// # use screeps_async::time::delay;
+    /// async fn watchdog(mut activity: mpsc::Receiver<()>) {
+    ///     let mut idle = delay(10);
+    ///     loop {
+    ///         screeps_async::select! {
+    ///             _ = activity.recv() => idle.reset(10),
+    ///             _ = &mut idle => {
+    ///                 // Nothing happened for 10 ticks
+    ///                 return;
+    ///             }
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn reset(&mut self, deadline: impl Into<Deadline>) {
+        let now = match &self.handle {
+            Some(handle) => handle.game_time(),
+            None => game_time(),
+        };
+        self.reset_at(Tick(deadline.into().when_from(now)));
+    }
+
+    /// Re-arm this [Delay] to complete on game tick `when`. See [reset](Self::reset)
+    pub fn reset_at(&mut self, when: impl Into<Tick>) {
+        self.deregister();
+        self.when = when.into().0;
+    }
+
+    /// Remove this [Delay]'s timer from the runtime, if it has one
+    fn deregister(&mut self) {
+        let Some(timer) = self.timer.take() else {
+            return; // Never registered, nothing to clean up
+        };
+        let Some(timers) = timer.timers.upgrade() else {
+            return; // Runtime is gone, nothing to clean up
+        };
+        let Ok(mut timers) = timers.try_borrow_mut() else {
+            return;
+        };
+
+        if let Some(entries) = timers.ticks.get_mut(&self.when) {
+            entries.retain(|entry| entry.id != timer.id);
+            if entries.is_empty() {
+                // Don't leave empty ticks behind for long-running bots to accumulate
+                if let Some(entries) = timers.ticks.remove(&self.when) {
+                    timers.recycle(entries);
+                }
+            }
+        }
     }
 }
 
 impl Future for Delay {
     type Output = ();
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        if game_time() >= self.when {
-            return Poll::Ready(());
-        }
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let when = self.when;
+        let Some(timer) = &self.timer else {
+            // First poll, register with the runtime. Even if `when` has already passed,
+            // we wait for the runtime to fire the timer so that the task always yields
+            let registered = match &self.handle {
+                Some(handle) => handle.register_timer(when, cx.waker()),
+                None => with_runtime(|runtime| runtime.handle().register_timer(when, cx.waker())),
+            };
+            let Some((when, timer)) = registered else {
+                return Poll::Ready(()); // Runtime is gone so nothing would ever wake us
+            };
+            self.when = when;
+            self.timer = Some(timer);
 
-        with_runtime(|runtime| {
-            let mut timers = runtime.timers.try_lock().unwrap();
+            return Poll::Pending;
+        };
 
-            // SAFETY: timers map gets populated before future is created and removed on final wake
-            let wakers = timers.get_mut(&self.when).unwrap();
+        let Some(timers) = timer.timers.upgrade() else {
+            return Poll::Ready(()); // Runtime is gone so nothing would ever wake us
+        };
+        let mut timers = timers.borrow_mut();
 
-            if let Some(waker) = wakers.get_mut(self.timer_index).and_then(Option::as_mut) {
-                // Waker already registered, check if it needs updating
-                waker.clone_from(cx.waker());
-            } else {
-                // First time this future was polled, save the waker
-                wakers[self.timer_index] = Some(cx.waker().clone())
+        let entry = timers
+            .ticks
+            .get_mut(&when)
+            .and_then(|entries| entries.iter_mut().find(|entry| entry.id == timer.id));
+
+        match entry {
+            Some(entry) => {
+                // Still waiting, make sure we wake the right task
+                if let TimerTarget::Waker(waker) = &mut entry.target {
+                    waker.clone_from(cx.waker());
+                }
+                Poll::Pending
             }
-        });
+            // Timer has been removed from the map, meaning it has fired
+            None => Poll::Ready(()),
+        }
+    }
+}
 
-        Poll::Pending
+impl Drop for Delay {
+    fn drop(&mut self) {
+        self.deregister();
     }
 }
 
-/// Sleeps for `dur` game ticks.
+/// Sleeps until `deadline`, given as a number of game ticks or a [Deadline].
+///
+/// The returned future always yields to the scheduler and completes during the first call
+/// to [run](crate::run) where [screeps::game::time()] >= `game_time() + ticks`.
+/// Thus, `delay(0)` (or `delay(Deadline::ThisTick)`) resolves later this tick, once the runtime
+/// has polled all other scheduled tasks.
+/// If you wish to yield execution back to the scheduler without waiting for the runtime
+/// to fire timers again, please use [yield_now] instead
 ///
-/// If `dur` is zero, this function completes immediately and does not yield to the scheduler.
-/// If you wish to yield execution back to the scheduler, please use [yield_now] instead
+/// Delays that complete on the same tick are woken in the order they were first polled
+pub fn delay(deadline: impl Into<Deadline>) -> Delay {
+    Delay::new(deadline.into().when())
+}
+
+/// Alias of [delay]
 pub fn delay_ticks(dur: u32) -> Delay {
-    let when = game_time() + dur;
-    Delay::new(when)
+    delay(dur)
 }
 
 /// Sleep until [screeps::game::time()] >= `when`
 ///
-/// The Future returned by this function completes immediately if [screeps::game::time()] is already
-/// >= `when` and does not yield to the scheduler.
-pub fn delay_until(when: u32) -> Delay {
-    Delay::new(when)
+/// Prefer this over [delay] when you already know the absolute tick something should happen on,
+/// rather than computing `when - game_time()` yourself.
+///
+/// If `when` is the current tick or is already in the past, the returned future resolves later
+/// this tick, the same as `delay(0)`. `when` may be a raw game time or a [Tick].
+pub fn delay_until(when: impl Into<Tick>) -> Delay {
+    Delay::new(when.into().0)
+}
+
+/// A [Stream] that fires every `period` ticks. Created by [interval]
+///
+/// Yields the game tick it fired on. Use [tick](Interval::tick) to wait for the next fire without
+/// pulling in stream utilities.
+pub struct Interval {
+    period: u32,
+    /// [None] if the interval should fire as soon as it is polled
+    delay: Option<Delay>,
+}
+
+impl Interval {
+    /// Wait until the next time this [Interval] fires, returning the game tick it fired on
+    pub async fn tick(&mut self) -> u32 {
+        std::future::poll_fn(|cx| self.poll_tick(cx)).await
+    }
+
+    /// Poll for the next time this [Interval] fires
+    ///
+    /// Once fired, the interval re-arms itself to fire again `period` ticks from the current tick.
+    /// If the runtime didn't run when the interval was due (e.g. the bucket was empty), the
+    /// interval fires once as soon as possible rather than trying to catch up on missed ticks.
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<u32> {
+        if let Some(delay) = &mut self.delay {
+            if Pin::new(delay).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+
+        let now = game_time();
+        self.delay = Some(delay_until(now.wrapping_add(self.period)));
+        Poll::Ready(now)
+    }
+
+    /// The number of ticks between each time this [Interval] fires
+    pub fn period(&self) -> u32 {
+        self.period
+    }
+}
+
+impl Stream for Interval {
+    type Item = u32;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_tick(cx).map(Some)
+    }
+}
+
+/// Create an [Interval] that fires every `period` ticks, starting `period` ticks from now.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::time::interval;
+/// screeps_async::spawn(async move {
+///     let mut interval = interval(100);
+///     loop {
+///         let tick = interval.tick().await;
+///         // check for new construction sites every 100 ticks
+///     }
+/// }).detach();
+/// ```
+///
+/// # Panics
+///
+/// This function panics if `period` is zero
+pub fn interval(period: u32) -> Interval {
+    assert!(period > 0, "Interval period must be non-zero");
+    Interval {
+        period,
+        delay: Some(delay(period)),
+    }
+}
+
+/// Create an [Interval] that first fires on tick `start`, then every `period` ticks after that.
+///
+/// Useful for aligning intervals to fixed ticks, e.g. spreading out CPU-heavy work by running it
+/// on multiples of 100. If `start` is the current tick or is already in the past, the interval
+/// first fires as soon as it is polled and continues every `period` ticks from then on.
+/// `start` may be a raw game time or a [Tick].
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::time::interval_at;
+/// screeps_async::spawn(async move {
+///     let next_hundred = (screeps_async::current_tick() / 100 + 1) * 100;
+///     let mut interval = interval_at(next_hundred, 100);
+///     loop {
+///         let tick = interval.tick().await;
+///         // plan roads on every multiple of 100 ticks
+///     }
+/// }).detach();
+/// ```
+///
+/// # Panics
+///
+/// This function panics if `period` is zero
+pub fn interval_at(start: impl Into<Tick>, period: u32) -> Interval {
+    assert!(period > 0, "Interval period must be non-zero");
+    Interval {
+        period,
+        delay: Some(delay_until(start)),
+    }
+}
+
+/// Create an [Interval] that fires once every game tick, starting with the current tick.
+///
+/// Yields the current [game time](screeps::game::time()) each time it fires. Even if the runtime
+/// is [run](crate::run) more than once in the same tick, it only fires once per tick.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::time::each_tick;
+/// screeps_async::spawn(async move {
+///     let mut ticks = each_tick();
+///     loop {
+///         let tick = ticks.tick().await;
+///         // move creeps
+///     }
+/// }).detach();
+/// ```
+pub fn each_tick() -> Interval {
+    Interval {
+        period: 1,
+        delay: None,
+    }
+}
+
+/// Wait for `fut` to complete, giving up at `deadline`, given as a number of game ticks or a
+/// [Deadline].
+///
+/// Resolves to [Ok] with the output of `fut` if it completes before the deadline of
+/// `game_time() + ticks`, otherwise resolves to [Elapsed] on the first call to [run](crate::run)
+/// after the deadline is reached. With [Deadline::ThisTick], `fut` has until the runtime has
+/// polled all other scheduled tasks this tick. Whichever of the two finishes second is dropped, so `fut` is not
+/// polled again after timing out and the timer is removed if `fut` completes first.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::sync::oneshot;
+/// # use screeps_async::time::timeout;
+/// async fn wait_for_path(rx: oneshot::Receiver<u32>) -> Option<u32> {
+///     timeout(10, rx).await.ok()?.ok()
+/// }
+/// ```
+pub async fn timeout<F: Future>(
+    deadline: impl Into<Deadline>,
+    fut: F,
+) -> Result<F::Output, Elapsed> {
+    let when = deadline.into().when();
+    crate::select! {
+        output = fut => Ok(output),
+        _ = delay_until(when) => Err(Elapsed),
+    }
+}
+
+/// Wait for `fut` to complete, falling back to `default` if it doesn't by `deadline`.
+///
+/// Shorthand for `timeout(deadline, fut).await.unwrap_or(default)`. See [timeout] for details.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::time::timeout_or;
+/// # use std::future::Future;
+/// async fn path_length(find_path: impl Future<Output = u32>) -> u32 {
+///     // Assume the worst if pathfinding takes too long
+///     timeout_or(10, u32::MAX, find_path).await
+/// }
+/// ```
+pub async fn timeout_or<F: Future>(
+    deadline: impl Into<Deadline>,
+    default: F::Output,
+    fut: F,
+) -> F::Output {
+    timeout(deadline, fut).await.unwrap_or(default)
+}
+
+/// Wait until the CPU bucket is at or above `threshold`.
+///
+/// The bucket is checked once each tick, starting next tick, so this never resolves in the middle
+/// of the tick it was first polled on, even if the bucket is already high enough. Useful for
+/// putting off non-urgent work until CPU is healthy again.
+///
+/// # Examples
+/// ```no_run
+/// # use screeps_async::time::sleep_until_bucket;
+/// screeps_async::spawn(async move {
+///     sleep_until_bucket(8000).await;
+///     // plan the next remote mining room
+/// }).detach();
+/// ```
+pub async fn sleep_until_bucket(threshold: i32) {
+    let mut ticks = interval(1);
+    loop {
+        ticks.tick().await;
+        if with_clock(|clock| clock.bucket()) >= threshold {
+            return;
+        }
+    }
 }
 
 /// Delay execution until the next tick
@@ -82,6 +580,9 @@ pub async fn yield_tick() {
 /// can consume all remaining CPU time this tick since the scheduler cannot interrupt work in the middle
 /// of synchronous sections of code. To alleviate this problem, [yield_now] should be called periodically
 /// to yield control back to the scheduler and give other tasks a chance to run.
+///
+/// Unlike [delay(0)](delay), the task is rescheduled immediately rather than waiting for the
+/// runtime to run out of other scheduled tasks first.
 pub async fn yield_now() {
     struct YieldNow {
         yielded: bool,
@@ -104,11 +605,38 @@ pub async fn yield_now() {
     YieldNow { yielded: false }.await;
 }
 
+/// Yield back to the runtime only if this tick's time allocation is nearly used up.
+///
+/// Resolves immediately, without rescheduling the task, while more than the
+/// [threshold](crate::runtime::Builder::maybe_yield_threshold) of the
+/// [time allocation](crate::time_remaining) is left. Otherwise this behaves like [yield_now], so
+/// the runtime gets a chance to stop polling and leave the rest of the work for next tick. Cheap
+/// enough to call on every iteration of a hot loop.
+///
+/// # Examples
+/// ```no_run
+/// screeps_async::spawn(async move {
+///     for room in 0..100 {
+///         // plan the room
+///         screeps_async::maybe_yield().await;
+///     }
+/// }).detach();
+/// ```
+///
+/// # Panics
+///
+/// This function panics if not called from within a task running on the current runtime
+pub async fn maybe_yield() {
+    if with_runtime(|runtime| runtime.should_yield()) {
+        yield_now().await;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::spawn;
-    use crate::tests::game_time;
+    use crate::tests::{game_time, TIME_USED};
     use rstest::rstest;
     use std::cell::{OnceCell, RefCell};
     use std::rc::Rc;
@@ -137,8 +665,8 @@ mod tests {
         // task hasn't run yet
         assert!(has_run.get().is_none());
 
-        // Should complete within `dur` ticks (since we have infinite cpu time in this test)
-        while game_time() <= dur {
+        // Should complete within `expected` ticks (since we have infinite cpu time in this test)
+        while game_time() <= expected {
             crate::tests::tick().unwrap()
         }
 
@@ -146,6 +674,457 @@ mod tests {
         assert!(has_run.get().is_some(), "Future failed to complete");
     }
 
+    #[test]
+    fn test_same_tick_delays_fire_in_registration_order() {
+        crate::tests::init_test();
+
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let mut handles = Vec::new();
+        // Task `i` yields `3 - i` times before registering so the delays are registered in the
+        // reverse of the order the tasks were spawned (and are polled each tick)
+        for i in 0..4 {
+            let order = order.clone();
+            handles.push(spawn(async move {
+                for _ in i..3 {
+                    yield_now().await;
+                }
+                delay_until(5).await;
+                order.borrow_mut().push(i);
+            }));
+        }
+
+        crate::tests::tick().unwrap();
+        // Removing a timer from the middle of the tick must not reorder the rest
+        handles[2].abort();
+
+        while game_time() <= 5 {
+            crate::tests::tick().unwrap();
+        }
+
+        assert_eq!(vec![3, 1, 0], order.take());
+    }
+
+    #[rstest]
+    #[case::past(2, 5)]
+    #[case::present(5, 5)]
+    #[case::future(8, 8)]
+    fn test_delay_until(#[case] when: u32, #[case] expected: u32) {
+        crate::tests::init_test();
+        crate::tests::GAME_TIME.with_borrow_mut(|t| *t = 5);
+
+        let has_run = Rc::new(OnceCell::new());
+        {
+            let has_run = has_run.clone();
+
+            spawn(async move {
+                delay_until(when).await;
+                assert_eq!(expected, game_time());
+
+                has_run.set(()).unwrap();
+            })
+            .detach();
+        }
+
+        while game_time() <= expected {
+            crate::tests::tick().unwrap()
+        }
+
+        assert!(has_run.get().is_some(), "Future failed to complete");
+    }
+
+    #[test]
+    fn test_delay_ordering() {
+        crate::tests::init_test();
+
+        let fired = Rc::new(RefCell::new(Vec::new()));
+        for ticks in [3, 1, 2] {
+            let fired = fired.clone();
+            spawn(async move {
+                delay(ticks).await;
+                fired.borrow_mut().push((ticks, game_time()));
+            })
+            .detach();
+        }
+
+        for _ in 0..=3 {
+            crate::tests::tick().unwrap();
+        }
+
+        assert_eq!(vec![(1, 1), (2, 2), (3, 3)], fired.take());
+    }
+
+    #[test]
+    fn test_delays_across_wraparound() {
+        crate::tests::init_test();
+        crate::tests::GAME_TIME.with_borrow_mut(|t| *t = u32::MAX - 1);
+
+        let fired = Rc::new(RefCell::new(Vec::new()));
+        for ticks in [5, 1, 3, 2] {
+            let fired = fired.clone();
+            spawn(async move {
+                delay(ticks).await;
+                fired.borrow_mut().push((ticks, game_time()));
+            })
+            .detach();
+        }
+
+        crate::tests::tick().unwrap();
+        assert_eq!(
+            Some(u32::MAX),
+            with_runtime(|runtime| runtime.next_timer_tick())
+        );
+        for _ in 0..5 {
+            crate::tests::tick().unwrap();
+        }
+
+        assert_eq!(
+            vec![(1, u32::MAX), (2, 0), (3, 1), (5, 3)],
+            fired.take(),
+            "Timers after the wrap fired at the wrong tick"
+        );
+    }
+
+    #[rstest]
+    #[case::before_wrap(u32::MAX - 2, u32::MAX)]
+    #[case::at_wrap(u32::MAX, u32::MAX)]
+    #[case::after_wrap(1, 1)]
+    fn test_delay_until_across_wraparound(#[case] when: u32, #[case] expected: u32) {
+        crate::tests::init_test();
+        crate::tests::GAME_TIME.with_borrow_mut(|t| *t = u32::MAX);
+
+        let handle = spawn(async move {
+            delay_until(when).await;
+            game_time()
+        });
+        for _ in 0..3 {
+            crate::tests::tick().unwrap();
+        }
+
+        assert!(handle.is_finished(), "Delay never fired");
+        assert_eq!(expected, crate::run_blocking(handle).unwrap());
+    }
+
+    #[test]
+    fn test_dropped_delays_are_removed() {
+        crate::tests::init_test();
+
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+        let mut delays: Vec<_> = (0..1000).map(|i| Box::pin(delay(i % 10))).collect();
+        for delay in &mut delays {
+            assert!(delay.as_mut().poll(&mut cx).is_pending());
+        }
+
+        assert_eq!(1000, with_runtime(|runtime| runtime.timer_count()));
+
+        drop(delays);
+
+        let timers_empty = with_runtime(|runtime| runtime.timers.borrow().ticks.is_empty());
+        assert!(timers_empty, "Dropped delays left entries in the timer map");
+    }
+
+    #[test]
+    fn test_delay_reset() {
+        crate::tests::init_test();
+
+        let handle = spawn(async move {
+            let mut idle = delay(3);
+            (&mut idle).await;
+            let first = game_time();
+
+            // Re-arming a completed delay
+            idle.reset(3);
+            (&mut idle).await;
+            (first, game_time())
+        });
+
+        let rearm = spawn(async move {
+            let mut idle = delay(2);
+            for _ in 0..3 {
+                // Poll once so the original deadline is registered, then push it back
+                crate::select! {
+                    _ = &mut idle => unreachable!("Fired at its original deadline"),
+                    _ = delay(1) => idle.reset(2),
+                }
+            }
+            let reset_on = game_time();
+            idle.await;
+            (reset_on, game_time())
+        });
+
+        while !(handle.is_finished() && rearm.is_finished()) {
+            crate::tests::tick().unwrap();
+        }
+
+        assert_eq!((3, 6), crate::block_on(handle).unwrap().unwrap());
+        assert_eq!((3, 5), crate::block_on(rearm).unwrap().unwrap());
+        let timers_empty = with_runtime(|runtime| runtime.timers.borrow().ticks.is_empty());
+        assert!(timers_empty, "Reset delays left stale timers behind");
+    }
+
+    #[test]
+    fn test_timeout_completes_in_time() {
+        crate::tests::init_test();
+
+        let handle = spawn(async move { timeout(5, delay(2)).await });
+
+        while !handle.is_finished() {
+            crate::tests::tick().unwrap();
+        }
+
+        assert_eq!(3, game_time());
+        let result = crate::block_on(handle).unwrap().unwrap();
+        assert_eq!(Ok(()), result);
+
+        let has_timers = with_runtime(|runtime| {
+            let timers = runtime.timers.borrow();
+            timers.ticks.values().any(|entries| !entries.is_empty())
+        });
+        assert!(!has_timers, "Timeout's timer was not removed");
+    }
+
+    #[test]
+    fn test_timeout_elapsed() {
+        crate::tests::init_test();
+
+        let handle = spawn(async move { timeout(2, delay(5)).await });
+
+        while !handle.is_finished() {
+            crate::tests::tick().unwrap();
+        }
+
+        assert_eq!(3, game_time());
+        let result = crate::block_on(handle).unwrap().unwrap();
+        assert_eq!(Err(Elapsed), result);
+    }
+
+    #[rstest]
+    #[case(Deadline::Ticks(2), 2)]
+    #[case(Deadline::ThisTick, 0)]
+    #[case(Deadline::At(Tick(3)), 3)]
+    fn test_delay_deadline(#[case] deadline: Deadline, #[case] expected: u32) {
+        crate::tests::init_test();
+
+        let handle = spawn(async move {
+            delay(deadline).await;
+            game_time()
+        });
+
+        while !handle.is_finished() {
+            crate::tests::tick().unwrap();
+        }
+
+        assert_eq!(expected, crate::block_on(handle).unwrap().unwrap());
+    }
+
+    #[rstest]
+    #[case(2, 5)]
+    #[case(5, 2)]
+    fn test_timeout_or(#[case] work: u32, #[case] ticks: u32) {
+        crate::tests::init_test();
+
+        let handle = spawn(async move {
+            let fut = async move {
+                delay(work).await;
+                "done"
+            };
+            timeout_or(ticks, "fallback", fut).await
+        });
+
+        while !handle.is_finished() {
+            crate::tests::tick().unwrap();
+        }
+
+        let expected = if work < ticks { "done" } else { "fallback" };
+        assert_eq!(expected, crate::block_on(handle).unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_tick_arithmetic() {
+        let start = Tick(100);
+        assert_eq!(Tick(105), start + 5);
+        assert_eq!(Tick(105), start + Ticks(5));
+        assert_eq!(Ticks(5), Tick(105) - start);
+        assert_eq!(
+            Ticks(0),
+            start - Tick(105),
+            "Negative durations saturate to zero"
+        );
+        assert!(start < start + 1);
+        assert_eq!(100, u32::from(start));
+    }
+
+    #[test]
+    fn test_tick_arithmetic_across_wraparound() {
+        let before = Tick(u32::MAX - 5);
+        let after = Tick(5);
+        assert_eq!(after, before + 11);
+        assert_eq!(Ticks(11), after - before);
+        assert_eq!(
+            Ticks(0),
+            before - after,
+            "Negative durations saturate to zero"
+        );
+        assert!(before < after);
+        assert!(after > before);
+        assert!(Tick(5) > Tick(u32::MAX));
+        assert!(Tick(0) < Tick(i32::MAX as u32));
+        // Ticks more than i32::MAX ticks ahead count as being in the past
+        assert!(Tick(0) > Tick(i32::MAX as u32 + 2));
+    }
+
+    #[test]
+    fn test_ticks_half_range_apart_are_unordered() {
+        let (a, b) = (Tick(0), Tick(1 << 31));
+        assert_eq!(None, a.partial_cmp(&b));
+        assert_eq!(None, b.partial_cmp(&a));
+        assert_eq!(Ticks(0), b - a);
+        assert_eq!(Some(Ordering::Greater), Tick(1 << 31).partial_cmp(&Tick(1)));
+    }
+
+    #[test]
+    fn test_delay_until_tick() {
+        crate::tests::init_test();
+        crate::tests::GAME_TIME.with_borrow_mut(|t| *t = 10);
+
+        let handle = spawn(async move {
+            let started = Tick::now();
+            delay_until(started + Ticks(3)).await;
+            Tick::now() - started
+        });
+
+        while !handle.is_finished() {
+            crate::tests::tick().unwrap();
+        }
+        assert_eq!(Ticks(3), crate::block_on(handle).unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_timeout_this_tick() {
+        crate::tests::init_test();
+
+        let in_time = spawn(async move { timeout(Deadline::ThisTick, yield_now()).await });
+        let too_slow = spawn(async move { timeout(Deadline::ThisTick, delay(1)).await });
+
+        crate::run().unwrap();
+        assert!(in_time.is_finished() && too_slow.is_finished());
+
+        assert_eq!(Ok(()), crate::block_on(in_time).unwrap().unwrap());
+        assert_eq!(Err(Elapsed), crate::block_on(too_slow).unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_interval() {
+        crate::tests::init_test();
+
+        let fired = Rc::new(RefCell::new(Vec::new()));
+        {
+            let fired = fired.clone();
+            spawn(async move {
+                let mut interval = interval(2);
+                loop {
+                    let tick = interval.tick().await;
+                    fired.borrow_mut().push(tick);
+                }
+            })
+            .detach();
+        }
+
+        for _ in 0..7 {
+            crate::tests::tick().unwrap();
+        }
+
+        assert_eq!(vec![2, 4, 6], fired.take());
+    }
+
+    #[rstest]
+    #[case::future_start(0, 5, 3, vec![5, 8, 11])]
+    #[case::current_start(4, 4, 5, vec![4, 9, 14])]
+    #[case::past_start(10, 3, 4, vec![10, 14, 18, 22])]
+    fn test_interval_at(
+        #[case] now: u32,
+        #[case] start: u32,
+        #[case] period: u32,
+        #[case] expected: Vec<u32>,
+    ) {
+        crate::tests::init_test();
+        crate::tests::GAME_TIME.with_borrow_mut(|t| *t = now);
+
+        let fired = Rc::new(RefCell::new(Vec::new()));
+        {
+            let fired = fired.clone();
+            spawn(async move {
+                let mut interval = interval_at(start, period);
+                loop {
+                    let tick = interval.tick().await;
+                    fired.borrow_mut().push(tick);
+                }
+            })
+            .detach();
+        }
+
+        for _ in 0..13 {
+            crate::tests::tick().unwrap();
+        }
+
+        assert_eq!(expected, fired.take());
+    }
+
+    #[test]
+    fn test_interval_missed_ticks() {
+        crate::tests::init_test();
+
+        let fired = Rc::new(RefCell::new(Vec::new()));
+        {
+            let fired = fired.clone();
+            spawn(async move {
+                let mut interval = interval(2);
+                loop {
+                    let tick = interval.tick().await;
+                    fired.borrow_mut().push(tick);
+                }
+            })
+            .detach();
+        }
+
+        crate::tests::tick().unwrap();
+        // Skip several ticks without running, as if we were out of bucket
+        crate::tests::GAME_TIME.with_borrow_mut(|t| *t = 9);
+
+        for _ in 0..4 {
+            crate::tests::tick().unwrap();
+        }
+
+        // Fires once for all the missed ticks, then re-arms from the tick it fired on
+        assert_eq!(vec![9, 11], fired.take());
+    }
+
+    #[test]
+    fn test_each_tick() {
+        crate::tests::init_test();
+
+        let fired = Rc::new(RefCell::new(Vec::new()));
+        {
+            let fired = fired.clone();
+            spawn(async move {
+                let mut ticks = each_tick();
+                loop {
+                    let tick = ticks.tick().await;
+                    fired.borrow_mut().push(tick);
+                }
+            })
+            .detach();
+        }
+
+        crate::tests::tick().unwrap();
+        crate::tests::tick().unwrap();
+        // Running again in the same tick must not fire again
+        crate::run().unwrap();
+        crate::run().unwrap();
+        crate::tests::tick().unwrap();
+
+        assert_eq!(vec![0, 1, 2], fired.take());
+    }
+
     #[test]
     fn test_yield_now() {
         crate::tests::init_test();
@@ -179,4 +1158,88 @@ mod tests {
 
         assert_eq!(vec![1, 2, 3], steps);
     }
+
+    #[test]
+    fn test_maybe_yield() {
+        crate::tests::init_test();
+        crate::runtime::Builder::new()
+            .tick_time_allocation(1.0)
+            .apply();
+
+        let order = Rc::new(RefCell::new(Vec::new()));
+        {
+            let order = order.clone();
+            spawn(async move {
+                TIME_USED.with_borrow_mut(|t| *t = 0.5);
+                maybe_yield().await;
+                order.borrow_mut().push("plenty left");
+
+                TIME_USED.with_borrow_mut(|t| *t = 0.95);
+                maybe_yield().await;
+                order.borrow_mut().push("nearly out");
+            })
+            .detach();
+        }
+        {
+            let order = order.clone();
+            spawn(async move { order.borrow_mut().push("other") }).detach();
+        }
+
+        crate::run().unwrap();
+        // Only the second call let the other task run first
+        assert_eq!(vec!["plenty left", "other", "nearly out"], order.take());
+    }
+
+    #[test]
+    fn test_yield_now_interleaves() {
+        crate::tests::init_test();
+
+        let steps = Rc::new(RefCell::new(Vec::new()));
+        for task in ['a', 'b'] {
+            let steps = steps.clone();
+            spawn(async move {
+                for i in 0..3 {
+                    steps.borrow_mut().push((task, i));
+                    yield_now().await;
+                }
+            })
+            .detach();
+        }
+
+        crate::run().unwrap();
+
+        let expected = vec![('a', 0), ('b', 0), ('a', 1), ('b', 1), ('a', 2), ('b', 2)];
+        assert_eq!(expected, steps.take());
+    }
+
+    /// Spawn a task that waits for the bucket to reach 3000, then step through `buckets`, one per
+    /// tick, returning the tick the task resolved on
+    fn run_bucket_script(buckets: &[i32]) -> Option<u32> {
+        crate::tests::init_test();
+
+        let handle = spawn(async move {
+            sleep_until_bucket(3000).await;
+            game_time()
+        });
+        for bucket in buckets {
+            crate::tests::BUCKET.with_borrow_mut(|b| *b = *bucket);
+            crate::tests::tick().unwrap();
+        }
+
+        handle
+            .is_finished()
+            .then(|| crate::block_on(handle).unwrap().unwrap())
+    }
+
+    #[test]
+    fn sleep_until_bucket_waits_for_threshold() {
+        assert_eq!(Some(3), run_bucket_script(&[500, 1000, 2999, 3000, 3500]));
+        assert_eq!(None, run_bucket_script(&[500, 2000, 2500]));
+    }
+
+    #[test]
+    fn sleep_until_bucket_waits_for_next_tick() {
+        assert_eq!(None, run_bucket_script(&[10000]));
+        assert_eq!(Some(1), run_bucket_script(&[10000, 10000]));
+    }
 }